@@ -6,6 +6,8 @@ use crossbeam::{Receiver, bounded, Sender};
 use std::fmt::{Debug, Formatter};
 use std::fs::File;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use crate::util::compression::Decompress;
 
 struct ChannelReader {
     receiver: Receiver<Box<[u8]>>,
@@ -107,6 +109,39 @@ impl BinaryReader for FileReader {
     }
 }
 
+/**
+    Reads its input through a decoder. The state of the decoder can't be copied, so
+    clones share it, and with it the read position, just like clones of a file
+    reader share the file offset.
+*/
+struct DecompressingReader {
+    reader: Arc<Mutex<Box<dyn Read + Send>>>,
+}
+
+impl DecompressingReader {
+    fn new(input: Box<dyn Read + Send>, decompress: Decompress) -> CrushResult<DecompressingReader> {
+        Ok(DecompressingReader { reader: Arc::new(Mutex::new(decompress.reader(input)?)) })
+    }
+}
+
+impl Debug for DecompressingReader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str("<decompressing reader>")
+    }
+}
+
+impl Read for DecompressingReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.reader.lock().unwrap().read(buf)
+    }
+}
+
+impl BinaryReader for DecompressingReader {
+    fn clone(&self) -> Box<dyn BinaryReader + Send + Sync> {
+        Box::from(DecompressingReader { reader: self.reader.clone() })
+    }
+}
+
 impl dyn BinaryReader {
     pub fn paths(mut files: Vec<Box<Path>>) -> CrushResult<Box<dyn BinaryReader + Send + Sync>> {
         if files.len() == 1 {
@@ -122,6 +157,29 @@ impl dyn BinaryReader {
         }
     }
 
+    pub fn paths_decompressed(mut files: Vec<Box<Path>>, decompress: Decompress) -> CrushResult<Box<dyn BinaryReader + Send + Sync>> {
+        if decompress == Decompress::None {
+            return BinaryReader::paths(files);
+        }
+        let mut readers: Vec<Box<dyn BinaryReader + Send + Sync>> = Vec::new();
+        for p in files.drain(..) {
+            let file = to_crush_error(File::open(p))?;
+            readers.push(Box::from(DecompressingReader::new(Box::from(file), decompress)?));
+        }
+        if readers.len() == 1 {
+            Ok(readers.remove(0))
+        } else {
+            Ok(Box::from(MultiReader { inner: VecDeque::from(readers) }))
+        }
+    }
+
+    pub fn decompressed(input: Box<dyn BinaryReader + Send + Sync>, decompress: Decompress) -> CrushResult<Box<dyn BinaryReader + Send + Sync>> {
+        if decompress == Decompress::None {
+            return Ok(input);
+        }
+        Ok(Box::from(DecompressingReader::new(Box::new(input), decompress)?))
+    }
+
     pub fn vec(vec: &Vec<u8>) -> Box<dyn BinaryReader + Send + Sync> {
         return Box::from(VecReader { vec: vec.clone(), offset: 0 });
     }
@@ -196,3 +254,22 @@ impl Debug for VecReader {
         f.write_str("<vec reader>")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::compression::Compression;
+
+    #[test]
+    fn clones_of_decompressing_readers_share_the_position() {
+        let mut compressed = Vec::new();
+        Compression::Gzip.compress(&mut &b"abcdef"[..], &mut compressed, 6).unwrap();
+        let mut reader = BinaryReader::decompressed(BinaryReader::vec(&compressed), Decompress::Auto).unwrap();
+        let mut start = [0u8; 2];
+        reader.read_exact(&mut start).unwrap();
+        let mut rest = Vec::new();
+        reader.as_ref().clone().read_to_end(&mut rest).unwrap();
+        assert_eq!(&start, b"ab");
+        assert_eq!(rest, b"cdef");
+    }
+}
@@ -114,7 +114,7 @@ impl CommandInvocation {
 
     pub fn can_block(&self, arg: &Vec<ArgumentDefinition>, env: &Scope) -> bool {
         let cmd = self.command.compile_non_blocking(env);
-        match cmd {
+        match cmd.map(|(this, value)| (this, callable(value))) {
             Ok((_, Value::Command(command))) =>
                 command.can_block(arg, env) || arg_can_block(&self.arguments, env),
 
@@ -165,6 +165,20 @@ impl CommandInvocation {
     }
 }
 
+/**
+    A namespace with a __call__ member can be called like a command, which is how a command
+    can have members of its own, like cd:history. Anything else is returned as is.
+*/
+fn callable(value: Value) -> Value {
+    match value {
+        Value::Scope(scope) => match scope.get("__call__") {
+            Some(Value::Command(call)) => Value::Command(call),
+            _ => Value::Scope(scope),
+        },
+        value => value,
+    }
+}
+
 fn invoke_value(
     this: Option<Value>,
     value: Value,
@@ -173,6 +187,7 @@ fn invoke_value(
     input: ValueReceiver,
     output: ValueSender) -> CrushResult<JobJoinHandle> {
     let local_env = env.clone();
+    let value = callable(value);
     match value {
         Value::Command(command) =>
             invoke_command(command, this, local_arguments, local_env, input, output),
@@ -282,3 +297,25 @@ impl ToString for CommandInvocation {
         self.command.to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::value::ValueType;
+
+    fn noop(_context: ExecutionContext) -> CrushResult<()> {
+        Ok(())
+    }
+
+    #[test]
+    fn namespaces_with_call_members_are_callable() {
+        let callable_namespace = Scope::new();
+        callable_namespace.declare("__call__", Value::Command(CrushCommand::command_undocumented(noop, false))).unwrap();
+        assert!(callable(Value::Scope(callable_namespace)).value_type() == ValueType::Command);
+
+        let namespace = Scope::new();
+        namespace.declare("other", Value::Command(CrushCommand::command_undocumented(noop, false))).unwrap();
+        assert!(callable(Value::Scope(namespace)).value_type() == ValueType::Scope);
+        assert!(callable(Value::Integer(1)).value_type() == ValueType::Integer);
+    }
+}
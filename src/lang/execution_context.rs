@@ -17,6 +17,7 @@ use crate::lang::table::Table;
 pub trait ArgumentVector {
     fn check_len(&self, len: usize) -> CrushResult<()>;
     fn string(&mut self, idx: usize) -> CrushResult<Box<str>>;
+    fn string_or_file(&mut self, idx: usize) -> CrushResult<Box<str>>;
     fn integer(&mut self, idx: usize) -> CrushResult<i128>;
     fn float(&mut self, idx: usize) -> CrushResult<f64>;
     fn field(&mut self, idx: usize) -> CrushResult<Vec<Box<str>>>;
@@ -92,6 +93,15 @@ impl ArgumentVector for Vec<Argument> {
         }
     }
 
+    fn string_or_file(&mut self, idx: usize) -> CrushResult<Box<str>> {
+        match self.value(idx)? {
+            Value::String(s) => Ok(s),
+            Value::File(f) => Ok(Box::from(f.to_string_lossy().as_ref())),
+            v => argument_error(
+                format!("Invalid value, expected a string or a file, found a {}", v.value_type().to_string()).as_str()),
+        }
+    }
+
     fn value(&mut self, idx: usize) -> CrushResult<Value> {
         if idx < self.len() {
             Ok(self.replace(idx, Argument::unnamed(Value::Bool(false))).value)
@@ -17,3 +17,4 @@ pub mod errors;
 pub mod ast;
 pub mod help;
 pub mod execution_context;
+pub mod serialization;
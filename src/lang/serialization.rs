@@ -0,0 +1,790 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::ffi::OsStr;
+use std::path::Path;
+
+use chrono::{Duration, Local, TimeZone};
+use regex::Regex;
+
+use crate::lang::errors::{data_error, error, to_crush_error, CrushResult};
+use crate::lang::{dict::Dict, list::List, r#struct::Struct, r#struct::ROOT, scope::Scope};
+use crate::lang::{table::ColumnType, table::Row, table::Table, value::Value, value::ValueType};
+use crate::util::glob::Glob;
+
+const MAGIC: &[u8; 4] = b"CRSH";
+const VERSION: u8 = 1;
+
+const STRING: u8 = 1;
+const INTEGER: u8 = 2;
+const TIME: u8 = 3;
+const DURATION: u8 = 4;
+const FIELD: u8 = 5;
+const GLOB: u8 = 6;
+const REGEX: u8 = 7;
+const FILE: u8 = 8;
+const TABLE: u8 = 9;
+const STRUCT: u8 = 10;
+const LIST: u8 = 11;
+const DICT: u8 = 12;
+const BOOL: u8 = 13;
+const FLOAT: u8 = 14;
+const EMPTY: u8 = 15;
+const BINARY: u8 = 16;
+const TYPE: u8 = 17;
+
+/* Tags of types that only occur inside of type values and column types. */
+const TABLE_STREAM: u8 = 18;
+const COMMAND: u8 = 19;
+const SCOPE: u8 = 20;
+const ANY: u8 = 21;
+const BINARY_STREAM: u8 = 22;
+
+/* Whether a struct is stored in full or refers to an earlier one. */
+const STRUCT_DEFINITION: u8 = 0;
+const STRUCT_REFERENCE: u8 = 1;
+
+/* What a struct inherits from. */
+const NO_PARENT: u8 = 0;
+const ROOT_PARENT: u8 = 1;
+const STRUCT_PARENT: u8 = 2;
+const CLASS_PARENT: u8 = 3;
+
+/* How deeply values and types may be nested, so that corrupt data can't overflow the stack. */
+const MAX_DEPTH: usize = 256;
+
+/**
+    A class is a struct with methods. Methods can't be serialized, so structs inheriting
+    from a class refer to it by the name of the variable holding it.
+*/
+fn is_class(s: &Struct) -> bool {
+    s.local_elements().iter().any(|(_, value)| value.value_type() == ValueType::Command)
+}
+
+struct Encoder<'a> {
+    out: &'a mut dyn Write,
+    env: &'a Scope,
+    depth: usize,
+    /* The id of every struct written so far, keyed by Struct::id. The struct is kept alive so its id stays unique. */
+    structs: HashMap<usize, (usize, Struct)>,
+    classes: HashMap<usize, String>,
+}
+
+impl Encoder<'_> {
+    fn enter(&mut self) -> CrushResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return error("Value is nested too deeply to be serialized");
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, b: &[u8]) -> CrushResult<()> {
+        to_crush_error(self.out.write_all(b))
+    }
+
+    fn byte(&mut self, b: u8) -> CrushResult<()> {
+        self.bytes(&[b])
+    }
+
+    fn length(&mut self, mut n: usize) -> CrushResult<()> {
+        loop {
+            let b = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                return self.byte(b);
+            }
+            self.byte(b | 0x80)?;
+        }
+    }
+
+    fn blob(&mut self, b: &[u8]) -> CrushResult<()> {
+        self.length(b.len())?;
+        self.bytes(b)
+    }
+
+    fn string(&mut self, s: &str) -> CrushResult<()> {
+        self.blob(s.as_bytes())
+    }
+
+    fn columns(&mut self, columns: &[ColumnType]) -> CrushResult<()> {
+        self.length(columns.len())?;
+        for column in columns {
+            self.string(&column.name)?;
+            self.value_type(&column.cell_type)?;
+        }
+        Ok(())
+    }
+
+    fn value_type(&mut self, t: &ValueType) -> CrushResult<()> {
+        self.enter()?;
+        match t {
+            ValueType::String => self.byte(STRING),
+            ValueType::Integer => self.byte(INTEGER),
+            ValueType::Time => self.byte(TIME),
+            ValueType::Duration => self.byte(DURATION),
+            ValueType::Field => self.byte(FIELD),
+            ValueType::Glob => self.byte(GLOB),
+            ValueType::Regex => self.byte(REGEX),
+            ValueType::File => self.byte(FILE),
+            ValueType::Struct => self.byte(STRUCT),
+            ValueType::Bool => self.byte(BOOL),
+            ValueType::Float => self.byte(FLOAT),
+            ValueType::Empty => self.byte(EMPTY),
+            ValueType::Binary => self.byte(BINARY),
+            ValueType::Type => self.byte(TYPE),
+            ValueType::Command => self.byte(COMMAND),
+            ValueType::Scope => self.byte(SCOPE),
+            ValueType::Any => self.byte(ANY),
+            ValueType::BinaryStream => self.byte(BINARY_STREAM),
+            ValueType::Table(columns) => {
+                self.byte(TABLE)?;
+                self.columns(columns)
+            }
+            ValueType::TableStream(columns) => {
+                self.byte(TABLE_STREAM)?;
+                self.columns(columns)
+            }
+            ValueType::List(element) => {
+                self.byte(LIST)?;
+                self.value_type(element)
+            }
+            ValueType::Dict(key, value) => {
+                self.byte(DICT)?;
+                self.value_type(key)?;
+                self.value_type(value)
+            }
+        }?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn class_name(&mut self, class: &Struct) -> CrushResult<String> {
+        if let Some(name) = self.classes.get(&class.id()) {
+            return Ok(name.clone());
+        }
+        let mut variables = HashMap::new();
+        self.env.dump(&mut variables);
+        let mut names = variables.drain()
+            .filter(|(_, t)| *t == ValueType::Struct)
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            if let Some(Value::Struct(s)) = self.env.get(&name) {
+                if s.id() == class.id() {
+                    self.classes.insert(class.id(), name.clone());
+                    return Ok(name);
+                }
+            }
+        }
+        error("Structs inheriting from a class can only be serialized if the class is stored in a variable")
+    }
+
+    fn r#struct(&mut self, s: &Struct) -> CrushResult<()> {
+        self.enter()?;
+        if let Some((id, _)) = self.structs.get(&s.id()) {
+            let id = *id;
+            self.byte(STRUCT_REFERENCE)?;
+            self.length(id)?;
+            self.depth -= 1;
+            return Ok(());
+        }
+        self.byte(STRUCT_DEFINITION)?;
+        match s.parent() {
+            None => self.byte(NO_PARENT)?,
+            Some(p) if p.is_root() => self.byte(ROOT_PARENT)?,
+            Some(p) if is_class(&p) => {
+                let name = self.class_name(&p)?;
+                self.byte(CLASS_PARENT)?;
+                self.string(&name)?;
+            }
+            Some(p) => {
+                self.byte(STRUCT_PARENT)?;
+                self.r#struct(&p)?;
+            }
+        }
+        /* Register the struct before writing its fields, so that fields can refer back to it. */
+        let id = self.structs.len();
+        self.structs.insert(s.id(), (id, s.clone()));
+        let elements = s.local_elements();
+        self.length(elements.len())?;
+        for (name, value) in elements {
+            self.string(&name)?;
+            self.value(value)?;
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn value(&mut self, value: Value) -> CrushResult<()> {
+        self.enter()?;
+        match value {
+            Value::String(s) => {
+                self.byte(STRING)?;
+                self.string(&s)
+            }
+            Value::Integer(i) => {
+                self.byte(INTEGER)?;
+                self.bytes(&i.to_le_bytes())
+            }
+            Value::Time(t) => {
+                self.byte(TIME)?;
+                self.bytes(&t.timestamp().to_le_bytes())?;
+                self.bytes(&t.timestamp_subsec_nanos().to_le_bytes())
+            }
+            Value::Duration(d) => {
+                self.byte(DURATION)?;
+                let seconds = d.num_seconds();
+                let nanoseconds = (d - Duration::seconds(seconds)).num_nanoseconds().unwrap_or(0) as i32;
+                self.bytes(&seconds.to_le_bytes())?;
+                self.bytes(&nanoseconds.to_le_bytes())
+            }
+            Value::Field(f) => {
+                self.byte(FIELD)?;
+                self.length(f.len())?;
+                for part in f {
+                    self.string(&part)?;
+                }
+                Ok(())
+            }
+            Value::Glob(g) => {
+                self.byte(GLOB)?;
+                self.string(&g.to_string())
+            }
+            Value::Regex(s, _) => {
+                self.byte(REGEX)?;
+                self.string(&s)
+            }
+            Value::File(f) => {
+                self.byte(FILE)?;
+                self.blob(f.as_os_str().as_bytes())
+            }
+            Value::Table(t) => {
+                self.byte(TABLE)?;
+                self.columns(t.types())?;
+                self.length(t.rows().len())?;
+                for row in t.rows() {
+                    for cell in row.cells() {
+                        self.value(cell.clone())?;
+                    }
+                }
+                Ok(())
+            }
+            Value::Struct(s) => {
+                self.byte(STRUCT)?;
+                self.r#struct(&s)
+            }
+            Value::List(l) => {
+                self.byte(LIST)?;
+                self.value_type(&l.element_type())?;
+                let cells = l.dump();
+                self.length(cells.len())?;
+                for cell in cells {
+                    self.value(cell)?;
+                }
+                Ok(())
+            }
+            Value::Dict(d) => {
+                self.byte(DICT)?;
+                self.value_type(&d.key_type())?;
+                self.value_type(&d.value_type())?;
+                let elements = d.elements();
+                self.length(elements.len())?;
+                for (key, value) in elements {
+                    self.value(key)?;
+                    self.value(value)?;
+                }
+                Ok(())
+            }
+            Value::Bool(b) => {
+                self.byte(BOOL)?;
+                self.byte(b as u8)
+            }
+            Value::Float(f) => {
+                self.byte(FLOAT)?;
+                self.bytes(&f.to_le_bytes())
+            }
+            Value::Empty() => self.byte(EMPTY),
+            Value::Binary(b) => {
+                self.byte(BINARY)?;
+                self.blob(&b)
+            }
+            Value::Type(t) => {
+                self.byte(TYPE)?;
+                self.value_type(&t)
+            }
+            Value::TableStream(_) | Value::BinaryStream(_) => self.value(value.materialize()),
+            Value::Command(_) | Value::Scope(_) =>
+                error(format!("Values of type {} can not be serialized", value.value_type().to_string()).as_str()),
+        }?;
+        self.depth -= 1;
+        Ok(())
+    }
+}
+
+struct Decoder<'a> {
+    input: &'a mut dyn Read,
+    env: &'a Scope,
+    depth: usize,
+    /* Every struct read so far, indexed by id. */
+    structs: Vec<Struct>,
+}
+
+impl Decoder<'_> {
+    fn enter(&mut self) -> CrushResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return data_error("Serialized data is nested too deeply");
+        }
+        Ok(())
+    }
+
+    fn bytes<const N: usize>(&mut self) -> CrushResult<[u8; N]> {
+        let mut res = [0u8; N];
+        match self.input.read_exact(&mut res) {
+            Ok(()) => Ok(res),
+            Err(_) => data_error("Unexpected end of serialized data"),
+        }
+    }
+
+    fn byte(&mut self) -> CrushResult<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn length(&mut self) -> CrushResult<usize> {
+        let mut res = 0usize;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            res |= ((b & 0x7f) as usize) << shift;
+            if b & 0x80 == 0 {
+                return Ok(res);
+            }
+        }
+        data_error("Invalid length in serialized data")
+    }
+
+    fn blob(&mut self) -> CrushResult<Vec<u8>> {
+        let len = self.length()?;
+        let mut res = Vec::new();
+        /* Read via take, so that a corrupt length doesn't allocate huge amounts of memory up front. */
+        to_crush_error(self.input.take(len as u64).read_to_end(&mut res))?;
+        if res.len() != len {
+            return data_error("Unexpected end of serialized data");
+        }
+        Ok(res)
+    }
+
+    fn string(&mut self) -> CrushResult<Box<str>> {
+        match String::from_utf8(self.blob()?) {
+            Ok(s) => Ok(s.into_boxed_str()),
+            Err(_) => data_error("Invalid string in serialized data"),
+        }
+    }
+
+    fn columns(&mut self) -> CrushResult<Vec<ColumnType>> {
+        let len = self.length()?;
+        let mut res = Vec::new();
+        for _ in 0..len {
+            let name = self.string()?;
+            res.push(ColumnType { name, cell_type: self.value_type()? });
+        }
+        Ok(res)
+    }
+
+    fn value_type(&mut self) -> CrushResult<ValueType> {
+        self.enter()?;
+        let res = match self.byte()? {
+            STRING => ValueType::String,
+            INTEGER => ValueType::Integer,
+            TIME => ValueType::Time,
+            DURATION => ValueType::Duration,
+            FIELD => ValueType::Field,
+            GLOB => ValueType::Glob,
+            REGEX => ValueType::Regex,
+            FILE => ValueType::File,
+            STRUCT => ValueType::Struct,
+            BOOL => ValueType::Bool,
+            FLOAT => ValueType::Float,
+            EMPTY => ValueType::Empty,
+            BINARY => ValueType::Binary,
+            TYPE => ValueType::Type,
+            COMMAND => ValueType::Command,
+            SCOPE => ValueType::Scope,
+            ANY => ValueType::Any,
+            BINARY_STREAM => ValueType::BinaryStream,
+            TABLE => ValueType::Table(self.columns()?),
+            TABLE_STREAM => ValueType::TableStream(self.columns()?),
+            LIST => ValueType::List(Box::from(self.value_type()?)),
+            DICT => {
+                let key = self.value_type()?;
+                ValueType::Dict(Box::from(key), Box::from(self.value_type()?))
+            }
+            tag => return data_error(format!("Unknown type tag {} in serialized data", tag).as_str()),
+        };
+        self.depth -= 1;
+        Ok(res)
+    }
+
+    fn r#struct(&mut self) -> CrushResult<Struct> {
+        self.enter()?;
+        match self.byte()? {
+            STRUCT_DEFINITION => {}
+            STRUCT_REFERENCE => {
+                let id = self.length()?;
+                self.depth -= 1;
+                return match self.structs.get(id) {
+                    Some(s) => Ok(s.clone()),
+                    None => data_error("Invalid struct reference in serialized data"),
+                };
+            }
+            tag => return data_error(format!("Unknown struct tag {} in serialized data", tag).as_str()),
+        }
+        let parent = match self.byte()? {
+            NO_PARENT => None,
+            ROOT_PARENT => Some(ROOT.clone()),
+            STRUCT_PARENT => Some(self.r#struct()?),
+            CLASS_PARENT => {
+                let name = self.string()?;
+                match self.env.get(&name) {
+                    Some(Value::Struct(class)) => Some(class),
+                    _ => return data_error(format!("Unknown class {} in serialized data", name).as_str()),
+                }
+            }
+            tag => return data_error(format!("Unknown struct parent tag {} in serialized data", tag).as_str()),
+        };
+        let res = Struct::new(vec![], parent);
+        self.structs.push(res.clone());
+        let len = self.length()?;
+        for _ in 0..len {
+            let name = self.string()?;
+            let value = self.value()?;
+            res.clone().set(&name, value);
+        }
+        self.depth -= 1;
+        Ok(res)
+    }
+
+    fn value(&mut self) -> CrushResult<Value> {
+        self.enter()?;
+        let res = match self.byte()? {
+            STRING => Value::String(self.string()?),
+            INTEGER => Value::Integer(i128::from_le_bytes(self.bytes()?)),
+            TIME => {
+                let seconds = i64::from_le_bytes(self.bytes()?);
+                let nanoseconds = u32::from_le_bytes(self.bytes()?);
+                match Local.timestamp_opt(seconds, nanoseconds).single() {
+                    Some(t) => Value::Time(t),
+                    None => return data_error("Invalid time in serialized data"),
+                }
+            }
+            DURATION => {
+                let seconds = i64::from_le_bytes(self.bytes()?);
+                let nanoseconds = i32::from_le_bytes(self.bytes()?);
+                Value::Duration(Duration::seconds(seconds) + Duration::nanoseconds(nanoseconds as i64))
+            }
+            FIELD => {
+                let len = self.length()?;
+                let mut res = Vec::new();
+                for _ in 0..len {
+                    res.push(self.string()?);
+                }
+                Value::Field(res)
+            }
+            GLOB => Value::Glob(Glob::new(&self.string()?)),
+            REGEX => {
+                let s = self.string()?;
+                let re = to_crush_error(Regex::new(&s))?;
+                Value::Regex(s, re)
+            }
+            FILE => Value::File(Box::from(Path::new(OsStr::from_bytes(&self.blob()?)))),
+            TABLE => {
+                let columns = self.columns()?;
+                let len = self.length()?;
+                let mut rows = Vec::new();
+                for _ in 0..len {
+                    let mut cells = Vec::new();
+                    for column in &columns {
+                        let cell = self.value()?;
+                        check_type(&cell, &column.cell_type, || format!("column {}", column.name))?;
+                        cells.push(cell);
+                    }
+                    rows.push(Row::new(cells));
+                }
+                Value::Table(Table::new(columns, rows))
+            }
+            STRUCT => Value::Struct(self.r#struct()?),
+            LIST => {
+                let element_type = self.value_type()?;
+                let len = self.length()?;
+                let mut cells = Vec::new();
+                for _ in 0..len {
+                    let cell = self.value()?;
+                    check_type(&cell, &element_type, || "list".to_string())?;
+                    cells.push(cell);
+                }
+                Value::List(List::new(element_type, cells))
+            }
+            DICT => {
+                let key_type = self.value_type()?;
+                let value_type = self.value_type()?;
+                let dict = Dict::new(key_type.clone(), value_type.clone());
+                let len = self.length()?;
+                for _ in 0..len {
+                    let key = self.value()?;
+                    check_type(&key, &key_type, || "dict key".to_string())?;
+                    let value = self.value()?;
+                    check_type(&value, &value_type, || "dict value".to_string())?;
+                    dict.insert(key, value)?;
+                }
+                Value::Dict(dict)
+            }
+            BOOL => Value::Bool(self.byte()? != 0),
+            FLOAT => Value::Float(f64::from_le_bytes(self.bytes()?)),
+            EMPTY => Value::Empty(),
+            BINARY => Value::Binary(self.blob()?),
+            TYPE => Value::Type(self.value_type()?),
+            tag => return data_error(format!("Unknown value tag {} in serialized data", tag).as_str()),
+        };
+        self.depth -= 1;
+        Ok(res)
+    }
+}
+
+/**
+    Corrupt data could contain values that don't match the type declared for the table
+    column, list or dict they are in, which the rest of crush assumes can't happen.
+*/
+fn check_type(value: &Value, expected: &ValueType, place: impl FnOnce() -> String) -> CrushResult<()> {
+    if expected.is(value) {
+        Ok(())
+    } else {
+        data_error(format!(
+            "Invalid value of type {} in {} of type {} in serialized data",
+            value.value_type().to_string(), place(), expected.to_string()).as_str())
+    }
+}
+
+/**
+    A lossless binary format for crush values.
+
+    Serialized data starts with a four byte magic number and a version byte,
+    followed by a single value. Every value starts with a tag byte identifying
+    its type. Lengths and counts are encoded as unsigned LEB128 varints,
+    integers as 16 little endian bytes and floats as their little endian
+    IEEE 754 representation. Times are stored as seconds and nanoseconds
+    since the epoch, without a time zone, and are read back in local time.
+
+    Every struct is written once and given an id, in the order structs are
+    written. Later occurrences, e.g. a parent shared by many structs, only
+    store the id. Structs inheriting from a class with methods store the name
+    of the variable holding the class in env instead of the class itself, and
+    that name is looked up in env when deserializing.
+
+    Streams are read to completion and stored as tables and binaries.
+    Commands and scopes can not be serialized.
+*/
+pub fn serialize(value: Value, env: &Scope, out: &mut dyn Write) -> CrushResult<()> {
+    let mut encoder = Encoder { out, env, depth: 0, structs: HashMap::new(), classes: HashMap::new() };
+    encoder.bytes(MAGIC)?;
+    encoder.byte(VERSION)?;
+    encoder.value(value)
+}
+
+pub fn deserialize(input: &mut dyn Read, env: &Scope) -> CrushResult<Value> {
+    let mut decoder = Decoder { input, env, depth: 0, structs: Vec::new() };
+    if &decoder.bytes::<4>()? != MAGIC {
+        return data_error("Not serialized crush data");
+    }
+    match decoder.byte()? {
+        VERSION => decoder.value(),
+        version => data_error(format!("Unsupported serialization format version {}", version).as_str()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+    use crate::lang::command::CrushCommand;
+    use crate::lang::execution_context::ExecutionContext;
+
+    fn round_trip(value: Value) -> Value {
+        let env = Scope::new();
+        let mut data = Vec::new();
+        serialize(value, &env, &mut data).unwrap();
+        deserialize(&mut data.as_slice(), &env).unwrap()
+    }
+
+    fn method(_context: ExecutionContext) -> CrushResult<()> {
+        Ok(())
+    }
+
+    #[test]
+    fn scalars_round_trip() {
+        let time = FixedOffset::east_opt(3600).unwrap()
+            .timestamp_opt(1_580_000_000, 123_456_789).unwrap()
+            .with_timezone(&Local);
+        let values = vec![
+            Value::string("crush"),
+            Value::Integer(-170_141_183_460_469_231_731_687_303_715_884_105_728),
+            Value::Time(time),
+            Value::Duration(Duration::seconds(-90) + Duration::nanoseconds(5)),
+            Value::Field(vec![Box::from("a"), Box::from("b")]),
+            Value::Glob(Glob::new("*.rs")),
+            Value::Regex(Box::from("a+b"), Regex::new("a+b").unwrap()),
+            Value::Bool(true),
+            Value::Float(1.5),
+            Value::Binary(vec![0, 1, 255]),
+        ];
+        for value in values {
+            assert!(round_trip(value.clone()) == value);
+        }
+        assert!(round_trip(Value::Empty()).value_type() == ValueType::Empty);
+        match round_trip(Value::File(Box::from(Path::new("/tmp/x")))) {
+            Value::File(f) => assert_eq!(f.as_ref(), Path::new("/tmp/x")),
+            _ => panic!("Expected a file"),
+        }
+        let t = ValueType::Dict(Box::from(ValueType::String), Box::from(ValueType::List(Box::from(ValueType::Any))));
+        match round_trip(Value::Type(t.clone())) {
+            Value::Type(res) => assert_eq!(res, t),
+            _ => panic!("Expected a type"),
+        }
+    }
+
+    #[test]
+    fn tables_keep_their_column_types() {
+        let types = vec![
+            ColumnType::new("name", ValueType::String),
+            ColumnType::new("tags", ValueType::List(Box::from(ValueType::String))),
+            ColumnType::new("nested", ValueType::Table(vec![ColumnType::new("n", ValueType::Integer)])),
+        ];
+        let table = Table::new(types.clone(), vec![Row::new(vec![
+            Value::string("a"),
+            Value::List(List::new(ValueType::String, vec![Value::string("x")])),
+            Value::Table(Table::new(
+                vec![ColumnType::new("n", ValueType::Integer)],
+                vec![Row::new(vec![Value::Integer(1)])])),
+        ])]);
+        match round_trip(Value::Table(table.clone())) {
+            Value::Table(t) => {
+                assert!(t.types() == &types);
+                assert!(t == table);
+            }
+            _ => panic!("Expected a table"),
+        }
+    }
+
+    #[test]
+    fn structs_keep_their_parent() {
+        let class = Struct::new(vec![(Box::from("kind"), Value::string("point"))], Some(ROOT.clone()));
+        let point = Struct::new(vec![
+            (Box::from("x"), Value::Integer(1)),
+            (Box::from("y"), Value::Integer(2)),
+        ], Some(class));
+        match round_trip(Value::Struct(point)) {
+            Value::Struct(s) => {
+                assert!(s.get("kind") == Some(Value::string("point")));
+                assert!(s.local_elements().iter().map(|(n, _)| n.to_string()).collect::<Vec<_>>() == vec!["x", "y"]);
+                assert!(s.parent().unwrap().parent().unwrap().is_root());
+            }
+            _ => panic!("Expected a struct"),
+        }
+    }
+
+    #[test]
+    fn shared_structs_are_stored_once() {
+        let parent = Struct::new(vec![(Box::from("kind"), Value::string("point"))], None);
+        let a = Struct::new(vec![(Box::from("x"), Value::Integer(1))], Some(parent.clone()));
+        let b = Struct::new(vec![(Box::from("x"), Value::Integer(2))], Some(parent));
+        match round_trip(Value::List(List::new(ValueType::Struct, vec![Value::Struct(a), Value::Struct(b)]))) {
+            Value::List(l) => match (l.dump().remove(0), l.dump().remove(1)) {
+                (Value::Struct(a), Value::Struct(b)) =>
+                    assert_eq!(a.parent().unwrap().id(), b.parent().unwrap().id()),
+                _ => panic!("Expected structs"),
+            },
+            _ => panic!("Expected a list"),
+        }
+
+        let s = Struct::new(vec![], None);
+        s.clone().set("me", Value::Struct(s.clone()));
+        match round_trip(Value::Struct(s)) {
+            Value::Struct(s) => match s.get("me") {
+                Some(Value::Struct(me)) => assert_eq!(me.id(), s.id()),
+                _ => panic!("Expected a struct"),
+            },
+            _ => panic!("Expected a struct"),
+        }
+    }
+
+    #[test]
+    fn classes_are_referred_to_by_name() {
+        let class = Struct::new(
+            vec![(Box::from("len"), Value::Command(CrushCommand::command_undocumented(method, false)))],
+            Some(ROOT.clone()));
+        let point = Struct::new(vec![(Box::from("x"), Value::Integer(1))], Some(class.clone()));
+
+        let env = Scope::new();
+        let mut data = Vec::new();
+        assert!(serialize(Value::Struct(point.clone()), &env, &mut data).is_err());
+
+        env.declare("Point", Value::Struct(class.clone())).unwrap();
+        let mut data = Vec::new();
+        serialize(Value::Struct(point), &env, &mut data).unwrap();
+        match deserialize(&mut data.as_slice(), &env).unwrap() {
+            Value::Struct(s) => assert_eq!(s.parent().unwrap().id(), class.id()),
+            _ => panic!("Expected a struct"),
+        }
+        assert!(deserialize(&mut data.as_slice(), &Scope::new()).is_err());
+    }
+
+    #[test]
+    fn deeply_nested_data_is_rejected() {
+        let mut data = b"CRSH\x01\x11".to_vec();
+        data.extend(vec![LIST; 100_000]);
+        data.push(STRING);
+        assert!(deserialize(&mut data.as_slice(), &Scope::new()).is_err());
+    }
+
+    #[test]
+    fn cells_must_match_their_column_type() {
+        /* A table with an integer column a, containing the string x. */
+        let data = b"CRSH\x01\x09\x01\x01a\x02\x01\x01\x01x";
+        assert!(deserialize(&mut &data[..], &Scope::new()).is_err());
+        let data = b"CRSH\x01\x09\x01\x01a\x01\x01\x01\x01x";
+        assert!(deserialize(&mut &data[..], &Scope::new()).is_ok());
+    }
+
+    #[test]
+    fn elements_must_match_their_collection_type() {
+        /* A list of integers containing the string x. */
+        let data = b"CRSH\x01\x0b\x02\x01\x01\x01x";
+        assert!(deserialize(&mut &data[..], &Scope::new()).is_err());
+        /* A dict from strings to integers, with the string x as a value. */
+        let data = b"CRSH\x01\x0c\x01\x02\x01\x01\x01k\x01\x01x";
+        assert!(deserialize(&mut &data[..], &Scope::new()).is_err());
+        let data = b"CRSH\x01\x0c\x01\x01\x01\x01\x01k\x01\x01x";
+        assert!(deserialize(&mut &data[..], &Scope::new()).is_ok());
+    }
+
+    #[test]
+    fn dicts_round_trip() {
+        let dict = Dict::new(ValueType::String, ValueType::Integer);
+        dict.insert(Value::string("a"), Value::Integer(1)).unwrap();
+        match round_trip(Value::Dict(dict)) {
+            Value::Dict(d) => {
+                assert!(d.dict_type() == ValueType::Dict(Box::from(ValueType::String), Box::from(ValueType::Integer)));
+                assert!(d.get(&Value::string("a")) == Some(Value::Integer(1)));
+            }
+            _ => panic!("Expected a dict"),
+        }
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let env = Scope::new();
+        assert!(deserialize(&mut &b"CRSH\x02\x0f"[..], &env).is_err());
+        assert!(deserialize(&mut &b"JUNK\x01\x0f"[..], &env).is_err());
+        assert!(deserialize(&mut &b"CRSH\x01\x01\x05ab"[..], &env).is_err());
+    }
+}
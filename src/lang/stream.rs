@@ -4,6 +4,7 @@ use crate::lang::{table::Row};
 use crossbeam::{Receiver, bounded, unbounded, Sender};
 use crate::lang::errors::{CrushError, error, CrushResult, to_crush_error, send_error};
 use lazy_static::lazy_static;
+use std::sync::{Arc, Weak};
 
 lazy_static! {
     static ref BLACK_HOLE: ValueSender = {
@@ -47,28 +48,43 @@ impl ValueReceiver {
     }
 }
 
+/*
+    The weak reference points to a token held by all copies of the InputStream, so that
+    we can tell if nobody is going to read our output any more without sending anything.
+*/
 pub enum OutputStream {
-    Sync(Sender<Row>),
-    Async(Sender<Row>),
+    Sync(Sender<Row>, Weak<()>),
+    Async(Sender<Row>, Weak<()>),
 }
 
 impl OutputStream {
     pub fn send(&self, row: Row) -> CrushResult<()> {
         let native_output = match self {
-            OutputStream::Sync(s) => s.send(row),
-            OutputStream::Async(s) => s.send(row),
+            OutputStream::Sync(s, _) => s.send(row),
+            OutputStream::Async(s, _) => s.send(row),
         };
         return match native_output {
             Ok(_) => Ok(()),
             Err(_) => error("Broken pipe"),
         };
     }
+
+    /**
+        True if the reading end of the stream has been dropped, so that sending would fail.
+    */
+    pub fn is_closed(&self) -> bool {
+        match self {
+            OutputStream::Sync(_, reader) | OutputStream::Async(_, reader) => reader.upgrade().is_none(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct InputStream {
     receiver: Receiver<Row>,
     types: Vec<ColumnType>,
+    /* Only held to keep the token alive, see OutputStream. */
+    _reader: Arc<()>,
 }
 
 impl InputStream {
@@ -103,12 +119,12 @@ impl InputStream {
                     return error("Wrong number of columns in input");
                 }
                 for (c, ct) in row.cells().iter().zip(self.types.iter()) {
-                    if c.value_type() != ct.cell_type {
+                    if !ct.cell_type.is(c) {
                         return error(format!(
                             "Wrong cell type in input column {:?}, expected {:?}, got {:?}",
                             ct.name,
-                            c.value_type(),
-                            ct.cell_type).as_str());
+                            ct.cell_type,
+                            c.value_type()).as_str());
                     }
                 }
                 res
@@ -125,12 +141,14 @@ pub fn channels() -> (ValueSender, ValueReceiver) {
 
 pub fn streams(signature: Vec<ColumnType>) -> (OutputStream, InputStream) {
     let (output, input) = bounded(128);
-    (OutputStream::Sync(output), InputStream { receiver: input, types: signature })
+    let reader = Arc::new(());
+    (OutputStream::Sync(output, Arc::downgrade(&reader)), InputStream { receiver: input, types: signature, _reader: reader })
 }
 
 pub fn unlimited_streams(signature: Vec<ColumnType>) -> (OutputStream, InputStream) {
     let (output, input) = unbounded();
-    (OutputStream::Async(output), InputStream { receiver: input, types: signature })
+    let reader = Arc::new(());
+    (OutputStream::Async(output, Arc::downgrade(&reader)), InputStream { receiver: input, types: signature, _reader: reader })
 }
 
 pub fn empty_channel() -> ValueReceiver {
@@ -156,3 +174,38 @@ impl Readable for InputStream {
         self.types()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::value::ValueType;
+
+    fn validate(column: ValueType, cell: Value) -> bool {
+        let (output, input) = streams(vec![ColumnType::new("c", column)]);
+        output.send(Row::new(vec![cell])).unwrap();
+        input.recv().is_ok()
+    }
+
+    #[test]
+    fn cells_must_match_the_column_type() {
+        assert!(validate(ValueType::Integer, Value::Integer(1)));
+        assert!(!validate(ValueType::Integer, Value::string("1")));
+        assert!(!validate(ValueType::Integer, Value::Empty()));
+    }
+
+    #[test]
+    fn columns_of_type_any_accept_all_cells() {
+        assert!(validate(ValueType::Any, Value::Integer(1)));
+        assert!(validate(ValueType::Any, Value::Empty()));
+    }
+
+    #[test]
+    fn output_is_closed_when_all_inputs_are_dropped() {
+        let (output, input) = streams(vec![]);
+        let copy = input.clone();
+        drop(input);
+        assert!(!output.is_closed());
+        drop(copy);
+        assert!(output.is_closed());
+    }
+}
@@ -152,6 +152,37 @@ impl Struct {
         self.data.lock().unwrap().cells.clone()
     }
 
+    /**
+        The fields of this struct itself, not including those of its parents, in
+        the order they were added.
+    */
+    pub fn local_elements(&self) -> Vec<(Box<str>, Value)> {
+        let data = self.data.lock().unwrap();
+        let mut res = data.lookup.iter()
+            .map(|(name, idx)| (*idx, name.clone()))
+            .collect::<Vec<_>>();
+        res.sort_by_key(|(idx, _)| *idx);
+        res.drain(..)
+            .map(|(idx, name)| (name, data.cells[idx].clone()))
+            .collect()
+    }
+
+    pub fn parent(&self) -> Option<Struct> {
+        self.data.lock().unwrap().parent.clone()
+    }
+
+    /**
+        An identifier that is unique among all live structs. Clones of a struct share
+        their identifier, structs with the same fields don't.
+    */
+    pub fn id(&self) -> usize {
+        Arc::as_ptr(&self.data) as usize
+    }
+
+    pub fn is_root(&self) -> bool {
+        Arc::ptr_eq(&self.data, &ROOT.data)
+    }
+
     pub fn get(&self, name: &str) -> Option<Value> {
         let data = self.data.lock().unwrap();
         match data.lookup.get(name) {
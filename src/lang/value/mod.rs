@@ -191,19 +191,28 @@ impl Value {
             Value::Regex(_, re) => re.match_files(&cwd()?, v),
             Value::TableStream(s) => {
                 let t = s.types();
-                if t.len() == 1 && t[0].cell_type == ValueType::File {
-                    loop {
-                        match s.recv() {
-                            Ok(row) => {
-                                if let Value::File(f) = row.into_vec().remove(0) {
-                                    v.push(f);
-                                }
+                if t.is_empty() {
+                    /* An empty input, e.g. from a command that isn't part of a pipeline. */
+                    return Ok(());
+                }
+                let idx = if t.len() == 1 && t[0].cell_type == ValueType::File {
+                    0
+                } else {
+                    match t.iter().position(|c| c.name.as_ref() == "file" && c.cell_type == ValueType::File) {
+                        Some(idx) => idx,
+                        None => return argument_error(
+                            "Table stream must contain one column of type file, or a column of type file named file"),
+                    }
+                };
+                loop {
+                    match s.recv() {
+                        Ok(row) => {
+                            if let Value::File(f) = row.into_vec().remove(idx) {
+                                v.push(f);
                             }
-                            Err(_) => break,
                         }
+                        Err(_) => break,
                     }
-                } else {
-                    return argument_error("Table stream must contain one column of type file");
                 }
             }
             _ => return error("Expected a file name"),
@@ -0,0 +1,659 @@
+use std::collections::HashMap;
+use std::io::{BufReader, Read};
+
+use chrono::{DateTime, Local, TimeZone, Utc};
+use serde_json::Value as Json;
+
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{error, mandate, to_crush_error, CrushResult};
+use crate::lang::{argument::Argument, binary::BinaryReader, dict::Dict, list::List, r#struct::Struct};
+use crate::lang::stream::ValueReceiver;
+use crate::lang::{table::ColumnType, table::Row, value::Value, value::ValueType};
+use super::convert::{binary_input, input_arguments};
+
+const MAGIC: &[u8] = b"Obj\x01";
+
+/**
+    An Avro schema. Named types are stored once, references to them are resolved when
+    decoding, so that recursive types work.
+*/
+#[derive(Debug, Clone)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<(String, Schema)>),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(usize),
+    Date,
+    TimeMillis,
+    TimeMicros,
+    TimestampMillis,
+    TimestampMicros,
+    /** A timestamp without a time zone, i.e. in local time. */
+    LocalTimestampMillis,
+    LocalTimestampMicros,
+    /** A decimal stored as bytes, or as fixed with the specified size. */
+    Decimal { scale: u32, fixed: Option<usize> },
+    Named(String),
+}
+
+#[derive(Default)]
+struct Names {
+    types: HashMap<String, Schema>,
+}
+
+fn full_name(name: &str, namespace: &str) -> String {
+    if name.contains('.') || namespace.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", namespace, name)
+    }
+}
+
+impl Names {
+    fn parse(&mut self, json: &Json, namespace: &str) -> CrushResult<Schema> {
+        match json {
+            Json::String(name) => self.primitive(name, namespace),
+            Json::Array(variants) => Ok(Schema::Union(
+                variants.iter()
+                    .map(|v| self.parse(v, namespace))
+                    .collect::<CrushResult<Vec<_>>>()?)),
+            Json::Object(o) => {
+                let type_name = mandate(o.get("type"), "Invalid schema, missing type")?;
+                let logical = o.get("logicalType").and_then(|t| t.as_str());
+                let type_name = match type_name {
+                    Json::String(s) => s.as_str(),
+                    nested => return self.parse(nested, namespace),
+                };
+                let schema = match (type_name, logical) {
+                    ("int", Some("date")) => return Ok(Schema::Date),
+                    ("int", Some("time-millis")) => return Ok(Schema::TimeMillis),
+                    ("long", Some("time-micros")) => return Ok(Schema::TimeMicros),
+                    ("long", Some("timestamp-millis")) => return Ok(Schema::TimestampMillis),
+                    ("long", Some("timestamp-micros")) => return Ok(Schema::TimestampMicros),
+                    ("long", Some("local-timestamp-millis")) => return Ok(Schema::LocalTimestampMillis),
+                    ("long", Some("local-timestamp-micros")) => return Ok(Schema::LocalTimestampMicros),
+                    ("bytes", Some("decimal")) => return Ok(Schema::Decimal {
+                        scale: o.get("scale").and_then(|s| s.as_u64()).unwrap_or(0) as u32,
+                        fixed: None,
+                    }),
+                    ("array", _) => return Ok(Schema::Array(Box::from(
+                        self.parse(mandate(o.get("items"), "Invalid schema, array without items")?, namespace)?))),
+                    ("map", _) => return Ok(Schema::Map(Box::from(
+                        self.parse(mandate(o.get("values"), "Invalid schema, map without values")?, namespace)?))),
+                    ("record", _) | ("error", _) | ("enum", _) | ("fixed", _) => type_name,
+                    (primitive, _) => return self.primitive(primitive, namespace),
+                };
+                let name = mandate(o.get("name").and_then(|n| n.as_str()), "Invalid schema, missing name")?;
+                let namespace = o.get("namespace").and_then(|n| n.as_str()).unwrap_or(namespace);
+                let name = full_name(name, namespace);
+                let namespace = match name.rfind('.') {
+                    Some(idx) => name[..idx].to_string(),
+                    None => String::new(),
+                };
+                let res = match schema {
+                    "enum" => Schema::Enum(
+                        mandate(o.get("symbols").and_then(|s| s.as_array()), "Invalid schema, enum without symbols")?
+                            .iter()
+                            .map(|s| s.as_str().unwrap_or("").to_string())
+                            .collect()),
+                    "fixed" => {
+                        let size = mandate(o.get("size").and_then(|s| s.as_u64()), "Invalid schema, fixed without size")? as usize;
+                        match logical {
+                            Some("decimal") => Schema::Decimal {
+                                scale: o.get("scale").and_then(|s| s.as_u64()).unwrap_or(0) as u32,
+                                fixed: Some(size),
+                            },
+                            _ => Schema::Fixed(size),
+                        }
+                    }
+                    _ => {
+                        /* Register the record before parsing the fields, it may refer to itself. */
+                        self.types.insert(name.clone(), Schema::Record(Vec::new()));
+                        let mut fields = Vec::new();
+                        for field in mandate(o.get("fields").and_then(|f| f.as_array()), "Invalid schema, record without fields")? {
+                            fields.push((
+                                mandate(field.get("name").and_then(|n| n.as_str()), "Invalid schema, field without name")?.to_string(),
+                                self.parse(mandate(field.get("type"), "Invalid schema, field without type")?, &namespace)?,
+                            ));
+                        }
+                        Schema::Record(fields)
+                    }
+                };
+                self.types.insert(name, res.clone());
+                Ok(res)
+            }
+            _ => error("Invalid schema"),
+        }
+    }
+
+    fn primitive(&self, name: &str, namespace: &str) -> CrushResult<Schema> {
+        Ok(match name {
+            "null" => Schema::Null,
+            "boolean" => Schema::Boolean,
+            "int" => Schema::Int,
+            "long" => Schema::Long,
+            "float" => Schema::Float,
+            "double" => Schema::Double,
+            "bytes" => Schema::Bytes,
+            "string" => Schema::String,
+            name => {
+                let qualified = full_name(name, namespace);
+                if self.types.contains_key(&qualified) {
+                    Schema::Named(qualified)
+                } else if self.types.contains_key(name) {
+                    Schema::Named(name.to_string())
+                } else {
+                    return error(format!("Unknown type {}", name).as_str());
+                }
+            }
+        })
+    }
+
+    fn resolve<'a>(&'a self, schema: &'a Schema) -> &'a Schema {
+        match schema {
+            Schema::Named(name) => self.types.get(name).unwrap_or(&Schema::Null),
+            schema => schema,
+        }
+    }
+
+    fn value_type(&self, schema: &Schema) -> ValueType {
+        match self.resolve(schema) {
+            Schema::Null => ValueType::Empty,
+            Schema::Boolean => ValueType::Bool,
+            Schema::Int | Schema::Long => ValueType::Integer,
+            Schema::Float | Schema::Double | Schema::Decimal { .. } => ValueType::Float,
+            Schema::Bytes | Schema::Fixed(_) => ValueType::Binary,
+            Schema::String | Schema::Enum(_) => ValueType::String,
+            Schema::Record(_) => ValueType::Struct,
+            Schema::Array(items) => ValueType::List(Box::from(self.value_type(items))),
+            Schema::Map(values) => ValueType::Dict(Box::from(ValueType::String), Box::from(self.value_type(values))),
+            /* Optional values are a union with null, so they can be empty. */
+            Schema::Union(variants) => match variants.as_slice() {
+                [variant] => self.value_type(variant),
+                _ => ValueType::Any,
+            },
+            Schema::Date | Schema::TimestampMillis | Schema::TimestampMicros |
+            Schema::LocalTimestampMillis | Schema::LocalTimestampMicros => ValueType::Time,
+            Schema::TimeMillis | Schema::TimeMicros => ValueType::Duration,
+            Schema::Named(_) => ValueType::Any,
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, len: usize) -> CrushResult<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return error("Unexpected end of data block");
+        }
+        self.pos += len;
+        Ok(&self.data[self.pos - len..self.pos])
+    }
+
+    fn long(&mut self) -> CrushResult<i64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok((n >> 1) as i64 ^ -((n & 1) as i64));
+            }
+        }
+        error("Invalid variable length integer")
+    }
+
+    fn length_prefixed(&mut self) -> CrushResult<&'a [u8]> {
+        let len = self.long()?;
+        if len < 0 {
+            return error("Invalid length");
+        }
+        self.bytes(len as usize)
+    }
+
+    /**
+        Arrays and maps are encoded as a series of blocks, each starting with the number of
+        items in it. A negative count is followed by the size of the block in bytes.
+    */
+    fn blocks(&mut self, mut item: impl FnMut(&mut Decoder<'a>) -> CrushResult<()>) -> CrushResult<()> {
+        loop {
+            let mut count = self.long()?;
+            if count == 0 {
+                return Ok(());
+            }
+            if count < 0 {
+                count = mandate(count.checked_neg(), "Invalid block count")?;
+                self.long()?;
+            }
+            for _ in 0..count {
+                item(self)?;
+            }
+        }
+    }
+
+    fn value(&mut self, names: &Names, schema: &Schema) -> CrushResult<Value> {
+        Ok(match names.resolve(schema) {
+            Schema::Null => Value::Empty(),
+            Schema::Boolean => Value::Bool(self.bytes(1)?[0] != 0),
+            Schema::Int | Schema::Long => Value::Integer(self.long()? as i128),
+            Schema::Float => {
+                let mut b = [0u8; 4];
+                b.copy_from_slice(self.bytes(4)?);
+                Value::Float(f32::from_le_bytes(b) as f64)
+            }
+            Schema::Double => {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(self.bytes(8)?);
+                Value::Float(f64::from_le_bytes(b))
+            }
+            Schema::Bytes => Value::Binary(self.length_prefixed()?.to_vec()),
+            Schema::String => Value::string(to_crush_error(std::str::from_utf8(self.length_prefixed()?))?),
+            Schema::Fixed(size) => Value::Binary(self.bytes(*size)?.to_vec()),
+            Schema::Enum(symbols) => {
+                let idx = self.long()?;
+                Value::string(mandate(symbols.get(idx as usize), "Invalid enum value")?)
+            }
+            Schema::Record(fields) => Value::Struct(Struct::new(
+                fields.iter()
+                    .map(|(name, schema)| Ok((Box::from(name.as_str()), self.value(names, schema)?)))
+                    .collect::<CrushResult<Vec<_>>>()?,
+                None)),
+            Schema::Array(items) => {
+                let mut res = Vec::new();
+                self.blocks(|d| {
+                    res.push(d.value(names, items)?);
+                    Ok(())
+                })?;
+                Value::List(List::new(names.value_type(items), res))
+            }
+            Schema::Map(values) => {
+                let res = Dict::new(ValueType::String, names.value_type(values));
+                self.blocks(|d| {
+                    let key = Value::string(to_crush_error(std::str::from_utf8(d.length_prefixed()?))?);
+                    res.insert(key, d.value(names, values)?)
+                })?;
+                Value::Dict(res)
+            }
+            Schema::Union(variants) => {
+                let idx = self.long()?;
+                self.value(names, mandate(variants.get(idx as usize), "Invalid union branch")?)?
+            }
+            Schema::Decimal { scale, fixed } => {
+                let bytes = match fixed {
+                    Some(size) => self.bytes(*size)?,
+                    None => self.length_prefixed()?,
+                };
+                Value::Float(decimal(bytes, *scale))
+            }
+            Schema::Date => Value::Time(date(self.long()?)?),
+            Schema::TimeMillis => Value::Duration(chrono::Duration::milliseconds(self.long()?)),
+            Schema::TimeMicros => Value::Duration(chrono::Duration::microseconds(self.long()?)),
+            Schema::TimestampMillis => Value::Time(timestamp(self.long()?, 1000, false)?),
+            Schema::TimestampMicros => Value::Time(timestamp(self.long()?, 1_000_000, false)?),
+            Schema::LocalTimestampMillis => Value::Time(timestamp(self.long()?, 1000, true)?),
+            Schema::LocalTimestampMicros => Value::Time(timestamp(self.long()?, 1_000_000, true)?),
+            Schema::Named(name) => return error(format!("Unknown type {}", name).as_str()),
+        })
+    }
+}
+
+/**
+    A big endian two's complement integer, scaled down by the specified number of digits.
+*/
+pub fn decimal(bytes: &[u8], scale: u32) -> f64 {
+    let negative = bytes.first().map(|b| b & 0x80 != 0).unwrap_or(false);
+    let mut n: i128 = if negative { -1 } else { 0 };
+    for b in bytes.iter().skip(bytes.len().saturating_sub(16)) {
+        n = (n << 8) | *b as i128;
+    }
+    n as f64 / 10f64.powi(scale as i32)
+}
+
+/**
+    A timestamp in the specified number of units per second since the epoch. Local
+    timestamps are wall clock times, and are interpreted in the local time zone.
+*/
+pub fn timestamp(value: i64, units_per_second: i64, local: bool) -> CrushResult<DateTime<Local>> {
+    let seconds = value.div_euclid(units_per_second);
+    let nanos = (value.rem_euclid(units_per_second) * (1_000_000_000 / units_per_second)) as u32;
+    let res = if local {
+        Utc.timestamp_opt(seconds, nanos).single()
+            .and_then(|t| Local.from_local_datetime(&t.naive_utc()).earliest())
+    } else {
+        Local.timestamp_opt(seconds, nanos).single()
+    };
+    mandate(res, format!("Timestamp {} is out of range", value).as_str())
+}
+
+/**
+    A number of days since the epoch. Dates have no time zone, so this is midnight local time.
+*/
+pub fn date(days: i64) -> CrushResult<DateTime<Local>> {
+    match days.checked_mul(24 * 3600) {
+        Some(seconds) => timestamp(seconds, 1, true),
+        None => error(format!("Date {} is out of range", days).as_str()),
+    }
+}
+
+fn read_long(input: &mut dyn Read) -> CrushResult<Option<i64>> {
+    let mut n = 0u64;
+    let mut byte = [0u8; 1];
+    for shift in (0..64).step_by(7) {
+        if to_crush_error(input.read(&mut byte))? == 0 {
+            return if shift == 0 { Ok(None) } else { error("Unexpected end of file") };
+        }
+        n |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some((n >> 1) as i64 ^ -((n & 1) as i64)));
+        }
+    }
+    error("Invalid variable length integer")
+}
+
+fn read_exact(input: &mut dyn Read, len: i64) -> CrushResult<Vec<u8>> {
+    if len < 0 {
+        return error("Invalid length");
+    }
+    /* Lengths are read from the input, so only allocate as much as actually arrives. */
+    let mut res = Vec::new();
+    to_crush_error(input.take(len as u64).read_to_end(&mut res))?;
+    if res.len() as i64 != len {
+        return error("Unexpected end of file");
+    }
+    Ok(res)
+}
+
+enum Codec {
+    Null,
+    Deflate,
+    Snappy,
+    Zstandard,
+}
+
+/**
+    An Avro object container file, read one block at a time.
+*/
+struct Container<R: Read> {
+    input: R,
+    names: Names,
+    schema: Schema,
+    codec: Codec,
+    sync: Vec<u8>,
+}
+
+impl<R: Read> Container<R> {
+    fn open(mut input: R) -> CrushResult<Container<R>> {
+        if read_exact(&mut input, 4)? != MAGIC {
+            return error("Not an Avro container file");
+        }
+        let mut metadata = HashMap::new();
+        loop {
+            let mut count = mandate(read_long(&mut input)?, "Unexpected end of file")?;
+            if count == 0 {
+                break;
+            }
+            if count < 0 {
+                count = mandate(count.checked_neg(), "Invalid block count")?;
+                read_long(&mut input)?;
+            }
+            for _ in 0..count {
+                let len = mandate(read_long(&mut input)?, "Unexpected end of file")?;
+                let key = to_crush_error(String::from_utf8(read_exact(&mut input, len)?))?;
+                let len = mandate(read_long(&mut input)?, "Unexpected end of file")?;
+                metadata.insert(key, read_exact(&mut input, len)?);
+            }
+        }
+        let sync = read_exact(&mut input, 16)?;
+
+        let schema = mandate(metadata.get("avro.schema"), "Missing schema")?;
+        let mut names = Names::default();
+        let schema = names.parse(&to_crush_error(serde_json::from_slice(schema))?, "")?;
+        let codec = match metadata.get("avro.codec").map(|c| c.as_slice()) {
+            None | Some(b"null") => Codec::Null,
+            Some(b"deflate") => Codec::Deflate,
+            Some(b"snappy") => Codec::Snappy,
+            Some(b"zstandard") => Codec::Zstandard,
+            Some(codec) => return error(format!("Unsupported codec {}", String::from_utf8_lossy(codec)).as_str()),
+        };
+        Ok(Container { input, names, schema, codec, sync })
+    }
+
+    /**
+        Records are returned as rows of a table with one column per field. Other values
+        become a table with a single column named value.
+    */
+    fn columns(&self) -> Vec<ColumnType> {
+        match self.names.resolve(&self.schema) {
+            Schema::Record(fields) => fields.iter()
+                .map(|(name, schema)| ColumnType::new(name, self.names.value_type(schema)))
+                .collect(),
+            schema => vec![ColumnType::new("value", self.names.value_type(schema))],
+        }
+    }
+
+    fn decompress(&self, data: Vec<u8>) -> CrushResult<Vec<u8>> {
+        match self.codec {
+            Codec::Null => Ok(data),
+            Codec::Deflate => {
+                let mut res = Vec::new();
+                to_crush_error(flate2::read::DeflateDecoder::new(data.as_slice()).read_to_end(&mut res))?;
+                Ok(res)
+            }
+            Codec::Snappy => {
+                /* The compressed data is followed by a CRC32 checksum of the uncompressed data. */
+                if data.len() < 4 {
+                    return error("Invalid snappy block");
+                }
+                to_crush_error(snap::raw::Decoder::new().decompress_vec(&data[..data.len() - 4]))
+            }
+            Codec::Zstandard => to_crush_error(zstd::decode_all(data.as_slice())),
+        }
+    }
+
+    fn next_block(&mut self) -> CrushResult<Option<Vec<Row>>> {
+        let count = match read_long(&mut self.input)? {
+            None => return Ok(None),
+            Some(count) => count,
+        };
+        let size = mandate(read_long(&mut self.input)?, "Unexpected end of file")?;
+        let data = read_exact(&mut self.input, size)?;
+        let data = self.decompress(data)?;
+        if read_exact(&mut self.input, 16)? != self.sync {
+            return error("Invalid sync marker, the file is corrupt");
+        }
+        let mut decoder = Decoder { data: &data, pos: 0 };
+        let mut rows = Vec::new();
+        for _ in 0..count {
+            rows.push(match decoder.value(&self.names, &self.schema)? {
+                Value::Struct(s) if matches!(self.names.resolve(&self.schema), Schema::Record(_)) =>
+                    Row::new(s.into_vec()),
+                value => Row::new(vec![value]),
+            });
+        }
+        Ok(Some(rows))
+    }
+}
+
+type Input = Box<dyn Read + Send>;
+
+/**
+    The input is either a binary stream or a set of files. Every file is a separate
+    container with its own schema, which must match the schema of the first one.
+*/
+fn containers(arguments: Vec<Argument>, input: ValueReceiver) -> CrushResult<(Vec<String>, Vec<Container<Input>>)> {
+    let (files, decompress) = input_arguments(arguments)?;
+    if files.is_empty() {
+        let input: Input = Box::from(BufReader::new(binary_input(files, decompress, input)?));
+        return Ok((vec!["input".to_string()], vec![Container::open(input)?]));
+    }
+    let mut names = Vec::new();
+    let mut res = Vec::new();
+    for file in files {
+        let name = file.to_string_lossy().to_string();
+        let file = BinaryReader::paths_decompressed(vec![file], decompress)?;
+        let input: Input = Box::from(BufReader::new(file));
+        match Container::open(input) {
+            Ok(container) => res.push(container),
+            Err(e) => return error(format!("{}: {}", name, e.message).as_str()),
+        }
+        names.push(name);
+    }
+    Ok((names, res))
+}
+
+pub fn perform(context: ExecutionContext) -> CrushResult<()> {
+    let (names, containers) = containers(context.arguments, context.input)?;
+    let types = containers[0].columns();
+    for (name, container) in names.iter().zip(containers.iter()) {
+        if container.columns() != types {
+            return error(format!("{}: The schema differs from that of the first file", name).as_str());
+        }
+    }
+    let output = context.output.initialize(types)?;
+    for mut container in containers {
+        while let Some(rows) = container.next_block()? {
+            for row in rows {
+                if output.send(row).is_err() {
+                    /* Whoever is reading our output has stopped listening. */
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn long(n: i64, out: &mut Vec<u8>) {
+        let mut n = ((n << 1) ^ (n >> 63)) as u64;
+        while n >= 0x80 {
+            out.push((n as u8) | 0x80);
+            n >>= 7;
+        }
+        out.push(n as u8);
+    }
+
+    fn string(s: &str, out: &mut Vec<u8>) {
+        long(s.len() as i64, out);
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    fn container(schema: &str, count: i64, block: &[u8]) -> Vec<u8> {
+        let sync = [7u8; 16];
+        let mut res = MAGIC.to_vec();
+        long(1, &mut res);
+        string("avro.schema", &mut res);
+        string(schema, &mut res);
+        long(0, &mut res);
+        res.extend_from_slice(&sync);
+        long(count, &mut res);
+        long(block.len() as i64, &mut res);
+        res.extend_from_slice(block);
+        res.extend_from_slice(&sync);
+        res
+    }
+
+    #[test]
+    fn records_are_read() {
+        let schema = r#"{"type": "record", "name": "Event", "namespace": "log", "fields": [
+            {"name": "id", "type": "long"},
+            {"name": "host", "type": ["null", "string"]},
+            {"name": "tags", "type": {"type": "array", "items": "string"}},
+            {"name": "at", "type": {"type": "long", "logicalType": "timestamp-millis"}},
+            {"name": "parent", "type": ["null", "Event"]}
+        ]}"#;
+        let mut block = Vec::new();
+        /* id = -3, host = "web", tags = ["a"], at = 1500, parent = {id: 1, host: null, ...} */
+        long(-3, &mut block);
+        long(1, &mut block);
+        string("web", &mut block);
+        long(1, &mut block);
+        string("a", &mut block);
+        long(0, &mut block);
+        long(1500, &mut block);
+        long(1, &mut block);
+        long(1, &mut block);
+        long(0, &mut block);
+        long(0, &mut block);
+        long(0, &mut block);
+        long(0, &mut block);
+
+        let data = container(schema, 1, &block);
+        let mut container = Container::open(data.as_slice()).unwrap();
+        let columns = container.columns();
+        /* Optional fields can be empty. */
+        assert!(columns[1].cell_type == ValueType::Any);
+        assert!(columns[2].cell_type == ValueType::List(Box::from(ValueType::String)));
+        assert!(columns[3].cell_type == ValueType::Time);
+        assert!(columns[4].cell_type == ValueType::Any);
+        let rows = container.next_block().unwrap().unwrap();
+        let cells = rows[0].cells();
+        assert!(cells[0] == Value::Integer(-3));
+        assert!(cells[1] == Value::string("web"));
+        assert!(cells[3] == Value::Time(Local.timestamp_opt(1, 500_000_000).unwrap()));
+        match &cells[4] {
+            Value::Struct(parent) => {
+                assert!(parent.get("id") == Some(Value::Integer(1)));
+                assert!(parent.get("host").map(|h| h.value_type()) == Some(ValueType::Empty));
+            }
+            _ => panic!("Expected a struct"),
+        }
+        assert!(container.next_block().unwrap().is_none());
+    }
+
+    #[test]
+    fn corrupt_files_are_rejected() {
+        assert!(Container::open(b"PAR1".as_ref()).is_err());
+        let mut data = container(r#""long""#, 1, &[0x02]);
+        let len = data.len();
+        data[len - 1] = 0;
+        let mut container = Container::open(data.as_slice()).unwrap();
+        assert!(container.next_block().is_err());
+
+        /* Lengths and counts that don't match the data are errors, not allocations or overflows. */
+        let mut data = MAGIC.to_vec();
+        long(1, &mut data);
+        long(i64::MAX, &mut data);
+        assert!(Container::open(data.as_slice()).is_err());
+        let mut data = MAGIC.to_vec();
+        long(i64::MIN, &mut data);
+        assert!(Container::open(data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn timestamps_out_of_range_are_rejected() {
+        assert!(timestamp(i64::MAX, 1000, false).is_err());
+        assert!(timestamp(i64::MIN, 1_000_000, true).is_err());
+        assert!(date(i64::MAX).is_err());
+    }
+
+    #[test]
+    fn local_timestamps_are_wall_clock_times() {
+        let local = timestamp(1_500, 1000, true).unwrap();
+        assert_eq!(local.naive_local(), Utc.timestamp_opt(1, 500_000_000).unwrap().naive_utc());
+        assert!(timestamp(1_500, 1000, false).unwrap() == Local.timestamp_opt(1, 500_000_000).unwrap());
+        assert_eq!(date(1).unwrap().naive_local().to_string(), "1970-01-02 00:00:00");
+    }
+
+    #[test]
+    fn decimals_are_scaled() {
+        assert_eq!(decimal(&[0x30, 0x39], 2), 123.45);
+        assert_eq!(decimal(&[0xff, 0x85], 1), -12.3);
+    }
+}
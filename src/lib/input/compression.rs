@@ -0,0 +1,65 @@
+use crate::lang::execution_context::{ArgumentVector, ExecutionContext};
+use crate::lang::errors::{argument_error, CrushResult, to_crush_error};
+use crate::lang::{binary::binary_channel, value::Value};
+use crate::util::compression::{Compression, Decompress};
+use super::convert::binary_input;
+
+fn compress(context: ExecutionContext, format: Compression) -> CrushResult<()> {
+    let mut level = None;
+    let mut files = Vec::new();
+    for arg in context.arguments {
+        match (arg.argument_type.as_deref(), &arg.value) {
+            (Some("level"), Value::Integer(l)) => level = Some(*l),
+            (Some("level"), _) => return argument_error("Expected an integer compression level"),
+            (None, _) => arg.value.file_expand(&mut files)?,
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+    let level = format.level(level)?;
+    let mut input = binary_input(files, Decompress::None, context.input)?;
+    let (mut out, reader) = binary_channel()?;
+    /* Send the stream before writing to it, so that compression happens while the output is read. */
+    context.output.send(Value::BinaryStream(reader))?;
+    format.compress(&mut input, &mut out, level)
+}
+
+fn decompress(mut context: ExecutionContext, format: Compression) -> CrushResult<()> {
+    let input = binary_input(context.arguments.files()?, Decompress::None, context.input)?;
+    let mut decoder = format.decoder(input)?;
+    let (mut out, reader) = binary_channel()?;
+    context.output.send(Value::BinaryStream(reader))?;
+    to_crush_error(std::io::copy(&mut decoder, &mut out))?;
+    Ok(())
+}
+
+pub fn gzip(context: ExecutionContext) -> CrushResult<()> {
+    compress(context, Compression::Gzip)
+}
+
+pub fn gunzip(context: ExecutionContext) -> CrushResult<()> {
+    decompress(context, Compression::Gzip)
+}
+
+pub fn zstd(context: ExecutionContext) -> CrushResult<()> {
+    compress(context, Compression::Zstd)
+}
+
+pub fn unzstd(context: ExecutionContext) -> CrushResult<()> {
+    decompress(context, Compression::Zstd)
+}
+
+pub fn xz(context: ExecutionContext) -> CrushResult<()> {
+    compress(context, Compression::Xz)
+}
+
+pub fn unxz(context: ExecutionContext) -> CrushResult<()> {
+    decompress(context, Compression::Xz)
+}
+
+pub fn bzip2(context: ExecutionContext) -> CrushResult<()> {
+    compress(context, Compression::Bzip2)
+}
+
+pub fn bunzip2(context: ExecutionContext) -> CrushResult<()> {
+    decompress(context, Compression::Bzip2)
+}
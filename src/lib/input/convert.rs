@@ -0,0 +1,164 @@
+use std::io::Read;
+use std::path::Path;
+
+use serde::ser::{Error, Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::lang::errors::{argument_error, CrushResult};
+use crate::lang::stream::ValueReceiver;
+use crate::lang::{argument::Argument, binary::BinaryReader, value::Value};
+use crate::util::compression::Decompress;
+use super::json::base64;
+
+/**
+    The input of a parser, either the specified files or a binary stream piped to it. If the
+    decompress argument is given, the input is decompressed as it is read.
+*/
+pub fn input_reader(arguments: Vec<Argument>, input: ValueReceiver) -> CrushResult<Box<dyn BinaryReader + Send + Sync>> {
+    let (files, decompress) = input_arguments(arguments)?;
+    binary_input(files, decompress, input)
+}
+
+/**
+    The files and the decompress argument of a parser that has no other arguments.
+*/
+pub fn input_arguments(arguments: Vec<Argument>) -> CrushResult<(Vec<Box<Path>>, Decompress)> {
+    let mut decompress = Decompress::None;
+    let mut files = Vec::new();
+    for arg in arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (Some("decompress"), Value::String(s)) => decompress = Decompress::parse(&s)?,
+            (None, value) => value.file_expand(&mut files)?,
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+    Ok((files, decompress))
+}
+
+/**
+    Like input_reader, for parsers that have arguments of their own.
+*/
+pub fn binary_input(files: Vec<Box<Path>>, decompress: Decompress, input: ValueReceiver) -> CrushResult<Box<dyn BinaryReader + Send + Sync>> {
+    if files.is_empty() {
+        match input.recv()? {
+            Value::BinaryStream(b) => BinaryReader::decompressed(b, decompress),
+            Value::Binary(b) => BinaryReader::decompressed(BinaryReader::vec(&b), decompress),
+            _ => argument_error("Expected either a file to read or binary pipe input"),
+        }
+    } else {
+        BinaryReader::paths_decompressed(files, decompress)
+    }
+}
+
+pub fn duration_seconds(d: &chrono::Duration) -> f64 {
+    match d.num_nanoseconds() {
+        Some(ns) => ns as f64 / 1_000_000_000.0,
+        None => d.num_milliseconds() as f64 / 1000.0,
+    }
+}
+
+/**
+    Makes a crush value serializable with serde, so that it can be written in any format
+    that has a serde serializer. This is also how to_json writes values. The order of struct
+    fields and table columns is kept.
+*/
+pub struct Serializable<'a>(pub &'a Value);
+
+struct SerializableRow<'a> {
+    names: &'a [Box<str>],
+    cells: Vec<Value>,
+}
+
+impl Serialize for SerializableRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.cells.len()))?;
+        for (name, cell) in self.names.iter().zip(self.cells.iter()) {
+            map.serialize_entry(name.as_ref(), &Serializable(cell))?;
+        }
+        map.end()
+    }
+}
+
+fn serialize_integer<S: Serializer>(i: i128, serializer: S) -> Result<S::Ok, S::Error> {
+    if i >= i64::MIN as i128 && i <= i64::MAX as i128 {
+        serializer.serialize_i64(i as i64)
+    } else if i >= 0 && i <= u64::MAX as i128 {
+        serializer.serialize_u64(i as u64)
+    } else {
+        serializer.serialize_i128(i)
+    }
+}
+
+impl Serialize for Serializable<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Empty() => serializer.serialize_none(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Integer(i) => serialize_integer(*i, serializer),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::File(f) => serializer.serialize_str(&f.to_string_lossy()),
+            Value::Time(t) => serializer.serialize_str(t.to_rfc3339().as_str()),
+            Value::Duration(d) => serializer.serialize_f64(duration_seconds(d)),
+            Value::Field(_) | Value::Glob(_) | Value::Type(_) =>
+                serializer.serialize_str(self.0.to_string().as_str()),
+            Value::Regex(pattern, _) => serializer.serialize_str(pattern),
+            Value::Binary(b) => serializer.serialize_str(base64(b).as_str()),
+            Value::BinaryStream(b) => {
+                let mut data = Vec::new();
+                b.as_ref().clone().read_to_end(&mut data).map_err(S::Error::custom)?;
+                serializer.serialize_str(base64(&data).as_str())
+            }
+            Value::List(l) => {
+                let cells = l.dump();
+                let mut seq = serializer.serialize_seq(Some(cells.len()))?;
+                for cell in &cells {
+                    seq.serialize_element(&Serializable(cell))?;
+                }
+                seq.end()
+            }
+            Value::Dict(d) => {
+                let elements = d.elements();
+                let mut map = serializer.serialize_map(Some(elements.len()))?;
+                for (key, value) in &elements {
+                    map.serialize_entry(key.to_string().as_str(), &Serializable(value))?;
+                }
+                map.end()
+            }
+            Value::Struct(s) => {
+                let names = s.types().into_iter().map(|t| t.name).collect::<Vec<_>>();
+                SerializableRow { names: &names, cells: s.into_vec() }.serialize(serializer)
+            }
+            value => match value.readable() {
+                Some(mut rows) => {
+                    let names = rows.types().iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+                    let mut seq = serializer.serialize_seq(None)?;
+                    while let Ok(row) = rows.read() {
+                        seq.serialize_element(&SerializableRow { names: &names, cells: row.into_vec() })?;
+                    }
+                    seq.end()
+                }
+                None => Err(S::Error::custom(format!(
+                    "Values of type {} can't be serialized", value.value_type().to_string()))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::stream::channels;
+    use crate::util::compression::Compression;
+
+    #[test]
+    fn piped_input_is_decompressed() {
+        let mut data = Vec::new();
+        Compression::Gzip.compress(&mut &b"crush"[..], &mut data, 6).unwrap();
+        let (sender, receiver) = channels();
+        sender.send(Value::Binary(data)).unwrap();
+        let arguments = vec![Argument::named("decompress", Value::string("auto"))];
+        let mut res = Vec::new();
+        input_reader(arguments, receiver).unwrap().read_to_end(&mut res).unwrap();
+        assert_eq!(res, b"crush");
+    }
+}
@@ -1,146 +1,440 @@
-use crate::lang::execution_context::{ExecutionContext, ArgumentVector};
+use crate::lang::execution_context::ExecutionContext;
 use crate::{
     lang::{
         argument::Argument,
         table::Row,
         value::Value,
+        value::ValueType,
     },
-    lang::stream::OutputStream,
-    lang::errors::{CrushError, argument_error},
+    lang::errors::{argument_error, error},
 };
-use std::{
-    io::BufReader,
-    io::prelude::*,
-};
-
-extern crate map_in_place;
+use std::io::{BufRead, BufReader, Write};
 
-use map_in_place::MapVecInPlace;
 use crate::lang::printer::printer;
-use crate::lang::{table::ColumnType, binary::BinaryReader};
+use crate::lang::{table::ColumnType, binary::BinaryReader, binary::binary_channel};
 use crate::lang::errors::{CrushResult, to_crush_error};
-use crate::lang::stream::ValueReceiver;
+use crate::lang::stream::{ValueReceiver, ValueSender};
+use crate::util::compression::Decompress;
+use super::convert::binary_input;
 
 pub struct Config {
     separator: char,
     columns: Vec<ColumnType>,
+    header: bool,
+    sample: usize,
     skip_head: usize,
     trim: Option<char>,
     input: Box<dyn BinaryReader>,
 }
 
+fn single_char(value: &str, message: &str) -> CrushResult<char> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => argument_error(message),
+    }
+}
+
 fn parse(arguments: Vec<Argument>, input: ValueReceiver) -> CrushResult<Config> {
     let mut separator = ',';
     let mut columns = Vec::new();
+    let mut header = false;
+    let mut sample = 100;
     let mut skip_head = 0;
     let mut trim = None;
+    let mut decompress = Decompress::None;
     let mut files = Vec::new();
 
     for arg in arguments {
-        match &arg.argument_type {
-            None => {
-                files.push(arg);
-            }
-            Some(name) => {
-                match (name.as_ref(), arg.value) {
-                    (_, Value::Type(s)) => columns.push(ColumnType::new(name, s)),
-
-                    ("head", Value::Integer(s)) => skip_head = s as usize,
-
-                    ("separator", Value::String(s)) =>
-                        if s.len() == 1 {
-                            separator = s.chars().next().unwrap();
-                        } else {
-                            return argument_error("Separator must be exactly one character long");
-                        }
-
-                    ("trim", Value::String(s)) =>
-                        if s.len() == 1 {
-                            trim = Some(s.chars().next().unwrap());
-                        } else {
-                            return argument_error("Only one character can be trimmed");
-                        }
-
-                    _ => return argument_error(format!("Unknown parameter {}", name).as_str()),
-                }
-            }
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, value) => value.file_expand(&mut files)?,
+            (Some(name), Value::Type(s)) => columns.push(ColumnType::new(name, s)),
+            (Some("head"), Value::Integer(s)) => skip_head = s as usize,
+            (Some("header"), Value::Bool(b)) => header = b,
+            (Some("sample"), Value::Integer(s)) if s > 0 => sample = s as usize,
+            (Some("separator"), Value::String(s)) =>
+                separator = single_char(&s, "Separator must be exactly one character long")?,
+            (Some("trim"), Value::String(s)) =>
+                trim = Some(single_char(&s, "Only one character can be trimmed")?),
+            (Some("decompress"), Value::String(s)) => decompress = Decompress::parse(&s)?,
+            (Some(name), _) => return argument_error(format!("Unknown parameter {}", name).as_str()),
         }
     }
 
-    let reader = match files.len() {
-        0 => {
-            match input.recv()? {
-                Value::BinaryStream(b) => Ok(b),
-                Value::Binary(b) => Ok(BinaryReader::vec(&b)),
-                _ => argument_error("Expected either a file to read or binary pipe input"),
-            }
-        }
-        _ => BinaryReader::paths(files.files()?),
-    }?;
+    let reader = binary_input(files, decompress, input)?;
 
     Ok(Config {
         separator,
         columns,
+        header,
+        sample,
         skip_head,
         trim,
         input: reader,
     })
 }
 
-fn run(cfg: Config, output: OutputStream) -> CrushResult<()> {
+/**
+    A single record of a CSV file, along with the line it started on.
+*/
+struct Record {
+    line: usize,
+    fields: Vec<String>,
+}
 
-    let separator = cfg.separator.clone();
-    let trim = cfg.trim.clone();
-    let columns = cfg.columns.clone();
-    let skip = cfg.skip_head;
+/**
+    Reads records as described in RFC 4180. Fields may be quoted, in which case they can
+    contain separators, newlines and quotes, the latter written as two quotes. Both \n and
+    \r\n line endings are accepted, and the last line does not need a line ending.
+*/
+struct RecordReader<R: BufRead> {
+    reader: R,
+    separator: char,
+    trim: Option<char>,
+    line: usize,
+}
 
-    let mut reader = BufReader::new(cfg.input);
-    let mut line = String::new();
-    let mut skipped = 0usize;
-    loop {
-        line.clear();
-        to_crush_error(reader.read_line(&mut line))?;
-        if line.is_empty() {
-            break;
+impl<R: BufRead> RecordReader<R> {
+    fn read_line(&mut self, buf: &mut String) -> CrushResult<bool> {
+        buf.clear();
+        if to_crush_error(self.reader.read_line(buf))? == 0 {
+            return Ok(false);
         }
-        if skipped < skip {
-            skipped += 1;
-            continue;
+        self.line += 1;
+        if buf.ends_with('\n') {
+            buf.pop();
+            if buf.ends_with('\r') {
+                buf.pop();
+            }
         }
-        let line_without_newline = &line[0..line.len() - 1];
-        let mut split: Vec<&str> = line_without_newline
-            .split(separator)
-            .map(|s| trim
-                .map(|c| s.trim_matches(c))
-                .unwrap_or(s))
-            .collect();
+        Ok(true)
+    }
+
+    fn finish_field(&self, field: &mut String, quoted: bool, fields: &mut Vec<String>) {
+        let value = match (quoted, self.trim) {
+            (false, Some(trim)) => field.trim_matches(trim).to_string(),
+            _ => field.clone(),
+        };
+        fields.push(value);
+        field.clear();
+    }
 
-        if split.len() != columns.len() {
-            printer().error("csv: Wrong number of columns in CSV file");
+    /**
+        Read the next record. Returns None at the end of the input. Malformed records are
+        consumed and reported as errors, so that reading can continue with the next one.
+    */
+    fn next(&mut self) -> Option<CrushResult<Record>> {
+        let mut buf = String::new();
+        loop {
+            match self.read_line(&mut buf) {
+                Ok(false) => return None,
+                Ok(true) if buf.is_empty() => continue,
+                Ok(true) => break,
+                Err(e) => return Some(Err(e)),
+            }
         }
+        let start = self.line;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        loop {
+            let mut chars = buf.chars().peekable();
+            while let Some(c) = chars.next() {
+                if in_quotes {
+                    if c == '"' {
+                        if chars.peek() == Some(&'"') {
+                            chars.next();
+                            field.push('"');
+                        } else {
+                            in_quotes = false;
+                            while self.trim.is_some() && chars.peek() == self.trim.as_ref() {
+                                chars.next();
+                            }
+                            match chars.peek() {
+                                None => {}
+                                Some(c) if *c == self.separator => {}
+                                Some(_) => return Some(error(format!(
+                                    "Line {}: unexpected character after a closing quote", self.line).as_str())),
+                            }
+                        }
+                    } else {
+                        field.push(c);
+                    }
+                } else if c == self.separator {
+                    self.finish_field(&mut field, quoted, &mut fields);
+                    quoted = false;
+                } else if c == '"' && field.chars().all(|f| Some(f) == self.trim) {
+                    field.clear();
+                    quoted = true;
+                    in_quotes = true;
+                } else if c == '"' {
+                    return Some(error(format!(
+                        "Line {}: quote in the middle of an unquoted field", self.line).as_str()));
+                } else {
+                    field.push(c);
+                }
+            }
+            if !in_quotes {
+                break;
+            }
+            /* The quoted field continues on the next line. */
+            field.push('\n');
+            match self.read_line(&mut buf) {
+                Ok(true) => {}
+                Ok(false) => return Some(error(format!(
+                    "Line {}: unterminated quoted field", start).as_str())),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.finish_field(&mut field, quoted, &mut fields);
+        Some(Ok(Record { line: start, fields }))
+    }
+}
+
+/**
+    Find the most specific type all non-empty values in a column of the sample can be
+    parsed as.
+*/
+fn infer_type<'a>(values: impl Iterator<Item=&'a str>) -> ValueType {
+    let candidates = [ValueType::Integer, ValueType::Float, ValueType::Bool];
+    let values = values.filter(|v| !v.is_empty()).collect::<Vec<_>>();
+    if values.is_empty() {
+        return ValueType::String;
+    }
+    candidates.iter()
+        .find(|t| values.iter().all(|v| t.parse(v).is_ok()))
+        .cloned()
+        .unwrap_or(ValueType::String)
+}
+
+fn infer_columns(names: Vec<String>, sample: &[Record]) -> Vec<ColumnType> {
+    names.iter()
+        .enumerate()
+        .map(|(idx, name)| ColumnType::new(
+            name,
+            infer_type(sample.iter().filter_map(|r| r.fields.get(idx)).map(|f| f.as_str()))))
+        .collect()
+}
 
-        if let Some(trim) = trim {
-            split = split.map(|s| s.trim_matches(trim));
+/**
+    Columns that have empty fields in the sample can't hold only values of their inferred
+    type, so their output type is any.
+*/
+fn output_columns(columns: &[ColumnType], sample: &[Record]) -> Vec<ColumnType> {
+    columns.iter()
+        .enumerate()
+        .map(|(idx, column)| match column.cell_type {
+            ValueType::String => column.clone(),
+            _ if sample.iter().any(|r| r.fields.get(idx).map(|f| f.is_empty()).unwrap_or(false)) =>
+                ColumnType::new(column.name.as_ref(), ValueType::Any),
+            _ => column.clone(),
+        })
+        .collect()
+}
+
+/**
+    Fields are parsed as the type in columns, empty fields are only allowed in the output
+    columns of type any.
+*/
+fn to_row(record: Record, columns: &[ColumnType], output: &[ColumnType]) -> CrushResult<Row> {
+    if record.fields.len() != columns.len() {
+        return error(format!(
+            "Line {}: expected {} columns, found {}",
+            record.line, columns.len(), record.fields.len()).as_str());
+    }
+    record.fields.iter()
+        .zip(columns.iter().zip(output.iter()))
+        .map(|(s, (t, o))| match (s.is_empty(), &t.cell_type, &o.cell_type) {
+            (_, ValueType::String, _) => Ok(Value::string(s)),
+            (true, _, ValueType::Any) => Ok(Value::Empty()),
+            (true, cell_type, _) => error(format!(
+                "Line {}: empty field in column {} of type {}",
+                record.line, t.name, cell_type.to_string()).as_str()),
+            (false, cell_type, _) => cell_type.parse(s)
+                .or_else(|e| error(format!("Line {}: {}", record.line, e.message).as_str())),
+        })
+        .collect::<CrushResult<Vec<Value>>>()
+        .map(Row::new)
+}
+
+fn run(cfg: Config, output: ValueSender) -> CrushResult<()> {
+    let mut reader = RecordReader {
+        reader: BufReader::new(cfg.input),
+        separator: cfg.separator,
+        trim: cfg.trim,
+        line: 0,
+    };
+    let mut buf = String::new();
+    for _ in 0..cfg.skip_head {
+        if !reader.read_line(&mut buf)? {
+            break;
         }
+    }
 
-        match split.iter()
-            .zip(columns.iter())
-            .map({ |(s, t)| t.cell_type.parse(*s) })
-            .collect::<Result<Vec<Value>, CrushError>>() {
-            Ok(cells) => {
-                let _ = output.send(Row::new(cells));
-            }
-            Err(err) => {
-                printer().crush_error(err);
+    let names = if cfg.header {
+        match reader.next() {
+            Some(record) => Some(record?.fields),
+            None => Some(Vec::new()),
+        }
+    } else {
+        None
+    };
+
+    let mut sample = Vec::new();
+    let columns = if cfg.columns.is_empty() {
+        while sample.len() < cfg.sample {
+            match reader.next() {
+                Some(Ok(record)) => sample.push(record),
+                Some(Err(e)) => printer().crush_error(e),
+                None => break,
             }
         }
+        let names = names.unwrap_or_else(|| {
+            let width = sample.first().map(|r| r.fields.len()).unwrap_or(0);
+            (1..=width).map(|idx| format!("_{}", idx)).collect()
+        });
+        infer_columns(names, &sample)
+    } else {
+        cfg.columns
+    };
+    let output_types = output_columns(&columns, &sample);
+
+    let output = output.initialize(output_types.clone())?;
+    let records = sample.into_iter().map(Ok).chain(std::iter::from_fn(|| reader.next()));
+    for record in records {
+        match record.and_then(|r| to_row(r, &columns, &output_types)) {
+            Ok(row) => if output.send(row).is_err() {
+                /* Whoever is reading our output has stopped listening. */
+                return Ok(());
+            },
+            Err(e) => printer().crush_error(e),
+        }
     }
     Ok(())
 }
 
 pub fn perform(context: ExecutionContext) -> CrushResult<()> {
     let cfg = parse(context.arguments, context.input)?;
-    let output = context.output.initialize(
-        cfg.columns.clone())?;
-    run(cfg, output)
+    run(cfg, context.output)
+}
+
+fn quote(value: &str, separator: char) -> String {
+    if value.contains(|c| c == separator || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_record(out: &mut dyn Write, fields: Vec<String>, separator: char) -> CrushResult<()> {
+    let line = fields.iter()
+        .map(|f| quote(f, separator))
+        .collect::<Vec<_>>()
+        .join(separator.to_string().as_str());
+    to_crush_error(out.write_all(format!("{}\r\n", line).as_bytes()))
+}
+
+pub fn to_csv(context: ExecutionContext) -> CrushResult<()> {
+    let mut separator = ',';
+    let mut header = true;
+    for arg in context.arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (Some("separator"), Value::String(s)) =>
+                separator = single_char(&s, "Separator must be exactly one character long")?,
+            (Some("header"), Value::Bool(b)) => header = b,
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+    let mut rows = match context.input.recv()?.readable() {
+        Some(rows) => rows,
+        None => return argument_error("Expected a table stream as input"),
+    };
+    let (mut out, reader) = binary_channel()?;
+    context.output.send(Value::BinaryStream(reader))?;
+    if header {
+        let names = rows.types().iter().map(|t| t.name.to_string()).collect();
+        write_record(&mut out, names, separator)?;
+    }
+    while let Ok(row) = rows.read() {
+        let fields = row.into_vec().iter()
+            .map(|v| match v {
+                Value::Empty() => String::new(),
+                v => v.to_string(),
+            })
+            .collect();
+        write_record(&mut out, fields, separator)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(input: &str) -> Vec<CrushResult<Vec<String>>> {
+        let mut reader = RecordReader {
+            reader: BufReader::new(input.as_bytes()),
+            separator: ',',
+            trim: None,
+            line: 0,
+        };
+        std::iter::from_fn(|| reader.next())
+            .map(|r| r.map(|r| r.fields))
+            .collect()
+    }
+
+    #[test]
+    fn quoted_fields_can_contain_separators_quotes_and_newlines() {
+        let res = records("a,\"b,c\",\"say \"\"hi\"\"\"\r\n\"multi\nline\",x,\n");
+        assert_eq!(res[0].as_ref().unwrap(), &vec!["a", "b,c", "say \"hi\""]);
+        assert_eq!(res[1].as_ref().unwrap(), &vec!["multi\nline", "x", ""]);
+        assert_eq!(res.len(), 2);
+    }
+
+    #[test]
+    fn the_last_line_does_not_need_a_newline() {
+        let res = records("ab,cd");
+        assert_eq!(res[0].as_ref().unwrap(), &vec!["ab", "cd"]);
+    }
+
+    #[test]
+    fn malformed_records_are_reported_with_line_numbers() {
+        let res = records("ok\nb\"ad\n\"x\"y\n\"open\n");
+        assert!(res[0].is_ok());
+        assert!(res[1].as_ref().unwrap_err().message.starts_with("Line 2:"));
+        assert!(res[2].as_ref().unwrap_err().message.starts_with("Line 3:"));
+        assert!(res[3].as_ref().unwrap_err().message.starts_with("Line 4:"));
+    }
+
+    #[test]
+    fn types_are_inferred_from_all_values() {
+        assert_eq!(infer_type(vec!["1", "", "3"].into_iter()), ValueType::Integer);
+        assert_eq!(infer_type(vec!["1", "2.5"].into_iter()), ValueType::Float);
+        assert_eq!(infer_type(vec!["true", "false"].into_iter()), ValueType::Bool);
+        assert_eq!(infer_type(vec!["1", "x"].into_iter()), ValueType::String);
+    }
+
+    #[test]
+    fn columns_with_empty_fields_are_of_type_any() {
+        let sample = records("a,b,c\n1,x,\n,y,2\n").into_iter()
+            .enumerate()
+            .map(|(idx, r)| Record { line: idx + 1, fields: r.unwrap() })
+            .collect::<Vec<_>>();
+        let columns = infer_columns(sample[0].fields.clone(), &sample[1..]);
+        let output = output_columns(&columns, &sample[1..]);
+        let types = output.iter().map(|c| c.cell_type.clone()).collect::<Vec<_>>();
+        assert_eq!(types, vec![ValueType::Any, ValueType::String, ValueType::Any]);
+        let row = to_row(Record { line: 4, fields: vec!["".to_string(), "".to_string(), "3".to_string()] }, &columns, &output).unwrap();
+        assert!(row.cells()[0].value_type() == ValueType::Empty);
+        assert!(row.cells()[2] == Value::Integer(3));
+        assert!(to_row(Record { line: 5, fields: vec!["1".to_string(), "".to_string(), "".to_string()] }, &columns, &columns).is_err());
+    }
+
+    #[test]
+    fn fields_are_quoted_when_needed() {
+        assert_eq!(quote("plain", ','), "plain");
+        assert_eq!(quote("a,b", ','), "\"a,b\"");
+        assert_eq!(quote("a\"b", ';'), "\"a\"\"b\"");
+    }
 }
@@ -1,4 +1,4 @@
-use crate::lang::execution_context::{ExecutionContext, ArgumentVector};
+use crate::lang::execution_context::ExecutionContext;
 use crate::{
     lang::{
         argument::Argument,
@@ -8,34 +8,27 @@ use crate::{
     },
     lang::errors::{CrushError, argument_error},
 };
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter, Write};
 
-use crate::lang::{r#struct::Struct, list::List, table::Table, binary::BinaryReader};
+use crate::lang::{r#struct::Struct, list::List, table::Table, binary::BinaryReader, binary::binary_channel};
 use crate::lang::errors::{CrushResult, to_crush_error, error};
 use crate::lang::stream::{ValueSender, ValueReceiver};
 use std::collections::HashSet;
 use crate::lang::errors::Kind::InvalidData;
 use crate::lang::table::ColumnType;
+use super::convert::{input_reader, Serializable};
 
 pub struct Config {
     input: Box<dyn BinaryReader>,
 }
 
-fn parse(mut arguments: Vec<Argument>, input: ValueReceiver) -> CrushResult<Config> {
-    let reader = match arguments.len() {
-        0 => match input.recv()? {
-            Value::BinaryStream(b) => Ok(b),
-            Value::Binary(b) => Ok(BinaryReader::vec(&b)),
-            _ => argument_error("Expected either a file to read or binary pipe input"),
-        },
-        _ => BinaryReader::paths(arguments.files()?),
-    };
+fn parse(arguments: Vec<Argument>, input: ValueReceiver) -> CrushResult<Config> {
     Ok(Config {
-        input: reader?,
+        input: input_reader(arguments, input)?,
     })
 }
 
-fn convert_json(json_value: &serde_json::Value) -> CrushResult<Value> {
+pub fn convert_json(json_value: &serde_json::Value) -> CrushResult<Value> {
     match json_value {
         serde_json::Value::Null => Ok(Value::Empty()),
         serde_json::Value::Bool(b) => Ok(Value::Bool(b.clone())),
@@ -113,3 +106,91 @@ pub fn perform(context: ExecutionContext) -> CrushResult<()> {
     let cfg = parse(context.arguments, context.input)?;
     run(cfg, context.output)
 }
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64(data: &[u8]) -> String {
+    let mut res = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(BASE64[(n >> (18 - 6 * i)) & 63] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+/**
+    Writes crush values as JSON, keeping the order of struct fields and table columns. Table
+    streams are written as they are read instead of being collected first.
+*/
+pub fn write_json(value: Value, out: &mut dyn Write, pretty: bool) -> CrushResult<()> {
+    if pretty {
+        to_crush_error(serde_json::to_writer_pretty(out, &Serializable(&value)))
+    } else {
+        to_crush_error(serde_json::to_writer(out, &Serializable(&value)))
+    }
+}
+
+pub fn to_json(context: ExecutionContext) -> CrushResult<()> {
+    let mut pretty = false;
+    for arg in context.arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (Some("pretty"), Value::Bool(b)) => pretty = b,
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+    let value = context.input.recv()?;
+    let (out, reader) = binary_channel()?;
+    /* Send the stream first, so that table streams can be converted while they are read. */
+    context.output.send(Value::BinaryStream(reader))?;
+    /* The writer produces many small fragments, don't send each one through the channel. */
+    let mut out = BufWriter::new(out);
+    write_json(value, &mut out, pretty)?;
+    to_crush_error(out.write_all(b"\n"))?;
+    to_crush_error(out.flush())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_partial_chunks() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    fn json(value: Value, pretty: bool) -> String {
+        let mut res = Vec::new();
+        write_json(value, &mut res, pretty).unwrap();
+        String::from_utf8(res).unwrap()
+    }
+
+    #[test]
+    fn struct_fields_keep_their_order() {
+        let s = Struct::new(vec![
+            (Box::from("b"), Value::Integer(1)),
+            (Box::from("a"), Value::string("x\"y")),
+        ], None);
+        assert_eq!(json(Value::Struct(s.clone()), false), r#"{"b":1,"a":"x\"y"}"#);
+        assert_eq!(json(Value::Struct(s), true), "{\n  \"b\": 1,\n  \"a\": \"x\\\"y\"\n}");
+    }
+
+    #[test]
+    fn tables_become_arrays_of_objects() {
+        let table = Table::new(
+            vec![ColumnType::new("n", ValueType::Integer), ColumnType::new("e", ValueType::Any)],
+            vec![Row::new(vec![Value::Integer(1), Value::Empty()])]);
+        assert_eq!(json(Value::Table(table), false), r#"[{"n":1,"e":null}]"#);
+        assert_eq!(json(Value::List(List::new(ValueType::Integer, vec![])), true), "[]");
+    }
+}
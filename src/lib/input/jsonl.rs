@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{argument_error, error, to_crush_error, CrushResult};
+use crate::lang::printer::printer;
+use crate::lang::stream::{OutputStream, ValueReceiver};
+use crate::lang::{argument::Argument, binary::binary_channel, binary::BinaryReader};
+use crate::lang::{table::ColumnType, table::Row, value::Value, value::ValueType};
+use crate::util::compression::Decompress;
+use super::convert::binary_input;
+use super::json::{convert_json, write_json};
+
+struct Config {
+    input: Box<dyn BinaryReader + Send + Sync>,
+    sample: usize,
+}
+
+fn parse(arguments: Vec<Argument>, input: ValueReceiver) -> CrushResult<Config> {
+    let mut files = Vec::new();
+    let mut sample = 100;
+    let mut decompress = Decompress::None;
+    for arg in arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, value) => value.file_expand(&mut files)?,
+            (Some("sample"), Value::Integer(n)) if n > 0 => sample = n as usize,
+            (Some("sample"), _) => return argument_error("sample must be a positive integer"),
+            (Some("decompress"), Value::String(s)) => decompress = Decompress::parse(&s)?,
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+    let input = binary_input(files, decompress, input)?;
+    Ok(Config { input, sample })
+}
+
+/**
+    A single line of input, converted to a list of key/value pairs.
+*/
+struct Line {
+    number: usize,
+    members: Vec<(String, Value)>,
+}
+
+fn parse_line(number: usize, text: &str) -> CrushResult<Line> {
+    let json = match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(json) => json,
+        Err(e) => return error(format!("Line {}: {}", number, e).as_str()),
+    };
+    match json {
+        serde_json::Value::Object(o) => Ok(Line {
+            number,
+            members: o.iter()
+                .map(|(k, v)| Ok((k.clone(), convert_json(v)?)))
+                .collect::<CrushResult<Vec<_>>>()?,
+        }),
+        _ => error(format!("Line {}: expected a JSON object", number).as_str()),
+    }
+}
+
+/**
+    Columns are created in the order keys are first seen in the sample. A column has the
+    type of its values if they all have the same type. If the values differ in type, or
+    the key is null or missing in some lines, the column is of type any.
+*/
+fn infer_columns(sample: &[Line]) -> Vec<ColumnType> {
+    let mut columns: Vec<ColumnType> = Vec::new();
+    let mut index = HashMap::new();
+    for (number, line) in sample.iter().enumerate() {
+        for (key, value) in &line.members {
+            let value_type = match value.value_type() {
+                ValueType::Empty => ValueType::Any,
+                t => t,
+            };
+            match index.get(key) {
+                None => {
+                    index.insert(key.clone(), columns.len());
+                    /* The key was missing from all earlier lines. */
+                    columns.push(ColumnType::new(key, if number == 0 { value_type } else { ValueType::Any }));
+                }
+                Some(idx) => {
+                    let column = &mut columns[*idx];
+                    if column.cell_type != value_type {
+                        column.cell_type = ValueType::Any;
+                    }
+                }
+            }
+        }
+        for column in columns.iter_mut() {
+            if !line.members.iter().any(|(key, _)| key.as_str() == column.name.as_ref()) {
+                column.cell_type = ValueType::Any;
+            }
+        }
+    }
+    columns
+}
+
+fn to_row(line: Line, columns: &[ColumnType]) -> CrushResult<Row> {
+    let number = line.number;
+    let mut cells = columns.iter().map(|_| None).collect::<Vec<_>>();
+    for (key, value) in line.members {
+        if let Some(idx) = columns.iter().position(|c| c.name.as_ref() == key.as_str()) {
+            if !columns[idx].cell_type.is(&value) {
+                return error(format!(
+                    "Line {}: expected a value of type {} for {}, got {}",
+                    number,
+                    columns[idx].cell_type.to_string(),
+                    key,
+                    value.value_type().to_string()).as_str());
+            }
+            cells[idx] = Some(value);
+        }
+    }
+    cells.into_iter()
+        .zip(columns.iter())
+        .map(|(cell, column)| match (cell, &column.cell_type) {
+            (Some(value), _) => Ok(value),
+            (None, ValueType::Any) => Ok(Value::Empty()),
+            (None, _) => error(format!("Line {}: missing value for {}", number, column.name).as_str()),
+        })
+        .collect::<CrushResult<Vec<_>>>()
+        .map(Row::new)
+}
+
+/**
+    Send a row, returning false if whoever is reading our output has stopped listening.
+*/
+fn send(output: &OutputStream, line: Line, columns: &[ColumnType]) -> bool {
+    match to_row(line, columns) {
+        Ok(row) => output.send(row).is_ok(),
+        Err(e) => {
+            printer().crush_error(e);
+            true
+        }
+    }
+}
+
+pub fn perform(context: ExecutionContext) -> CrushResult<()> {
+    let cfg = parse(context.arguments, context.input)?;
+    let mut lines = BufReader::new(cfg.input).lines().enumerate()
+        .filter(|(_, l)| l.as_ref().map(|l| !l.trim().is_empty()).unwrap_or(true))
+        .map(|(idx, l)| to_crush_error(l).and_then(|l| parse_line(idx + 1, &l)));
+
+    let mut sample = Vec::new();
+    while sample.len() < cfg.sample {
+        match lines.next() {
+            Some(Ok(line)) => sample.push(line),
+            Some(Err(e)) => printer().crush_error(e),
+            None => break,
+        }
+    }
+
+    let columns = infer_columns(&sample);
+    let output = context.output.initialize(columns.clone())?;
+    for line in sample {
+        if !send(&output, line, &columns) {
+            return Ok(());
+        }
+    }
+    for line in lines {
+        match line {
+            Ok(line) => if !send(&output, line, &columns) {
+                return Ok(());
+            },
+            Err(e) => printer().crush_error(e),
+        }
+    }
+    Ok(())
+}
+
+pub fn to_jsonl(context: ExecutionContext) -> CrushResult<()> {
+    if !context.arguments.is_empty() {
+        return argument_error("to_jsonl does not take any arguments");
+    }
+    let input = context.input.recv()?;
+    let mut rows = match input.readable() {
+        Some(rows) => rows,
+        None => return argument_error("Expected a table stream as input"),
+    };
+    let (mut out, reader) = binary_channel()?;
+    /*
+        Send the stream before writing to it, so that the reader can consume rows while we
+        are still producing them.
+    */
+    context.output.send(Value::BinaryStream(reader))?;
+    let types = rows.types().clone();
+    while let Ok(row) = rows.read() {
+        let mut line = Vec::new();
+        write_json(Value::Struct(row.into_struct(&types)), &mut line, false)?;
+        line.push(b'\n');
+        to_crush_error(out.write_all(&line))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<Line> {
+        text.iter().enumerate().map(|(idx, l)| parse_line(idx + 1, l).unwrap()).collect()
+    }
+
+    #[test]
+    fn columns_with_missing_or_null_values_are_of_type_any() {
+        let sample = lines(&[r#"{"a": 1, "b": "x", "c": 1}"#, r#"{"a": 2, "c": null, "d": true}"#]);
+        let columns = infer_columns(&sample);
+        let types = columns.iter().map(|c| c.cell_type.clone()).collect::<Vec<_>>();
+        assert_eq!(types, vec![ValueType::Integer, ValueType::Any, ValueType::Any, ValueType::Any]);
+        let row = to_row(lines(&[r#"{"a": 3}"#]).remove(0), &columns).unwrap();
+        assert!(row.cells()[1].value_type() == ValueType::Empty);
+        assert!(to_row(lines(&[r#"{"b": "y"}"#]).remove(0), &columns).is_err());
+    }
+}
@@ -1,9 +1,7 @@
-use crate::lang::execution_context::{ExecutionContext, ArgumentVector};
+use crate::lang::execution_context::ExecutionContext;
 use std::io::{BufReader, BufRead};
 use crate::{
-    lang::errors::argument_error,
     lang::{
-        argument::Argument,
         table::Row,
         table::ColumnType,
         value::ValueType,
@@ -11,9 +9,9 @@ use crate::{
     },
     lang::stream::OutputStream,
 };
-use crate::lang::stream::ValueReceiver;
 use crate::lang::errors::{CrushResult, to_crush_error};
 use crate::lang::binary::BinaryReader;
+use super::convert::input_reader;
 
 fn run(input: Box<dyn BinaryReader>, output: OutputStream) -> CrushResult<()> {
     let mut reader = BufReader::new(input);
@@ -29,22 +27,8 @@ fn run(input: Box<dyn BinaryReader>, output: OutputStream) -> CrushResult<()> {
     Ok(())
 }
 
-fn parse(mut arguments: Vec<Argument>, input: ValueReceiver) -> CrushResult<Box<dyn BinaryReader + Send + Sync>> {
-    match arguments.len() {
-        0 => {
-            let v = input.recv()?;
-            match v {
-                Value::BinaryStream(b) => Ok(b),
-                Value::Binary(b) => Ok(BinaryReader::vec(&b)),
-                _ => argument_error("Expected either a file to read or binary pipe input"),
-            }
-        }
-        _ => BinaryReader::paths(arguments.files()?),
-    }
-}
-
 pub fn perform(context: ExecutionContext) -> CrushResult<()> {
     let output = context.output.initialize(vec![ColumnType::new("line", ValueType::String)])?;
-    let file = parse(context.arguments, context.input)?;
+    let file = input_reader(context.arguments, context.input)?;
     run(file, output)
 }
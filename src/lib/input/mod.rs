@@ -9,7 +9,18 @@ use crate::lang::value::ValueType;
 mod lines;
 mod csv;
 mod json;
+mod jsonl;
+mod toml;
+mod yaml;
+mod xml;
+mod avro;
+mod parquet;
+mod sqlite;
+mod serialization;
+mod compression;
+pub mod convert;
 mod http;
+mod write;
 
 pub fn val(mut context: ExecutionContext) -> CrushResult<()> {
     context.arguments.check_len(1)?;
@@ -44,6 +55,84 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
     env.declare("cat", Value::Command(CrushCommand::command(
         cat, true,
         "cat @files:(file|glob)", "Read specified files as binary stream", None)))?;
+    env.declare("gzip", Value::Command(CrushCommand::command(
+        compression::gzip, true,
+        "gzip [level=level:integer] [file:file]",
+        "Compress a binary stream using gzip",
+        Some(r#"    Input can either be a binary stream or a file. The output is a binary
+    stream. level is between 0 and 9 and defaults to 6.
+
+    Examples:
+
+    ps | to_json | gzip | write /tmp/processes.json.gz"#))))?;
+    env.declare("gunzip", Value::Command(CrushCommand::command(
+        compression::gunzip, true,
+        "gunzip [file:file]",
+        "Decompress a gzip compressed binary stream",
+        Some(r#"    Input can either be a binary stream or a file. The input is decompressed
+    as it is read, so even very large files can be processed without temporary
+    files. Concatenated gzip files are decompressed as one.
+
+    Examples:
+
+    cat /var/log/syslog.2.gz | gunzip | lines"#))))?;
+    env.declare("zstd", Value::Command(CrushCommand::command(
+        compression::zstd, true,
+        "zstd [level=level:integer] [file:file]",
+        "Compress a binary stream using zstd",
+        Some(r#"    Input can either be a binary stream or a file. The output is a binary
+    stream. level is between 1 and 22 and defaults to 3.
+
+    Examples:
+
+    ps | serialize | zstd | write /tmp/processes.crush.zst"#))))?;
+    env.declare("unzstd", Value::Command(CrushCommand::command(
+        compression::unzstd, true,
+        "unzstd [file:file]",
+        "Decompress a zstd compressed binary stream",
+        Some(r#"    Input can either be a binary stream or a file, see gunzip.
+
+    Examples:
+
+    unzstd /tmp/processes.crush.zst | deserialize"#))))?;
+    env.declare("xz", Value::Command(CrushCommand::command(
+        compression::xz, true,
+        "xz [level=level:integer] [file:file]",
+        "Compress a binary stream using xz",
+        Some(r#"    Input can either be a binary stream or a file. The output is a binary
+    stream. level is between 0 and 9 and defaults to 6.
+
+    Examples:
+
+    cat /tmp/dump.sql | xz level=9 | write /tmp/dump.sql.xz"#))))?;
+    env.declare("unxz", Value::Command(CrushCommand::command(
+        compression::unxz, true,
+        "unxz [file:file]",
+        "Decompress an xz compressed binary stream",
+        Some(r#"    Input can either be a binary stream or a file, see gunzip.
+
+    Examples:
+
+    unxz /tmp/dump.sql.xz | lines"#))))?;
+    env.declare("bzip2", Value::Command(CrushCommand::command(
+        compression::bzip2, true,
+        "bzip2 [level=level:integer] [file:file]",
+        "Compress a binary stream using bzip2",
+        Some(r#"    Input can either be a binary stream or a file. The output is a binary
+    stream. level is between 1 and 9 and defaults to 9.
+
+    Examples:
+
+    cat /tmp/dump.sql | bzip2 | write /tmp/dump.sql.bz2"#))))?;
+    env.declare("bunzip2", Value::Command(CrushCommand::command(
+        compression::bunzip2, true,
+        "bunzip2 [file:file]",
+        "Decompress a bzip2 compressed binary stream",
+        Some(r#"    Input can either be a binary stream or a file, see gunzip.
+
+    Examples:
+
+    bunzip2 /tmp/dump.sql.bz2 | lines"#))))?;
     env.declare("http", Value::Command(CrushCommand::command(
         http::perform, true,
     "http url:string [form=formdata:string] [method=method:string] [header=header:string]...",
@@ -55,23 +144,313 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
     http "https://example.com/" header=("Authorization: Bearer {}":format token)"#))))?;
     env.declare("lines", Value::Command(CrushCommand::command(
         lines::perform, true,
-        "lines @files:(file|glob)", "Read specified files as a table with one line of text per row", None)))?;
+        "lines [decompress=format:string] @files:(file|glob)",
+        "Read specified files as a table with one line of text per row",
+        Some(r#"    Input can either be a binary stream or files. If decompress is "auto",
+    input compressed with gzip, zstd, xz or bzip2 is recognized from its
+    first few bytes and decompressed as it is read, while other input is
+    read as is. decompress can also be the name of one of those formats, in
+    which case all input must be compressed using it. Each file is
+    decompressed separately, and so is a binary stream.
+
+    Examples:
+
+    lines decompress="auto" /var/log/syslog*
+
+    cat /tmp/log.gz | lines decompress="gzip""#))))?;
     env.declare("csv", Value::Command(CrushCommand::command(
         csv::perform, true,
-        "csv <column_name>=type:type... [head=skip:integer] [separator=separator:string] [trim=trim:string] @files:(file|glob)",
-        "Parse specified files as CSV files", Some(r#"    Examples:
+        "csv <column_name>=type:type... [header=header:bool] [sample=sample:integer] [head=skip:integer] [separator=separator:string] [trim=trim:string] [decompress=format:string] @files:(file|glob)",
+        "Parse specified files as CSV files", Some(r#"    Fields may be quoted with double quotes, in which case they can contain
+    separators, newlines and quotes, the latter written as two double quotes.
+
+    If column types are given, they are used in the order they are specified.
+    Otherwise, column names are taken from the first row if header is true, or
+    are called _1, _2 and so on. Column types are then inferred from the first
+    sample rows, 100 by default, as the first of integer, float and bool that
+    all values in the column can be parsed as, or string. Columns that are not
+    of type string and have empty fields in the sample are of type any, and
+    those fields are empty. Rows with empty fields in other columns are
+    reported and skipped.
+
+    head skips the specified number of lines before the header or the first
+    row. trim removes the specified character from both ends of unquoted
+    fields.
+
+    decompress works like it does for the lines command.
+
+    Rows that are malformed or have the wrong number of columns are reported
+    along with their line number and skipped.
+
+    Examples:
+
+    csv separator="," head=1 name=type:string age=type:integer nick=type:string
+
+    csv header=true example_data/age.csv"#))))?;
+    env.declare("to_csv", Value::Command(CrushCommand::command(
+        csv::to_csv, true,
+        "to_csv [header=header:bool] [separator=separator:string]",
+        "Convert the input table stream to CSV",
+        Some(r#"    The result is a binary stream with one line per row, separated by \r\n.
+    Unless header is false, the first line contains the column names. Fields
+    containing the separator, quotes or newlines are quoted.
+
+    Examples:
 
-    csv separator="," head=1 name=string age=integer nick=string"#))))?;
+    ps | to_csv | write /tmp/processes.csv"#))))?;
     env.declare("json", Value::Command(CrushCommand::command(
         json::perform, true,
-        "json [file:file]", "Parse json", Some(
-            r#"    Input can either be a binary stream or a file.
+        "json [decompress=format:string] [file:file]", "Parse json", Some(
+            r#"    Input can either be a binary stream or a file. decompress works like it
+    does for the lines command.
 
     Examples:
 
     json some_file.json
 
     (http "https://jsonplaceholder.typicode.com/todos/3"):body | json"#))))?;
+    env.declare("write", Value::Command(CrushCommand::command(
+        write::perform, true,
+        "write file:(file|string) [append=append:bool] [atomic=atomic:bool] [format=format:string]",
+        "Write the input to a file",
+        Some(r#"    Binary streams and strings are written as is. Table streams, tables,
+    lists and dicts are written with one row per line and cells separated by
+    tabs. Any other value is written as a single line of text. This is the
+    "raw" format, which is the default. With format="json", the input is
+    written as pretty printed JSON instead, see to_json.
+
+    If append is true, the input is added to the end of the file instead of
+    replacing its contents. If atomic is true, the data is first written to a
+    temporary file in the same directory which is then renamed to the
+    destination, so that readers never see a partially written file. The
+    temporary file gets the permissions of the file it replaces, and both the
+    file and the rename are synced to disk before write returns.
+
+    Examples:
+
+    ps | write /tmp/processes.txt
+
+    (http "https://example.com/"):body | write /tmp/example.html atomic=true"#))))?;
+    env.declare("toml", Value::Command(CrushCommand::command(
+        toml::perform, true,
+        "toml [decompress=format:string] [file:file]", "Parse toml", Some(
+            r#"    Input can either be a binary stream or a file. Tables become structs, and
+    arrays of tables with the same keys become tables, just like with the json
+    command. Dates and times become strings. decompress works like it does for
+    the lines command.
+
+    Examples:
+
+    (toml Cargo.toml):dependencies"#))))?;
+    env.declare("to_toml", Value::Command(CrushCommand::command(
+        toml::to_toml, true,
+        "to_toml",
+        "Convert the input to TOML",
+        Some(r#"    The result is a binary stream. Values are converted the same way as by
+    to_json, except that empty values are left out, since TOML has no null.
+    The input must be a struct or a dict.
+
+    Examples:
+
+    data name="crush" version="0.1.0" | to_toml"#))))?;
+    env.declare("yaml", Value::Command(CrushCommand::command(
+        yaml::perform, true,
+        "yaml [decompress=format:string] [file:file]", "Parse yaml", Some(
+            r#"    Input can either be a binary stream or a file. Mappings become structs,
+    and sequences of mappings with the same keys become tables, just like
+    with the json command. Keys that are not strings are converted to strings.
+    If the input contains several documents separated by ---, a list with one
+    element per document is returned. decompress works like it does for the
+    lines command.
+
+    Examples:
+
+    (yaml deployment.yaml):spec:replicas"#))))?;
+    env.declare("to_yaml", Value::Command(CrushCommand::command(
+        yaml::to_yaml, true,
+        "to_yaml",
+        "Convert the input to YAML",
+        Some(r#"    The result is a binary stream. Values are converted the same way as by
+    to_json.
+
+    Examples:
+
+    ps | head 3 | to_yaml"#))))?;
+    env.declare("jsonl", Value::Command(CrushCommand::command(
+        jsonl::perform, true,
+        "jsonl [sample=sample:integer] [decompress=format:string] @files:(file|glob)",
+        "Parse newline delimited JSON into a table stream",
+        Some(r#"    Every non-empty line of input must contain one JSON object, which becomes
+    one row of output. Input is read one line at a time, so it can be arbitrarily
+    large. Input can either be a binary stream or files. decompress works like
+    it does for the lines command.
+
+    The columns are taken from the first sample objects, 100 by default. Keys
+    that first appear later on are ignored. Columns whose values have different
+    types in the sample, or that are null or missing in some of the sample, are
+    of type any, and missing keys are empty. Lines that can't be parsed or don't
+    match the column types are reported and skipped.
+
+    Examples:
+
+    jsonl /var/log/app.jsonl | where {level == "error"}"#))))?;
+    env.declare("to_jsonl", Value::Command(CrushCommand::command(
+        jsonl::to_jsonl, true,
+        "to_jsonl",
+        "Convert the input table stream to newline delimited JSON",
+        Some(r#"    Every row becomes one JSON object on a separate line, see to_json for how
+    values are converted. Rows are written as they are read, so the output can
+    be consumed while the input is still being produced.
+
+    Examples:
+
+    ps | to_jsonl | write /tmp/processes.jsonl"#))))?;
+    env.declare("to_json", Value::Command(CrushCommand::command(
+        json::to_json, true,
+        "to_json [pretty=pretty:bool]",
+        "Convert the input to JSON",
+        Some(r#"    The result is a binary stream. Values are converted as follows:
+
+    * tables and table streams become arrays of objects, one per row
+    * structs and dicts become objects, dict keys are converted to strings
+    * lists become arrays
+    * empty becomes null, as do floats that are not finite
+    * times become strings in RFC 3339 format
+    * durations become numbers of seconds
+    * files, fields, globs, regexes and types become strings
+    * binary data and binary streams become base64 encoded strings
+
+    Commands and scopes can't be converted.
+
+    Examples:
+
+    ps | to_json pretty=true | write /tmp/processes.json
+
+    data name="Triceratops" horns=3 | to_json"#))))?;
+    env.declare("to_sqlite", Value::Command(CrushCommand::command(
+        sqlite::to_sqlite, true,
+        "to_sqlite db:file table=name:string",
+        "Write the input to a table in an SQLite database",
+        Some(r#"    The database and the table are created if they do not exist. Columns
+    get an SQL type matching their crush type. Times are stored as text in
+    RFC 3339 format, durations as seconds and lists, dicts, structs and
+    tables as JSON. All rows are inserted in a single transaction. Returns
+    the number of inserted rows.
+
+    Examples:
+
+    ps | to_sqlite /tmp/snapshot.db table="processes""#))))?;
+    env.declare("avro", Value::Command(CrushCommand::command(
+        avro::perform, true,
+        "avro [decompress=format:string] @file:(file|glob)", "Read Avro container files",
+        Some(r#"    Input can either be a binary stream or files. Files are read one block
+    at a time, so arbitrarily large files can be processed. If multiple files
+    are given, they must all have the same schema. decompress works like it
+    does for the lines command.
+
+    If the schema is a record, the output is a table stream with one column
+    per field, otherwise a table stream with a single column named value.
+    Nested records become structs, arrays become lists and maps become
+    dicts. Unions, including optional fields, which are unions with null,
+    become columns of type any. Dates and timestamps become times, times of
+    day become durations and decimals become floats. Dates and local
+    timestamps have no time zone, and are read as local time.
+
+    The null, deflate, snappy and zstandard codecs are supported.
+
+    Examples:
+
+    avro /tmp/events.avro | where {status == 500}"#))))?;
+    env.declare("parquet", Value::Command(CrushCommand::command(
+        parquet::perform, true,
+        "parquet @file:file", "Read Parquet files",
+        Some(r#"    The output is a table stream with one column per top level field. If
+    multiple files are given, they must all have the same schema. Rows are
+    read one row group at a time. Because parquet files are read from the end,
+    they can't be piped to this command.
+
+    Groups become structs, lists and repeated fields become lists and maps
+    become dicts. Dates and timestamps become times and decimals become
+    floats. Optional top level fields become columns of type any, since they
+    can be empty. Dates are read as midnight local time.
+
+    Examples:
+
+    parquet /tmp/trips/*.parquet | group vendor | count"#))))?;
+    env.declare("xml", Value::Command(CrushCommand::command(
+        xml::xml, true,
+        "xml [decompress=format:string] [file:file]", "Parse xml",
+        Some(r#"    Input can either be a binary stream or a file. The document is
+    returned as a struct with the members tag, attributes, children and text.
+    Attributes are a dict from name to value and children is a table of nodes
+    of the same shape. Text between elements is included in the children with
+    the tag #text. The text of an element is all the text inside of it with
+    leading and trailing whitespace removed. decompress works like it does for
+    the lines command.
+
+    Examples:
+
+    xml /tmp/pom.xml
+
+    (http "https://example.com/feed.xml"):body | xml | select_path "//item/title""#))))?;
+    env.declare("html", Value::Command(CrushCommand::command(
+        xml::html, true,
+        "html [decompress=format:string] [file:file]", "Parse html",
+        Some(r#"    Input can either be a binary stream or a file. The result has the same
+    shape as the output of xml. The parser is lenient, like a browser: tag and
+    attribute names are lowercased, unclosed elements are closed implicitly and
+    stray end tags are ignored. Comments and doctype declarations are skipped.
+    decompress works like it does for the lines command.
+
+    Examples:
+
+    (http "https://example.com/"):body | html | select_path "a[href]""#))))?;
+    env.declare("select_path", Value::Command(CrushCommand::command(
+        xml::select_path, true,
+        "select_path query:string", "Find the elements in a document matching a query",
+        Some(r#"    The input is a document created by xml or html, or a table of nodes
+    returned by an earlier select_path. The output is a table stream with one
+    row per matching element, in the same shape as the nodes of the input.
+
+    Queries starting with / are a subset of XPath. Steps are separated by / to
+    select children or // to select descendants. Each step is an element name
+    or *, followed by any number of the predicates [@name], [@name='value']
+    and [n], which selects the nth match among its siblings.
+
+    All other queries are CSS selectors, made of element names, *, .class,
+    #id, [name] and [name=value], combined using space for descendants and >
+    for children.
+
+    Examples:
+
+    xml /tmp/pom.xml | select_path "/project/dependencies/dependency[1]"
+
+    html /tmp/page.html | select_path "div.content > p""#))))?;
+    env.declare("serialize", Value::Command(CrushCommand::command(
+        serialization::to_serialized, true,
+        "serialize",
+        "Convert the input to crush's own binary format",
+        Some(r#"    Unlike the text formats, nothing is lost in the conversion: tables keep
+    their column types, structs keep their parent, and times, durations,
+    globs, regexes, files and types are stored as themselves. Streams are read
+    to completion and stored as tables and binaries. Commands and scopes can
+    not be serialized. A struct inheriting from a class with methods stores
+    the name of the variable holding the class, so that class must be defined
+    under the same name when the data is deserialized. The result is a binary
+    stream that can be read back using deserialize.
+
+    Examples:
+
+    ps | serialize | write /tmp/processes.crush"#))))?;
+    env.declare("deserialize", Value::Command(CrushCommand::command(
+        serialization::perform, true,
+        "deserialize [decompress=format:string] [file:file]", "Read a value written by serialize",
+        Some(r#"    Input can either be a binary stream or a file. decompress works like it
+    does for the lines command.
+
+    Examples:
+
+    deserialize /tmp/processes.crush | where {cpu > 1}"#))))?;
     env.declare("echo", Value::Command(CrushCommand::command(
         echo, false,
         "echo @value:any", "Prints all arguments directly to the screen", None)))?;
@@ -87,5 +466,6 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
         "dir value:any", "List members of value", None)))?;
     env.readonly();
 
+    sqlite::declare(root)?;
     Ok(())
 }
@@ -0,0 +1,293 @@
+use std::fs::File;
+use std::path::Path;
+
+use parquet::basic::{ConvertedType, LogicalType, Repetition, Type as PhysicalType};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::Field;
+use parquet::schema::types::Type;
+
+use crate::lang::execution_context::{ArgumentVector, ExecutionContext};
+use crate::lang::errors::{argument_error, error, to_crush_error, CrushResult};
+use crate::lang::{dict::Dict, list::List, r#struct::Struct};
+use crate::lang::{table::ColumnType, table::Row, value::Value, value::ValueType};
+use super::avro::{date, decimal, timestamp};
+
+fn primitive_type(t: &Type) -> ValueType {
+    let converted = t.get_basic_info().converted_type();
+    match (t.get_physical_type(), converted) {
+        (_, ConvertedType::DECIMAL) => ValueType::Float,
+        (PhysicalType::BOOLEAN, _) => ValueType::Bool,
+        (PhysicalType::INT32, ConvertedType::DATE) => ValueType::Time,
+        (PhysicalType::INT64, ConvertedType::TIMESTAMP_MILLIS) |
+        (PhysicalType::INT64, ConvertedType::TIMESTAMP_MICROS) |
+        (PhysicalType::INT96, _) => ValueType::Time,
+        (PhysicalType::INT32, _) | (PhysicalType::INT64, _) => ValueType::Integer,
+        (PhysicalType::FLOAT, _) | (PhysicalType::DOUBLE, _) => ValueType::Float,
+        (PhysicalType::BYTE_ARRAY, ConvertedType::UTF8) |
+        (PhysicalType::BYTE_ARRAY, ConvertedType::ENUM) |
+        (PhysicalType::BYTE_ARRAY, ConvertedType::JSON) => ValueType::String,
+        (PhysicalType::FIXED_LEN_BYTE_ARRAY, _)
+        if t.get_basic_info().logical_type() == Some(LogicalType::Float16) => ValueType::Float,
+        _ => ValueType::Binary,
+    }
+}
+
+/**
+    The type of a repeated field without a list annotation, i.e. of a single element.
+*/
+fn element_type(t: &Type) -> ValueType {
+    if t.is_primitive() {
+        primitive_type(t)
+    } else {
+        match t.get_fields() {
+            [field] if field.get_basic_info().repetition() != Repetition::REPEATED => value_type(field),
+            _ => ValueType::Struct,
+        }
+    }
+}
+
+fn value_type(t: &Type) -> ValueType {
+    let info = t.get_basic_info();
+    if info.has_repetition() && info.repetition() == Repetition::REPEATED {
+        return ValueType::List(Box::from(element_type(t)));
+    }
+    if t.is_primitive() {
+        return primitive_type(t);
+    }
+    match (info.converted_type(), t.get_fields()) {
+        (ConvertedType::LIST, [repeated]) => ValueType::List(Box::from(element_type(repeated))),
+        (ConvertedType::MAP, [repeated]) | (ConvertedType::MAP_KEY_VALUE, [repeated]) =>
+            match repeated.get_fields() {
+                [key, value] => ValueType::Dict(Box::from(value_type(key)), Box::from(value_type(value))),
+                _ => ValueType::Any,
+            },
+        _ => ValueType::Struct,
+    }
+}
+
+fn optional(t: &Type) -> bool {
+    let info = t.get_basic_info();
+    info.has_repetition() && info.repetition() == Repetition::OPTIONAL
+}
+
+/**
+    Optional fields can be null, so their columns are of type any.
+*/
+fn columns(schema: &Type) -> Vec<ColumnType> {
+    schema.get_fields().iter()
+        .map(|f| ColumnType::new(f.name(), if optional(f) { ValueType::Any } else { value_type(f) }))
+        .collect()
+}
+
+fn element(value_type: &ValueType) -> ValueType {
+    match value_type {
+        ValueType::List(t) => t.as_ref().clone(),
+        _ => ValueType::Any,
+    }
+}
+
+fn convert(field: &Field, value_type: &ValueType) -> CrushResult<Value> {
+    Ok(match field {
+        Field::Null => Value::Empty(),
+        Field::Bool(b) => Value::Bool(*b),
+        Field::Byte(i) => Value::Integer(*i as i128),
+        Field::Short(i) => Value::Integer(*i as i128),
+        Field::Int(i) => Value::Integer(*i as i128),
+        Field::Long(i) => Value::Integer(*i as i128),
+        Field::UByte(i) => Value::Integer(*i as i128),
+        Field::UShort(i) => Value::Integer(*i as i128),
+        Field::UInt(i) => Value::Integer(*i as i128),
+        Field::ULong(i) => Value::Integer(*i as i128),
+        Field::Float16(f) => Value::Float(f.to_f64()),
+        Field::Float(f) => Value::Float(*f as f64),
+        Field::Double(f) => Value::Float(*f),
+        Field::Decimal(d) => Value::Float(decimal(d.data(), d.scale().max(0) as u32)),
+        Field::Str(s) => Value::string(s),
+        Field::Bytes(b) => Value::Binary(b.data().to_vec()),
+        Field::Date(days) => Value::Time(date(*days as i64)?),
+        Field::TimestampMillis(ms) => Value::Time(timestamp(*ms, 1000, false)?),
+        Field::TimestampMicros(us) => Value::Time(timestamp(*us, 1_000_000, false)?),
+        Field::Group(row) => Value::Struct(Struct::new(
+            row.get_column_iter()
+                .map(|(name, field)| Ok((Box::from(name.as_str()), convert(field, &ValueType::Any)?)))
+                .collect::<CrushResult<Vec<_>>>()?,
+            None)),
+        Field::ListInternal(list) => {
+            let element_type = element(value_type);
+            Value::List(List::new(
+                element_type.clone(),
+                list.elements().iter().map(|e| convert(e, &element_type)).collect::<CrushResult<Vec<_>>>()?))
+        }
+        Field::MapInternal(map) => {
+            let (key_type, value_type) = match value_type {
+                ValueType::Dict(k, v) => (k.as_ref().clone(), v.as_ref().clone()),
+                _ => (ValueType::Any, ValueType::Any),
+            };
+            let dict = Dict::new(key_type.clone(), value_type.clone());
+            for (key, value) in map.entries() {
+                /* The key and value types come from the schema, so inserting can't fail. */
+                let _ = dict.insert(convert(key, &key_type)?, convert(value, &value_type)?);
+            }
+            Value::Dict(dict)
+        }
+    })
+}
+
+fn open(file: &Path) -> CrushResult<SerializedFileReader<File>> {
+    let f = to_crush_error(File::open(file))?;
+    match SerializedFileReader::new(f) {
+        Ok(reader) => Ok(reader),
+        Err(e) => error(format!("{}: {}", file.to_string_lossy(), e).as_str()),
+    }
+}
+
+/**
+    Read all rows of a file, stopping early if the callback returns false.
+*/
+fn read_rows(
+    file: &Path,
+    reader: SerializedFileReader<File>,
+    types: &[ValueType],
+    mut send: impl FnMut(Row) -> bool,
+) -> CrushResult<bool> {
+    /* Rows are read one row group at a time, so the whole file is never in memory. */
+    for row in reader.into_iter() {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => return error(format!("{}: {}", file.to_string_lossy(), e).as_str()),
+        };
+        let cells = row.get_column_iter()
+            .zip(types.iter())
+            .map(|((_, field), value_type)| convert(field, value_type))
+            .collect::<CrushResult<Vec<_>>>();
+        let cells = match cells {
+            Ok(cells) => cells,
+            Err(e) => return error(format!("{}: {}", file.to_string_lossy(), e.message).as_str()),
+        };
+        if !send(Row::new(cells)) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+pub fn perform(mut context: ExecutionContext) -> CrushResult<()> {
+    let files = context.arguments.files()?;
+    if files.is_empty() {
+        return argument_error("Expected at least one file");
+    }
+    let first = open(&files[0])?;
+    let schema = first.metadata().file_metadata().schema();
+    let types = columns(schema);
+    /* The types of the values in the file, which may be more specific than the column types. */
+    let value_types = schema.get_fields().iter().map(|f| value_type(f)).collect::<Vec<_>>();
+    let output = context.output.initialize(types.clone())?;
+    let mut readers = vec![first];
+    for file in &files[1..] {
+        readers.push(open(file)?);
+    }
+    for (file, reader) in files.iter().zip(readers.into_iter()) {
+        if columns(reader.metadata().file_metadata().schema()) != types {
+            return error(format!("{}: The schema differs from that of the first file", file.to_string_lossy()).as_str());
+        }
+        if !read_rows(file, reader, &value_types, |row| output.send(row).is_ok())? {
+            /* Whoever is reading our output has stopped listening. */
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+
+    #[test]
+    fn rows_are_read() {
+        let path = std::env::temp_dir().join(format!("crush-parquet-test-{}.parquet", std::process::id()));
+        let schema = Arc::new(parse_message_type("
+            message event {
+                required int64 id;
+                optional binary host (UTF8);
+            }").unwrap());
+        let mut writer = SerializedFileWriter::new(
+            File::create(&path).unwrap(), schema, Default::default()).unwrap();
+        let mut group = writer.next_row_group().unwrap();
+        let mut column = group.next_column().unwrap().unwrap();
+        column.typed::<Int64Type>().write_batch(&[1, 2], None, None).unwrap();
+        column.close().unwrap();
+        let mut column = group.next_column().unwrap().unwrap();
+        column.typed::<ByteArrayType>()
+            .write_batch(&[ByteArray::from("web")], Some(&[1, 0]), None).unwrap();
+        column.close().unwrap();
+        group.close().unwrap();
+        writer.close().unwrap();
+
+        let reader = open(&path).unwrap();
+        let types = reader.metadata().file_metadata().schema().get_fields().iter()
+            .map(|f| value_type(f))
+            .collect::<Vec<_>>();
+        let mut rows = Vec::new();
+        read_rows(&path, reader, &types, |row| {
+            rows.push(row);
+            true
+        }).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].cells()[0] == Value::Integer(1));
+        assert!(rows[0].cells()[1] == Value::string("web"));
+        assert!(rows[1].cells()[1].value_type() == ValueType::Empty);
+    }
+
+    #[test]
+    fn schemas_are_mapped_to_column_types() {
+        let schema = parse_message_type("
+            message event {
+                required int64 id;
+                optional binary host (UTF8);
+                optional int64 at (TIMESTAMP_MILLIS);
+                optional int32 day (DATE);
+                optional fixed_len_byte_array(16) price (DECIMAL(20, 2));
+                optional binary payload;
+                optional group tags (LIST) {
+                    repeated group list {
+                        optional binary element (UTF8);
+                    }
+                }
+                optional group counts (MAP) {
+                    repeated group key_value {
+                        required binary key (UTF8);
+                        optional int32 value;
+                    }
+                }
+                repeated int32 legacy;
+                optional group location {
+                    required double lat;
+                    required double lon;
+                }
+            }").unwrap();
+        let columns = columns(&schema).into_iter().map(|c| c.cell_type).collect::<Vec<_>>();
+        assert!(columns[0] == ValueType::Integer);
+        assert!(columns[1..8] == vec![ValueType::Any; 7][..]);
+        assert!(columns[8] == ValueType::List(Box::from(ValueType::Integer)));
+        assert!(columns[9] == ValueType::Any);
+        let types = schema.get_fields().iter().map(|f| value_type(f)).collect::<Vec<_>>();
+        assert!(types == vec![
+            ValueType::Integer,
+            ValueType::String,
+            ValueType::Time,
+            ValueType::Time,
+            ValueType::Float,
+            ValueType::Binary,
+            ValueType::List(Box::from(ValueType::String)),
+            ValueType::Dict(Box::from(ValueType::String), Box::from(ValueType::Integer)),
+            ValueType::List(Box::from(ValueType::Integer)),
+            ValueType::Struct,
+        ]);
+    }
+}
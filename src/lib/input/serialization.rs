@@ -0,0 +1,19 @@
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{argument_error, CrushResult};
+use crate::lang::serialization::{deserialize, serialize};
+use crate::lang::{binary::BinaryReader, value::Value};
+use super::convert::input_reader;
+
+pub fn perform(context: ExecutionContext) -> CrushResult<()> {
+    let mut reader = input_reader(context.arguments, context.input)?;
+    context.output.send(deserialize(&mut reader, &context.env)?)
+}
+
+pub fn to_serialized(context: ExecutionContext) -> CrushResult<()> {
+    if !context.arguments.is_empty() {
+        return argument_error("serialize does not take any arguments");
+    }
+    let mut res = Vec::new();
+    serialize(context.input.recv()?, &context.env, &mut res)?;
+    context.output.send(Value::BinaryStream(BinaryReader::vec(&res)))
+}
@@ -0,0 +1,501 @@
+use std::path::Path;
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{Connection, OpenFlags, Statement};
+
+use crate::lang::command::CrushCommand;
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{argument_error, error, mandate, CrushResult};
+use crate::lang::scope::Scope;
+use crate::lang::{argument::Argument, table::ColumnType, table::Row};
+use crate::lang::value::{Value, ValueType};
+use super::convert::duration_seconds;
+use super::json::write_json;
+
+struct Config {
+    db: Box<Path>,
+    sql: Option<Box<str>>,
+    params: Vec<Value>,
+    table: Option<Box<str>>,
+}
+
+/**
+    The database and the sql statement are the first two unnamed arguments, any further
+    unnamed arguments are parameters of the statement.
+*/
+fn parse(arguments: Vec<Argument>) -> CrushResult<Config> {
+    let mut unnamed = Vec::new();
+    let mut table = None;
+    for arg in arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, value) => unnamed.push(value),
+            (Some("table"), Value::String(s)) => table = Some(s),
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+    let mut unnamed = unnamed.into_iter();
+    let db = match unnamed.next() {
+        Some(Value::File(f)) => f,
+        Some(Value::String(s)) => Box::from(Path::new(s.as_ref())),
+        _ => return argument_error("Expected a database file"),
+    };
+    let sql = match unnamed.next() {
+        Some(Value::String(s)) => Some(s),
+        None => None,
+        Some(_) => return argument_error("Expected an sql statement"),
+    };
+    Ok(Config { db, sql, params: unnamed.collect(), table })
+}
+
+fn open(db: &Path, read_only: bool) -> CrushResult<Connection> {
+    let flags = if read_only {
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI
+    } else {
+        OpenFlags::default()
+    };
+    match Connection::open_with_flags(db, flags) {
+        Ok(c) => Ok(c),
+        Err(e) => error(format!("{}: {}", db.to_string_lossy(), e).as_str()),
+    }
+}
+
+fn sql_error<T>(e: rusqlite::Error) -> CrushResult<T> {
+    error(e.to_string().as_str())
+}
+
+/**
+    Map a declared column type to a crush type, using the same rules as SQLite uses to
+    determine column affinity. Columns without a declared type, e.g. expressions, can
+    contain anything.
+*/
+fn column_type(declared: Option<&str>) -> ValueType {
+    let declared = match declared {
+        Some(d) => d.to_uppercase(),
+        None => return ValueType::Any,
+    };
+    if declared.contains("INT") {
+        ValueType::Integer
+    } else if declared.contains("CHAR") || declared.contains("CLOB") || declared.contains("TEXT") {
+        ValueType::String
+    } else if declared.contains("BLOB") {
+        ValueType::Binary
+    } else if declared.contains("REAL") || declared.contains("FLOA") || declared.contains("DOUB") {
+        ValueType::Float
+    } else if declared.contains("BOOL") {
+        ValueType::Bool
+    } else if declared.contains("DATE") || declared.contains("TIME") {
+        ValueType::Time
+    } else {
+        ValueType::Any
+    }
+}
+
+fn parse_time(text: &str) -> Option<DateTime<Local>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(text) {
+        return Some(t.with_timezone(&Local));
+    }
+    /* The format used by the SQLite date and time functions, which is always in UTC. */
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"].iter()
+        .filter_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .next()
+        .map(|t| Utc.from_utc_datetime(&t).with_timezone(&Local))
+}
+
+fn natural_value(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Empty(),
+        ValueRef::Integer(i) => Value::Integer(i as i128),
+        ValueRef::Real(f) => Value::Float(f),
+        ValueRef::Text(t) => Value::string(&String::from_utf8_lossy(t)),
+        ValueRef::Blob(b) => Value::Binary(b.to_vec()),
+    }
+}
+
+/**
+    SQLite lets any column contain values of any type, so cells are converted to the type
+    of their column where that can be done without losing information, and keep their own
+    type otherwise.
+*/
+fn cell(column: &ColumnType, value: ValueRef) -> Value {
+    let converted = match (&column.cell_type, value) {
+        (_, ValueRef::Null) => Some(Value::Empty()),
+        (ValueType::Any, value) => Some(natural_value(value)),
+        (ValueType::Integer, ValueRef::Integer(i)) => Some(Value::Integer(i as i128)),
+        (ValueType::Integer, ValueRef::Real(f)) if f.fract() == 0.0 => Some(Value::Integer(f as i128)),
+        (ValueType::Integer, ValueRef::Text(t)) =>
+            String::from_utf8_lossy(t).trim().parse().ok().map(Value::Integer),
+        (ValueType::Float, ValueRef::Real(f)) => Some(Value::Float(f)),
+        (ValueType::Float, ValueRef::Integer(i)) => Some(Value::Float(i as f64)),
+        (ValueType::Float, ValueRef::Text(t)) =>
+            String::from_utf8_lossy(t).trim().parse().ok().map(Value::Float),
+        (ValueType::Bool, ValueRef::Integer(0)) => Some(Value::Bool(false)),
+        (ValueType::Bool, ValueRef::Integer(1)) => Some(Value::Bool(true)),
+        (ValueType::String, ValueRef::Text(t)) => Some(Value::string(&String::from_utf8_lossy(t))),
+        (ValueType::String, ValueRef::Integer(i)) => Some(Value::string(&i.to_string())),
+        (ValueType::String, ValueRef::Real(f)) => Some(Value::string(&f.to_string())),
+        (ValueType::Binary, ValueRef::Blob(b)) | (ValueType::Binary, ValueRef::Text(b)) =>
+            Some(Value::Binary(b.to_vec())),
+        (ValueType::Time, ValueRef::Text(t)) => parse_time(&String::from_utf8_lossy(t)).map(Value::Time),
+        (ValueType::Time, ValueRef::Integer(i)) => Local.timestamp_opt(i, 0).single().map(Value::Time),
+        _ => None,
+    };
+    converted.unwrap_or_else(|| natural_value(value))
+}
+
+fn sql_value(value: Value) -> CrushResult<SqlValue> {
+    Ok(match value {
+        Value::Empty() => SqlValue::Null,
+        Value::Integer(i) => {
+            if i < i64::MIN as i128 || i > i64::MAX as i128 {
+                return error("Integer is too large to be stored in SQLite");
+            }
+            SqlValue::Integer(i as i64)
+        }
+        Value::Bool(b) => SqlValue::Integer(b as i64),
+        Value::Float(f) => SqlValue::Real(f),
+        Value::String(s) => SqlValue::Text(s.to_string()),
+        Value::File(f) => SqlValue::Text(f.to_string_lossy().to_string()),
+        Value::Time(t) => SqlValue::Text(t.to_rfc3339()),
+        Value::Duration(d) => SqlValue::Real(duration_seconds(&d)),
+        Value::Binary(b) => SqlValue::Blob(b),
+        Value::Field(_) | Value::Glob(_) | Value::Regex(_, _) | Value::Type(_) => SqlValue::Text(value.to_string()),
+        value => {
+            /* Nested values are stored as JSON. */
+            let mut json = Vec::new();
+            write_json(value, &mut json, false)?;
+            SqlValue::Text(String::from_utf8_lossy(&json).to_string())
+        }
+    })
+}
+
+fn sql_type(value_type: &ValueType) -> &'static str {
+    match value_type {
+        ValueType::Integer => "INTEGER",
+        ValueType::Float | ValueType::Duration => "REAL",
+        ValueType::Bool => "BOOLEAN",
+        ValueType::Binary | ValueType::BinaryStream => "BLOB",
+        ValueType::Time => "TIMESTAMP",
+        ValueType::Any | ValueType::Empty => "",
+        _ => "TEXT",
+    }
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn prepare<'a>(conn: &'a Connection, sql: &str) -> CrushResult<Statement<'a>> {
+    conn.prepare(sql).or_else(sql_error)
+}
+
+fn columns(statement: &Statement) -> Vec<ColumnType> {
+    statement.columns().iter()
+        .map(|c| ColumnType::new(c.name(), column_type(c.decl_type())))
+        .collect()
+}
+
+/**
+    Run a query, calling send for every row until it returns false.
+*/
+fn query_rows(
+    statement: &mut Statement,
+    columns: &[ColumnType],
+    params: Vec<Value>,
+    mut send: impl FnMut(Row) -> bool,
+) -> CrushResult<()> {
+    let params = params.into_iter().map(sql_value).collect::<CrushResult<Vec<_>>>()?;
+    let mut rows = statement.query(rusqlite::params_from_iter(params)).or_else(sql_error)?;
+    while let Some(row) = rows.next().or_else(sql_error)? {
+        let cells = columns.iter().enumerate()
+            .map(|(idx, column)| Ok(cell(column, row.get_ref(idx).or_else(sql_error)?)))
+            .collect::<CrushResult<Vec<_>>>()?;
+        if !send(Row::new(cells)) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/**
+    Run a query and send the result to the output. Since any column can contain null and
+    values of other types than the declared one, the whole result is read first, and a
+    column only keeps its declared type if all of its cells have that type. Other columns
+    are of type any, unless the types are specified by the caller.
+*/
+fn query_command(
+    context: ExecutionContext,
+    read_only: bool,
+    sql: Option<&str>,
+    types: &[ValueType],
+) -> CrushResult<()> {
+    let cfg = parse(context.arguments)?;
+    let sql = match sql {
+        Some(_) if cfg.sql.is_some() || !cfg.params.is_empty() => return argument_error("Unknown argument"),
+        Some(sql) => sql.to_string(),
+        None => mandate(cfg.sql, "Missing sql statement")?.to_string(),
+    };
+    let conn = open(&cfg.db, read_only)?;
+    let mut statement = prepare(&conn, &sql)?;
+    let mut columns = columns(&statement);
+    for (column, cell_type) in columns.iter_mut().zip(types.iter()) {
+        column.cell_type = cell_type.clone();
+    }
+    let mut rows = Vec::new();
+    query_rows(&mut statement, &columns, cfg.params, |row| {
+        rows.push(row);
+        true
+    })?;
+    let output = context.output.initialize(
+        columns.iter().enumerate()
+            .map(|(idx, c)| match types.get(idx) {
+                Some(cell_type) => ColumnType::new(&c.name, cell_type.clone()),
+                None => ColumnType::new(&c.name, result_type(c, idx, &rows)),
+            })
+            .collect())?;
+    for row in rows {
+        if output.send(row).is_err() {
+            /* Whoever is reading our output has stopped listening. */
+            break;
+        }
+    }
+    Ok(())
+}
+
+/**
+    The declared type of a column if every cell of the result was converted to it, any
+    otherwise.
+*/
+fn result_type(column: &ColumnType, idx: usize, rows: &[Row]) -> ValueType {
+    if rows.iter().all(|row| column.cell_type.is(&row.cells()[idx])) {
+        column.cell_type.clone()
+    } else {
+        ValueType::Any
+    }
+}
+
+fn query(context: ExecutionContext) -> CrushResult<()> {
+    query_command(context, true, None, &[])
+}
+
+fn exec(context: ExecutionContext) -> CrushResult<()> {
+    let cfg = parse(context.arguments)?;
+    let sql = mandate(cfg.sql, "Missing sql statement")?;
+    let conn = open(&cfg.db, false)?;
+    if cfg.params.is_empty() {
+        conn.execute_batch(&sql).or_else(sql_error)?;
+    } else {
+        let params = cfg.params.into_iter().map(sql_value).collect::<CrushResult<Vec<_>>>()?;
+        conn.execute(&sql, rusqlite::params_from_iter(params)).or_else(sql_error)?;
+    }
+    context.output.send(Value::Integer(conn.changes() as i128))
+}
+
+fn tables(context: ExecutionContext) -> CrushResult<()> {
+    query_command(context, true, Some(
+        "SELECT name, type FROM sqlite_master \
+         WHERE type IN ('table', 'view') AND name NOT LIKE 'sqlite_%' ORDER BY name"),
+        &[ValueType::String, ValueType::String])
+}
+
+fn schema(context: ExecutionContext) -> CrushResult<()> {
+    query_command(context, true, Some(
+        "SELECT m.name AS \"table\", p.name AS name, p.type AS type, \
+         p.\"notnull\" AS not_null, p.dflt_value AS \"default\", p.pk > 0 AS primary_key \
+         FROM sqlite_master m JOIN pragma_table_info(m.name) p \
+         WHERE m.type IN ('table', 'view') AND m.name NOT LIKE 'sqlite_%' \
+         ORDER BY m.name, p.cid"),
+        &[ValueType::String, ValueType::String, ValueType::String, ValueType::Bool, ValueType::Any, ValueType::Bool])
+}
+
+/**
+    Create the table if it doesn't exist and insert the rows in a single transaction.
+*/
+fn insert_rows(
+    conn: &mut Connection,
+    table: &str,
+    columns: &[ColumnType],
+    mut next: impl FnMut() -> Option<Row>,
+) -> CrushResult<usize> {
+    let definitions = columns.iter()
+        .map(|c| format!("{} {}", quote(&c.name), sql_type(&c.cell_type)).trim().to_string())
+        .collect::<Vec<_>>();
+    let transaction = conn.transaction().or_else(sql_error)?;
+    transaction.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} ({})", quote(table), definitions.join(", ")))
+        .or_else(sql_error)?;
+    let mut count = 0;
+    {
+        let mut insert = prepare(&transaction, &format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            quote(table),
+            columns.iter().map(|c| quote(&c.name)).collect::<Vec<_>>().join(", "),
+            columns.iter().map(|_| "?").collect::<Vec<_>>().join(", ")))?;
+        while let Some(row) = next() {
+            let params = row.into_vec().into_iter().map(sql_value).collect::<CrushResult<Vec<_>>>()?;
+            insert.execute(rusqlite::params_from_iter(params)).or_else(sql_error)?;
+            count += 1;
+        }
+    }
+    transaction.commit().or_else(sql_error)?;
+    Ok(count)
+}
+
+pub fn to_sqlite(context: ExecutionContext) -> CrushResult<()> {
+    let cfg = parse(context.arguments)?;
+    if cfg.sql.is_some() || !cfg.params.is_empty() {
+        return argument_error("Unknown argument");
+    }
+    let table = mandate(cfg.table, "Missing table name")?;
+    let mut rows = match context.input.recv()?.readable() {
+        Some(rows) => rows,
+        None => return argument_error("Expected a table stream as input"),
+    };
+    let columns = rows.types().clone();
+    let mut conn = open(&cfg.db, false)?;
+    let count = insert_rows(&mut conn, &table, &columns, || rows.read().ok())?;
+    context.output.send(Value::Integer(count as i128))
+}
+
+pub fn declare(root: &Scope) -> CrushResult<()> {
+    let env = root.create_namespace("sqlite")?;
+    env.declare("query", Value::Command(CrushCommand::command(
+        query, true,
+        "sqlite:query db:file sql:string @params:any",
+        "Run an sql query and return the result as a table stream",
+        Some(r#"    The database is opened read only. Any arguments after the query are
+    bound to the ? parameters of the query, in order.
+
+    Cells are converted to the declared type of their column, using the same
+    rules SQLite uses for column affinity, where that can be done without
+    losing information. Columns declared as BOOLEAN become bools and columns
+    declared as DATE, DATETIME or TIMESTAMP become times. Since SQLite lets any
+    column contain null and values of other types, cells that can't be
+    converted keep their own type. Columns where that happens, or that contain
+    null, are of type any. Because of this, the whole result is read before
+    any of it is returned.
+
+    Examples:
+
+    sqlite:query /tmp/places.sqlite "select url, visit_count from moz_places where visit_count > ?" 10"#))))?;
+    env.declare("exec", Value::Command(CrushCommand::command(
+        exec, true,
+        "sqlite:exec db:file sql:string @params:any",
+        "Run sql statements that modify a database",
+        Some(r#"    The database is created if it does not exist. Without parameters, the
+    sql can contain multiple statements separated by semicolons. Returns the
+    number of rows changed by the last statement.
+
+    Examples:
+
+    sqlite:exec /tmp/state.db "delete from jobs where finished < ?" (time:now)"#))))?;
+    env.declare("tables", Value::Command(CrushCommand::command(
+        tables, true,
+        "sqlite:tables db:file",
+        "List the tables and views of a database",
+        None)))?;
+    env.declare("schema", Value::Command(CrushCommand::command(
+        schema, true,
+        "sqlite:schema db:file",
+        "List the columns of all tables and views of a database",
+        Some(r#"    Examples:
+
+    sqlite:schema /tmp/state.db | where {table == "jobs"}"#))))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::list::List;
+
+    fn all_rows(conn: &Connection, sql: &str, params: Vec<Value>) -> (Vec<ColumnType>, Vec<Row>) {
+        let mut statement = conn.prepare(sql).unwrap();
+        let columns = columns(&statement);
+        let mut rows = Vec::new();
+        query_rows(&mut statement, &columns, params, |row| {
+            rows.push(row);
+            true
+        }).unwrap();
+        (columns, rows)
+    }
+
+    #[test]
+    fn tables_survive_a_round_trip() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let columns = vec![
+            ColumnType::new("name", ValueType::String),
+            ColumnType::new("size", ValueType::Integer),
+            ColumnType::new("ratio", ValueType::Float),
+            ColumnType::new("done", ValueType::Bool),
+            ColumnType::new("modified", ValueType::Time),
+            ColumnType::new("tags", ValueType::List(Box::from(ValueType::String))),
+        ];
+        let now = Local.timestamp_opt(1_600_000_000, 0).unwrap();
+        let mut input = vec![
+            Row::new(vec![
+                Value::string("a"), Value::Integer(1), Value::Float(0.5), Value::Bool(true),
+                Value::Time(now), Value::List(List::new(ValueType::String, vec![Value::string("x")])),
+            ]),
+            Row::new(vec![
+                Value::string("b"), Value::Empty(), Value::Float(1.0), Value::Bool(false),
+                Value::Time(now), Value::Empty(),
+            ]),
+        ].into_iter();
+        assert_eq!(insert_rows(&mut conn, "my table", &columns, || input.next()).unwrap(), 2);
+
+        let (types, rows) = all_rows(&conn, "SELECT * FROM \"my table\" WHERE ratio < ?", vec![Value::Integer(1)]);
+        let types = types.into_iter().map(|c| c.cell_type).collect::<Vec<_>>();
+        assert!(types == vec![
+            ValueType::String, ValueType::Integer, ValueType::Float, ValueType::Bool,
+            ValueType::Time, ValueType::String,
+        ]);
+        assert_eq!(rows.len(), 1);
+        let cells = rows[0].cells();
+        assert!(cells[0] == Value::string("a"));
+        assert!(cells[1] == Value::Integer(1));
+        assert!(cells[3] == Value::Bool(true));
+        assert!(cells[4] == Value::Time(now));
+        assert!(cells[5] == Value::string("[\"x\"]"));
+    }
+
+    #[test]
+    fn cells_are_converted_to_the_column_type() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("
+            CREATE TABLE t (i INTEGER, d DATETIME);
+            INSERT INTO t VALUES (2.0, '2020-01-02 03:04:05');
+            INSERT INTO t VALUES ('x', NULL);
+            INSERT INTO t VALUES (1, 9223372036854775807);").unwrap();
+        let mut statement = conn.prepare("SELECT i, d, i * 2 FROM t").unwrap();
+        let columns = columns(&statement);
+        assert!(columns[2].cell_type == ValueType::Any);
+        let mut rows = statement.query([]).unwrap();
+        let row = rows.next().unwrap().unwrap();
+        assert!(cell(&columns[0], row.get_ref(0).unwrap()) == Value::Integer(2));
+        assert!(cell(&columns[1], row.get_ref(1).unwrap()) ==
+            Value::Time(Utc.with_ymd_and_hms(2020, 1, 2, 3, 4, 5).unwrap().with_timezone(&Local)));
+        /* Values that can't be converted keep their own type. */
+        let row = rows.next().unwrap().unwrap();
+        assert!(cell(&columns[0], row.get_ref(0).unwrap()) == Value::string("x"));
+        assert!(cell(&columns[1], row.get_ref(1).unwrap()).value_type() == ValueType::Empty);
+        let row = rows.next().unwrap().unwrap();
+        assert!(cell(&columns[1], row.get_ref(1).unwrap()) == Value::Integer(i64::MAX as i128));
+    }
+
+    #[test]
+    fn columns_keep_their_type_if_all_cells_converted() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("
+            CREATE TABLE t (i INTEGER, b BOOLEAN, n INTEGER);
+            INSERT INTO t VALUES (1, 0, NULL);
+            INSERT INTO t VALUES ('2', 2, 3);").unwrap();
+        let (columns, rows) = all_rows(&conn, "SELECT * FROM t", vec![]);
+        assert!(result_type(&columns[0], 0, &rows) == ValueType::Integer);
+        /* Only 0 and 1 are bools, other integers keep their value. */
+        assert!(rows[0].cells()[1] == Value::Bool(false));
+        assert!(rows[1].cells()[1] == Value::Integer(2));
+        assert!(result_type(&columns[1], 1, &rows) == ValueType::Any);
+        assert!(result_type(&columns[2], 2, &rows) == ValueType::Any);
+    }
+}
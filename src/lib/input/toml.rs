@@ -0,0 +1,45 @@
+use std::io::Read;
+
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{argument_error, to_crush_error, CrushResult};
+use crate::lang::{binary::BinaryReader, value::Value};
+use super::convert::{input_reader, Serializable};
+use super::json::convert_json;
+
+/**
+    Convert to a JSON value, so that the conversion to crush values can be shared with the
+    json command. Dates and times are converted to strings.
+*/
+fn to_json(value: toml::Value) -> serde_json::Value {
+    match value {
+        toml::Value::String(s) => serde_json::Value::String(s),
+        toml::Value::Integer(i) => serde_json::Value::from(i),
+        toml::Value::Float(f) => serde_json::Number::from_f64(f)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        toml::Value::Boolean(b) => serde_json::Value::Bool(b),
+        toml::Value::Datetime(d) => serde_json::Value::String(d.to_string()),
+        toml::Value::Array(a) => serde_json::Value::Array(a.into_iter().map(to_json).collect()),
+        toml::Value::Table(t) => serde_json::Value::Object(
+            t.into_iter().map(|(k, v)| (k, to_json(v))).collect()),
+    }
+}
+
+pub fn perform(context: ExecutionContext) -> CrushResult<()> {
+    let mut reader = input_reader(context.arguments, context.input)?;
+    let mut content = String::new();
+    to_crush_error(reader.read_to_string(&mut content))?;
+    let value = to_crush_error(toml::from_str::<toml::Value>(&content))?;
+    context.output.send(convert_json(&to_json(value))?)
+}
+
+pub fn to_toml(context: ExecutionContext) -> CrushResult<()> {
+    if !context.arguments.is_empty() {
+        return argument_error("to_toml does not take any arguments");
+    }
+    let value = context.input.recv()?;
+    /* Going through toml::Value makes sure plain values are written before tables. */
+    let toml_value = to_crush_error(toml::Value::try_from(Serializable(&value)))?;
+    let res = to_crush_error(toml::to_string(&toml_value))?;
+    context.output.send(Value::BinaryStream(BinaryReader::vec(&res.into_bytes())))
+}
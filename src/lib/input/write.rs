@@ -0,0 +1,134 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{argument_error, mandate, to_crush_error, CrushResult};
+use crate::lang::{argument::Argument, value::Value};
+use super::json::write_json;
+
+enum Format {
+    /** Binary data is written as is, everything else as text with one row per line. */
+    Raw,
+    /** Pretty printed JSON, as produced by to_json. */
+    Json,
+}
+
+struct Config {
+    file: Box<Path>,
+    append: bool,
+    atomic: bool,
+    format: Format,
+}
+
+fn parse(arguments: Vec<Argument>) -> CrushResult<Config> {
+    let mut file = None;
+    let mut append = false;
+    let mut atomic = false;
+    let mut format = Format::Raw;
+    for arg in arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, Value::File(f)) | (Some("file"), Value::File(f)) => file = Some(f),
+            (None, Value::String(s)) | (Some("file"), Value::String(s)) =>
+                file = Some(Box::from(Path::new(s.as_ref()))),
+            (Some("append"), Value::Bool(b)) => append = b,
+            (Some("atomic"), Value::Bool(b)) => atomic = b,
+            (Some("format"), Value::String(s)) => format = match s.as_ref() {
+                "raw" => Format::Raw,
+                "json" => Format::Json,
+                f => return argument_error(format!("Unknown format {}", f).as_str()),
+            },
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+    Ok(Config {
+        file: mandate(file, "Missing file to write to")?,
+        append,
+        atomic,
+        format,
+    })
+}
+
+fn write_raw(value: Value, out: &mut dyn Write) -> CrushResult<()> {
+    match value {
+        Value::BinaryStream(mut b) => to_crush_error(std::io::copy(&mut b, out)).map(|_| ()),
+        Value::Binary(b) => to_crush_error(out.write_all(&b)),
+        Value::String(s) => to_crush_error(out.write_all(s.as_bytes())),
+        value => match value.readable() {
+            Some(mut rows) => {
+                while let Ok(row) = rows.read() {
+                    let line = row.cells().iter()
+                        .map(|c| c.to_string())
+                        .collect::<Vec<_>>()
+                        .join("\t");
+                    to_crush_error(writeln!(out, "{}", line))?;
+                }
+                Ok(())
+            }
+            None => to_crush_error(writeln!(out, "{}", value.to_string())),
+        }
+    }
+}
+
+/**
+    A temporary file next to the destination, so that it can be renamed into place.
+*/
+fn temporary_file(file: &Path) -> CrushResult<PathBuf> {
+    let name = mandate(file.file_name(), "Invalid file name")?;
+    Ok(file.with_file_name(
+        format!(".{}.crush-tmp-{}", name.to_string_lossy(), std::process::id())))
+}
+
+/**
+    Make sure the data is on disk before the temporary file replaces the destination, and
+    that the rename itself is on disk before we return.
+*/
+fn commit(out: BufWriter<File>, target: &Path, file: &Path) -> CrushResult<()> {
+    let tmp = to_crush_error(out.into_inner())?;
+    if let Ok(meta) = fs::metadata(file) {
+        to_crush_error(tmp.set_permissions(meta.permissions()))?;
+    }
+    to_crush_error(tmp.sync_all())?;
+    to_crush_error(fs::rename(target, file))?;
+    let dir = match file.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    to_crush_error(File::open(dir).and_then(|d| d.sync_all()))
+}
+
+pub fn perform(context: ExecutionContext) -> CrushResult<()> {
+    let cfg = parse(context.arguments)?;
+    let value = context.input.recv()?;
+
+    let target = if cfg.atomic { temporary_file(&cfg.file)? } else { cfg.file.to_path_buf() };
+    if cfg.atomic && cfg.append && cfg.file.exists() {
+        to_crush_error(fs::copy(&cfg.file, &target))?;
+    }
+    let file: File = to_crush_error(
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(cfg.append)
+            .truncate(!cfg.append)
+            .open(&target))?;
+    let mut out = BufWriter::new(file);
+
+    let res = match cfg.format {
+        Format::Raw => write_raw(value, &mut out),
+        Format::Json => write_json(value, &mut out, true)
+            .and_then(|_| to_crush_error(writeln!(out))),
+    }.and_then(|_| to_crush_error(out.flush()));
+
+    if cfg.atomic {
+        match res.and_then(|_| commit(out, &target, &cfg.file)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&target);
+                Err(e)
+            }
+        }
+    } else {
+        res
+    }
+}
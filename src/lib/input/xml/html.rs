@@ -0,0 +1,262 @@
+use super::{Node, TreeBuilder};
+
+/** Elements that never have any content or end tag. */
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param",
+    "source", "track", "wbr",
+];
+
+/** Elements whose content is not parsed as HTML. */
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+/** Elements that implicitly close an open paragraph. */
+const CLOSES_PARAGRAPH: &[&str] = &[
+    "address", "article", "aside", "blockquote", "div", "dl", "fieldset", "footer", "form",
+    "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "main", "nav", "ol", "p", "pre",
+    "section", "table", "ul",
+];
+
+fn decode_entity(name: &str) -> Option<char> {
+    if name.starts_with("#x") || name.starts_with("#X") {
+        return u32::from_str_radix(&name[2..], 16).ok().and_then(std::char::from_u32);
+    }
+    if name.starts_with('#') {
+        return name[1..].parse().ok().and_then(std::char::from_u32);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "laquo" => '«',
+        "raquo" => '»',
+        "middot" => '·',
+        _ => return None,
+    })
+}
+
+/**
+    Replace character references. Unknown references are kept as they are.
+*/
+fn decode_text(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..].find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end + 1]).map(|c| (c, end + 2)));
+        match decoded {
+            Some((c, len)) => {
+                res.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                res.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+struct Tokenizer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /**
+        Consume everything up to and including the specified string, or the rest of the
+        input if it does not occur. Returns the consumed text, not including the terminator.
+    */
+    fn until(&mut self, terminator: &str) -> &'a str {
+        let rest = self.rest();
+        match rest.find(terminator) {
+            Some(idx) => {
+                self.pos += idx + terminator.len();
+                &rest[..idx]
+            }
+            None => {
+                self.pos = self.input.len();
+                rest
+            }
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let rest = self.rest();
+        let len = rest.find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=')
+            .unwrap_or_else(|| rest.len());
+        self.pos += len;
+        rest[..len].to_lowercase()
+    }
+
+    fn attribute_value(&mut self) -> String {
+        let rest = self.rest();
+        match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                self.pos += 1;
+                decode_text(self.until(&quote.to_string()))
+            }
+            _ => {
+                let len = rest.find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or_else(|| rest.len());
+                self.pos += len;
+                decode_text(&rest[..len])
+            }
+        }
+    }
+
+    /**
+        Parse the attributes of a start tag and the end of the tag. Returns the attributes
+        and whether the tag was self-closing.
+    */
+    fn attributes(&mut self) -> (Vec<(String, String)>, bool) {
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.is_empty() {
+                return (attributes, false);
+            } else if rest.starts_with("/>") {
+                self.pos += 2;
+                return (attributes, true);
+            } else if rest.starts_with('>') {
+                self.pos += 1;
+                return (attributes, false);
+            } else if rest.starts_with('/') || rest.starts_with('=') {
+                self.pos += 1;
+                continue;
+            }
+            let name = self.name();
+            self.skip_whitespace();
+            let value = if self.rest().starts_with('=') {
+                self.pos += 1;
+                self.skip_whitespace();
+                self.attribute_value()
+            } else {
+                String::new()
+            };
+            if !attributes.iter().any(|(n, _)| n == &name) {
+                attributes.push((name, value));
+            }
+        }
+    }
+}
+
+fn start_element(builder: &mut TreeBuilder, tag: &str, attributes: Vec<(String, String)>) {
+    let implicitly_closed: &[&str] = match tag {
+        "li" => &["li"],
+        "dt" | "dd" => &["dt", "dd"],
+        "tr" => &["tr", "td", "th"],
+        "td" | "th" => &["td", "th"],
+        "option" => &["option"],
+        t if CLOSES_PARAGRAPH.contains(&t) => &["p"],
+        _ => &[],
+    };
+    while let Some(current) = builder.current_tag() {
+        if !implicitly_closed.contains(&current) {
+            break;
+        }
+        let current = current.to_string();
+        builder.end(&current);
+    }
+    builder.start(tag, attributes);
+}
+
+/**
+    A lenient HTML parser. It never fails: unknown constructs are treated as text, end tags
+    without a matching start tag are ignored and elements that are never closed are closed
+    at the end of the document.
+*/
+pub fn parse(input: &str) -> Node {
+    let mut builder = TreeBuilder::new();
+    let mut tokenizer = Tokenizer { input, pos: 0 };
+    while !tokenizer.rest().is_empty() {
+        let rest = tokenizer.rest();
+        if rest.starts_with("<!--") {
+            tokenizer.pos += 4;
+            tokenizer.until("-->");
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            tokenizer.until(">");
+        } else if rest.starts_with("</") && rest[2..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            tokenizer.pos += 2;
+            let tag = tokenizer.name();
+            tokenizer.until(">");
+            builder.end(&tag);
+        } else if rest.starts_with('<') && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            tokenizer.pos += 1;
+            let tag = tokenizer.name();
+            let (attributes, self_closing) = tokenizer.attributes();
+            start_element(&mut builder, &tag, attributes);
+            if self_closing || VOID_ELEMENTS.contains(&tag.as_str()) {
+                builder.end(&tag);
+            } else if RAW_TEXT_ELEMENTS.contains(&tag.as_str()) {
+                let content = tokenizer.until(&format!("</{}", tag));
+                builder.text(&decode_text(content));
+                tokenizer.until(">");
+                builder.end(&tag);
+            }
+        } else {
+            /* A lone < that does not start a tag is just text. */
+            let len = rest[1..].find('<').map(|idx| idx + 1).unwrap_or_else(|| rest.len());
+            tokenizer.pos += len;
+            builder.text(&decode_text(&rest[..len]));
+        }
+    }
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elements(node: &Node) -> Vec<&Node> {
+        node.children.iter().filter(|c| c.is_element()).collect()
+    }
+
+    #[test]
+    fn sloppy_html_is_parsed() {
+        let doc = parse(r#"<!DOCTYPE html>
+<HTML><body class=main>
+<p>One<p>Two &amp; <b>three</b>
+<ul><li>a<li>b</ul>
+<img src="x.png"><br/>
+<script>if (a < b) {}</script>
+</div></body></html>"#);
+        assert_eq!(doc.tag, "html");
+        let body = elements(&doc)[0];
+        assert_eq!(body.attribute("class"), Some("main"));
+        let children = elements(body);
+        let tags = children.iter().map(|c| c.tag.as_str()).collect::<Vec<_>>();
+        assert_eq!(tags, vec!["p", "p", "ul", "img", "br", "script"]);
+        assert_eq!(children[1].text, "Two & three");
+        assert_eq!(elements(children[2]).len(), 2);
+        assert_eq!(children[5].text, "if (a < b) {}");
+    }
+
+    #[test]
+    fn unknown_entities_are_kept() {
+        assert_eq!(decode_text("a &lt; b &unknown; &#65;&#x42; & c"), "a < b &unknown; AB & c");
+    }
+}
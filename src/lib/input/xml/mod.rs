@@ -0,0 +1,320 @@
+use std::io::{BufReader, Read};
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use crate::lang::execution_context::{ArgumentVector, ExecutionContext};
+use crate::lang::errors::{error, mandate, to_crush_error, CrushResult};
+use crate::lang::{dict::Dict, r#struct::Struct, table::ColumnType, table::Row, table::Table};
+use crate::lang::value::{Value, ValueType};
+use super::convert::input_reader;
+
+mod html;
+mod path;
+
+pub const TEXT_TAG: &str = "#text";
+
+/**
+    An element or a piece of text in a parsed document. Text nodes have the tag #text.
+*/
+#[derive(Debug, Clone)]
+pub struct Node {
+    tag: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+    /** For elements, all text inside the element with leading and trailing whitespace removed. */
+    text: String,
+}
+
+impl Node {
+    fn element(tag: &str, attributes: Vec<(String, String)>) -> Node {
+        Node { tag: tag.to_string(), attributes, children: Vec::new(), text: String::new() }
+    }
+
+    fn is_element(&self) -> bool {
+        self.tag != TEXT_TAG
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/**
+    Builds a tree of nodes from a sequence of start tags, end tags and text.
+*/
+pub struct TreeBuilder {
+    /** The open elements, the first one is the document itself. */
+    stack: Vec<Node>,
+}
+
+impl TreeBuilder {
+    pub fn new() -> TreeBuilder {
+        TreeBuilder { stack: vec![Node::element("#document", Vec::new())] }
+    }
+
+    pub fn start(&mut self, tag: &str, attributes: Vec<(String, String)>) {
+        self.stack.push(Node::element(tag, attributes));
+    }
+
+    fn close_top(&mut self) {
+        let mut node = self.stack.pop().unwrap();
+        node.text = node.text.trim().to_string();
+        self.stack.last_mut().unwrap().children.push(node);
+    }
+
+    pub fn current_tag(&self) -> Option<&str> {
+        match self.stack.len() {
+            1 => None,
+            _ => self.stack.last().map(|n| n.tag.as_str()),
+        }
+    }
+
+    /**
+        Close the innermost open element with the specified tag, along with any elements
+        opened inside of it. Returns false if there is no such element.
+    */
+    pub fn end(&mut self, tag: &str) -> bool {
+        match self.stack.iter().skip(1).rposition(|n| n.tag == tag) {
+            Some(idx) => {
+                while self.stack.len() > idx + 1 {
+                    self.close_top();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn text(&mut self, text: &str) {
+        for node in self.stack.iter_mut() {
+            node.text.push_str(text);
+        }
+        if !text.trim().is_empty() {
+            let parent = self.stack.last_mut().unwrap();
+            parent.children.push(Node {
+                tag: TEXT_TAG.to_string(),
+                attributes: Vec::new(),
+                children: Vec::new(),
+                text: text.to_string(),
+            });
+        }
+    }
+
+    pub fn open_elements(&self) -> usize {
+        self.stack.len() - 1
+    }
+
+    /**
+        Close all open elements. If the document has a single root element, that element is
+        returned, otherwise a node with the tag #document containing all top level nodes.
+    */
+    pub fn finish(mut self) -> Node {
+        while self.stack.len() > 1 {
+            self.close_top();
+        }
+        let mut document = self.stack.pop().unwrap();
+        document.text = document.text.trim().to_string();
+        if document.children.len() == 1 && document.children[0].is_element() {
+            document.children.remove(0)
+        } else {
+            document
+        }
+    }
+}
+
+pub fn node_columns() -> Vec<ColumnType> {
+    vec![
+        ColumnType::new("tag", ValueType::String),
+        ColumnType::new("attributes", ValueType::Dict(Box::from(ValueType::String), Box::from(ValueType::String))),
+        /* The type of the children table depends on how deeply nested it is. */
+        ColumnType::new("children", ValueType::Any),
+        ColumnType::new("text", ValueType::String),
+    ]
+}
+
+fn node_cells(node: &Node) -> CrushResult<Vec<Value>> {
+    let attributes = Dict::new(ValueType::String, ValueType::String);
+    for (key, value) in &node.attributes {
+        attributes.insert(Value::string(key), Value::string(value))?;
+    }
+    let children = node.children.iter()
+        .map(|child| Ok(Row::new(node_cells(child)?)))
+        .collect::<CrushResult<Vec<_>>>()?;
+    Ok(vec![
+        Value::string(&node.tag),
+        Value::Dict(attributes),
+        Value::Table(Table::new(node_columns(), children)),
+        Value::string(&node.text),
+    ])
+}
+
+pub fn node_row(node: &Node) -> CrushResult<Row> {
+    Ok(Row::new(node_cells(node)?))
+}
+
+pub fn node_value(node: &Node) -> CrushResult<Value> {
+    let names = node_columns().into_iter().map(|c| c.name);
+    Ok(Value::Struct(Struct::new(names.zip(node_cells(node)?).collect(), None)))
+}
+
+fn string_field(value: Option<Value>, name: &str) -> CrushResult<String> {
+    match value {
+        Some(Value::String(s)) => Ok(s.to_string()),
+        Some(Value::Empty()) | None => Ok(String::new()),
+        Some(_) => error(format!("Expected the {} field of a node to be a string", name).as_str()),
+    }
+}
+
+fn node_from_fields(field: impl Fn(&str) -> Option<Value>) -> CrushResult<Node> {
+    let tag = string_field(field("tag"), "tag")?;
+    if tag.is_empty() {
+        return error("Expected a node with a tag");
+    }
+    let attributes = match field("attributes") {
+        Some(Value::Dict(d)) => d.elements().into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let mut children = Vec::new();
+    if let Some(value) = field("children") {
+        nodes_from_value(value, &mut children)?;
+    }
+    Ok(Node { tag, attributes, children, text: string_field(field("text"), "text")? })
+}
+
+/**
+    Convert a value created by the xml or html commands, or a table of matches returned by
+    select_path, back into nodes.
+*/
+pub fn nodes_from_value(value: Value, res: &mut Vec<Node>) -> CrushResult<()> {
+    match value {
+        Value::Struct(s) => {
+            res.push(node_from_fields(|name| s.get(name))?);
+            Ok(())
+        }
+        Value::Empty() => Ok(()),
+        value => {
+            let mut rows = mandate(value.readable(), "Expected a document or a table of nodes")?;
+            let names = rows.types().iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+            while let Ok(row) = rows.read() {
+                let cells = row.into_vec();
+                res.push(node_from_fields(|name| names.iter()
+                    .position(|n| n.as_ref() == name)
+                    .map(|idx| cells[idx].clone()))?);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn parse_xml(input: impl Read) -> CrushResult<Node> {
+    let mut reader = Reader::from_reader(BufReader::new(input));
+    reader.trim_text(false);
+    reader.check_end_names(true);
+    let mut builder = TreeBuilder::new();
+    let mut buf = Vec::new();
+    loop {
+        let event = match reader.read_event(&mut buf) {
+            Ok(event) => event,
+            Err(e) => return error(format!("Error at position {}: {}", reader.buffer_position(), e).as_str()),
+        };
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let tag = String::from_utf8_lossy(e.name()).to_string();
+                let mut attributes = Vec::new();
+                for attr in e.attributes() {
+                    let attr = to_crush_error(attr)?;
+                    attributes.push((
+                        String::from_utf8_lossy(attr.key).to_string(),
+                        to_crush_error(attr.unescape_and_decode_value(&reader))?));
+                }
+                builder.start(&tag, attributes);
+                if let Event::Empty(_) = event {
+                    builder.end(&tag);
+                }
+            }
+            Event::End(e) => {
+                builder.end(&String::from_utf8_lossy(e.name()));
+            }
+            Event::Text(e) => builder.text(&to_crush_error(e.unescape_and_decode(&reader))?),
+            Event::CData(e) => builder.text(&String::from_utf8_lossy(&e)),
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    if builder.open_elements() != 0 {
+        return error("Unexpected end of document, not all elements are closed");
+    }
+    Ok(builder.finish())
+}
+
+pub fn xml(context: ExecutionContext) -> CrushResult<()> {
+    let reader = input_reader(context.arguments, context.input)?;
+    context.output.send(node_value(&parse_xml(reader)?)?)
+}
+
+pub fn html(context: ExecutionContext) -> CrushResult<()> {
+    let mut reader = input_reader(context.arguments, context.input)?;
+    let mut data = Vec::new();
+    to_crush_error(reader.read_to_end(&mut data))?;
+    context.output.send(node_value(&html::parse(&String::from_utf8_lossy(&data)))?)
+}
+
+pub fn select_path(mut context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(1)?;
+    let query = path::Query::parse(&context.arguments.string(0)?)?;
+    let mut roots = Vec::new();
+    nodes_from_value(context.input.recv()?, &mut roots)?;
+    let output = context.output.initialize(node_columns())?;
+    for node in query.select(&roots) {
+        if output.send(node_row(node)?).is_err() {
+            /* Whoever is reading our output has stopped listening. */
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xml_is_parsed_into_nodes() {
+        let doc = parse_xml(r#"<?xml version="1.0"?>
+<project a="1 &amp; 2">
+  <name>crush</name>
+  <empty/>
+  <![CDATA[<raw>]]>
+</project>"#.as_bytes()).unwrap();
+        assert_eq!(doc.tag, "project");
+        assert_eq!(doc.attribute("a"), Some("1 & 2"));
+        let elements = doc.children.iter().filter(|c| c.is_element()).collect::<Vec<_>>();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].text, "crush");
+        assert_eq!(elements[1].tag, "empty");
+        assert_eq!(doc.text, "crush\n  \n  <raw>");
+    }
+
+    #[test]
+    fn malformed_xml_is_rejected() {
+        assert!(parse_xml("<a><b></a>".as_bytes()).is_err());
+        assert!(parse_xml("<a><b></b>".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn nodes_survive_conversion_to_values() {
+        let doc = parse_xml("<a x=\"y\"><b>text</b></a>".as_bytes()).unwrap();
+        let mut nodes = Vec::new();
+        nodes_from_value(node_value(&doc).unwrap(), &mut nodes).unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].attribute("x"), Some("y"));
+        assert_eq!(nodes[0].children[0].tag, "b");
+        assert_eq!(nodes[0].children[0].children[0].text, "text");
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashSet;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::lang::errors::{argument_error, CrushResult};
+use super::Node;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Axis {
+    Child,
+    Descendant,
+}
+
+#[derive(Debug, PartialEq)]
+enum Predicate {
+    HasAttribute(String),
+    AttributeEquals(String, String),
+    /** The attribute is a whitespace separated list of words, one of which is the value. */
+    AttributeContainsWord(String, String),
+    /** The nth match among the children of the same parent, counting from 1. */
+    Index(usize),
+}
+
+#[derive(Debug, PartialEq)]
+struct Step {
+    axis: Axis,
+    /** None matches any tag. */
+    tag: Option<String>,
+    predicates: Vec<Predicate>,
+}
+
+/**
+    A parsed query. Queries starting with a slash use a subset of XPath, e.g.
+    `//item[@id='3']/name` or `/feed/entry[1]`. All other queries are CSS selectors, e.g.
+    `div.note > a[href]`.
+*/
+#[derive(Debug)]
+pub struct Query {
+    steps: Vec<Step>,
+}
+
+fn invalid<T>(query: &str, message: &str) -> CrushResult<T> {
+    argument_error(format!("Invalid query {}: {}", query, message).as_str())
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' || c == ':' || c == '.'
+}
+
+/** Dots start a class in CSS selectors, so they can't be part of a name. */
+fn is_css_name_char(c: char) -> bool {
+    c != '.' && is_name_char(c)
+}
+
+fn read_while(chars: &mut Peekable<Chars>, accept: impl Fn(char) -> bool) -> String {
+    let mut res = String::new();
+    while let Some(c) = chars.peek() {
+        if !accept(*c) {
+            break;
+        }
+        res.push(*c);
+        chars.next();
+    }
+    res
+}
+
+/**
+    A value in a predicate, either quoted with single or double quotes or ending at the
+    closing bracket.
+*/
+fn read_value(chars: &mut Peekable<Chars>, query: &str) -> CrushResult<String> {
+    match chars.peek() {
+        Some(&quote) if quote == '\'' || quote == '"' => {
+            chars.next();
+            let value = read_while(chars, |c| c != quote);
+            match chars.next() {
+                Some(_) => Ok(value),
+                None => invalid(query, "unterminated string"),
+            }
+        }
+        _ => Ok(read_while(chars, |c| c != ']').trim().to_string()),
+    }
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char, query: &str) -> CrushResult<()> {
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        Some(c) => invalid(query, format!("expected '{}', got '{}'", expected, c).as_str()),
+        None => invalid(query, format!("expected '{}'", expected).as_str()),
+    }
+}
+
+fn tag_test(chars: &mut Peekable<Chars>, accept: impl Fn(char) -> bool) -> Option<String> {
+    if chars.peek() == Some(&'*') {
+        chars.next();
+        None
+    } else {
+        Some(read_while(chars, accept))
+    }
+}
+
+fn parse_xpath(query: &str) -> CrushResult<Vec<Step>> {
+    let mut chars = query.chars().peekable();
+    let mut steps = Vec::new();
+    while chars.next().is_some() {
+        let axis = if chars.peek() == Some(&'/') {
+            chars.next();
+            Axis::Descendant
+        } else {
+            Axis::Child
+        };
+        let tag = tag_test(&mut chars, is_name_char);
+        if tag.as_deref() == Some("") {
+            return invalid(query, "expected an element name or *");
+        }
+        let mut predicates = Vec::new();
+        while chars.peek() == Some(&'[') {
+            chars.next();
+            if chars.peek() == Some(&'@') {
+                chars.next();
+                let name = read_while(&mut chars, is_name_char);
+                if chars.peek() == Some(&'=') {
+                    chars.next();
+                    predicates.push(Predicate::AttributeEquals(name, read_value(&mut chars, query)?));
+                } else {
+                    predicates.push(Predicate::HasAttribute(name));
+                }
+            } else {
+                match read_while(&mut chars, |c| c.is_ascii_digit()).parse() {
+                    Ok(idx) if idx > 0 => predicates.push(Predicate::Index(idx)),
+                    _ => return invalid(query, "expected an attribute or a positive index"),
+                }
+            }
+            expect(&mut chars, ']', query)?;
+        }
+        match chars.peek() {
+            None | Some('/') => {}
+            Some(c) => return invalid(query, format!("unexpected '{}'", c).as_str()),
+        }
+        steps.push(Step { axis, tag, predicates });
+    }
+    Ok(steps)
+}
+
+fn parse_css(query: &str) -> CrushResult<Vec<Step>> {
+    let mut chars = query.chars().peekable();
+    let mut steps = Vec::new();
+    let mut axis = Axis::Descendant;
+    loop {
+        read_while(&mut chars, char::is_whitespace);
+        match chars.peek() {
+            None => break,
+            Some('>') => {
+                if steps.is_empty() || axis == Axis::Child {
+                    return invalid(query, "unexpected '>'");
+                }
+                chars.next();
+                axis = Axis::Child;
+                continue;
+            }
+            _ => {}
+        }
+        let tag = match tag_test(&mut chars, is_css_name_char) {
+            Some(tag) if tag.is_empty() => None,
+            tag => tag,
+        };
+        let mut predicates = Vec::new();
+        loop {
+            match chars.peek() {
+                Some('.') => {
+                    chars.next();
+                    predicates.push(Predicate::AttributeContainsWord(
+                        "class".to_string(), read_while(&mut chars, is_css_name_char)));
+                }
+                Some('#') => {
+                    chars.next();
+                    predicates.push(Predicate::AttributeEquals(
+                        "id".to_string(), read_while(&mut chars, is_css_name_char)));
+                }
+                Some('[') => {
+                    chars.next();
+                    let name = read_while(&mut chars, is_css_name_char);
+                    if chars.peek() == Some(&'=') {
+                        chars.next();
+                        predicates.push(Predicate::AttributeEquals(name, read_value(&mut chars, query)?));
+                    } else {
+                        predicates.push(Predicate::HasAttribute(name));
+                    }
+                    expect(&mut chars, ']', query)?;
+                }
+                None | Some(' ') | Some('\t') | Some('\n') | Some('>') => break,
+                Some(c) => return invalid(query, format!("unexpected '{}'", c).as_str()),
+            }
+        }
+        steps.push(Step { axis, tag, predicates });
+        axis = Axis::Descendant;
+    }
+    if axis == Axis::Child {
+        return invalid(query, "expected a selector after '>'");
+    }
+    Ok(steps)
+}
+
+impl Predicate {
+    fn matches(&self, node: &Node) -> bool {
+        match self {
+            Predicate::HasAttribute(name) => node.attribute(name).is_some(),
+            Predicate::AttributeEquals(name, value) => node.attribute(name) == Some(value.as_str()),
+            Predicate::AttributeContainsWord(name, word) => node.attribute(name)
+                .map(|value| value.split_whitespace().any(|w| w == word))
+                .unwrap_or(false),
+            Predicate::Index(_) => true,
+        }
+    }
+}
+
+impl Step {
+    /**
+        The nodes matching this step among the specified siblings.
+    */
+    fn apply<'a>(&self, siblings: &'a [Node]) -> Vec<&'a Node> {
+        let mut res = siblings.iter()
+            .filter(|n| n.is_element())
+            .filter(|n| self.tag.as_ref().map(|t| t == &n.tag).unwrap_or(true))
+            .collect::<Vec<_>>();
+        for predicate in &self.predicates {
+            res = match predicate {
+                Predicate::Index(idx) => res.get(idx - 1).map(|n| vec![*n]).unwrap_or_default(),
+                predicate => res.into_iter().filter(|n| predicate.matches(n)).collect(),
+            };
+        }
+        res
+    }
+}
+
+/**
+    The lists of siblings that a step with the specified axis looks at.
+*/
+fn sibling_groups<'a>(children: &'a [Node], axis: Axis, res: &mut Vec<&'a [Node]>) {
+    res.push(children);
+    if axis == Axis::Descendant {
+        for child in children.iter().filter(|c| c.is_element()) {
+            sibling_groups(&child.children, axis, res);
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(query: &str) -> CrushResult<Query> {
+        let query = query.trim();
+        let steps = if query.starts_with('/') {
+            parse_xpath(query)?
+        } else {
+            parse_css(query)?
+        };
+        if steps.is_empty() {
+            return invalid(query, "empty query");
+        }
+        Ok(Query { steps })
+    }
+
+    /**
+        Run the query, treating the specified nodes as the top level elements of a
+        document. Every matching element is returned once.
+    */
+    pub fn select<'a>(&self, roots: &'a [Node]) -> Vec<&'a Node> {
+        let mut contexts = vec![roots];
+        let mut matches = Vec::new();
+        for step in &self.steps {
+            let mut groups = Vec::new();
+            for context in contexts {
+                sibling_groups(context, step.axis, &mut groups);
+            }
+            let mut seen = HashSet::new();
+            matches = groups.into_iter()
+                .flat_map(|group| step.apply(group))
+                .filter(|n| seen.insert(*n as *const Node))
+                .collect::<Vec<_>>();
+            contexts = matches.iter().map(|n| n.children.as_slice()).collect();
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::html;
+
+    fn tags(query: &str, doc: &str) -> Vec<String> {
+        let doc = html::parse(doc);
+        Query::parse(query).unwrap()
+            .select(std::slice::from_ref(&doc))
+            .iter()
+            .map(|n| format!("{}:{}", n.tag, n.text))
+            .collect()
+    }
+
+    const DOC: &str = r#"<html><body>
+<div id="main" class="note wide"><a href="/a">first</a><p><a>second</a></p></div>
+<div><a href="/b">third</a></div>
+</body></html>"#;
+
+    #[test]
+    fn xpath_queries() {
+        assert_eq!(tags("//a", DOC), vec!["a:first", "a:second", "a:third"]);
+        assert_eq!(tags("/html/body/div/a", DOC), vec!["a:first", "a:third"]);
+        assert_eq!(tags("//a[@href]", DOC), vec!["a:first", "a:third"]);
+        assert_eq!(tags("//div[@id='main']//a[2]", DOC), Vec::<String>::new());
+        assert_eq!(tags("//div[2]/a", DOC), vec!["a:third"]);
+        assert_eq!(tags("//*[@href=\"/b\"]", DOC), vec!["a:third"]);
+    }
+
+    #[test]
+    fn css_queries() {
+        assert_eq!(tags("div.note > a", DOC), vec!["a:first"]);
+        assert_eq!(tags("#main a", DOC), vec!["a:first", "a:second"]);
+        assert_eq!(tags("body > div > p > a", DOC), vec!["a:second"]);
+        assert_eq!(tags("[href='/a']", DOC), vec!["a:first"]);
+        assert_eq!(tags(".wide", DOC).len(), 1);
+    }
+
+    #[test]
+    fn invalid_queries_are_rejected() {
+        assert!(Query::parse("").is_err());
+        assert!(Query::parse("//a[").is_err());
+        assert!(Query::parse("> a").is_err());
+        assert!(Query::parse("a >").is_err());
+        assert!(Query::parse("/a/[1]").is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+
+use serde::Deserialize;
+
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{argument_error, to_crush_error, CrushResult};
+use crate::lang::{binary::BinaryReader, list::List, value::Value, value::ValueType};
+use super::convert::{input_reader, Serializable};
+use super::json::convert_json;
+
+/**
+    JSON objects only have string keys, other keys are converted to their YAML
+    representation.
+*/
+fn key_string(key: serde_yaml::Value) -> String {
+    match key {
+        serde_yaml::Value::String(s) => s,
+        key => serde_yaml::to_string(&key)
+            .map(|s| s.trim_start_matches("---").trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/**
+    Convert to a JSON value, so that the conversion to crush values can be shared with the
+    json command.
+*/
+fn to_json(value: serde_yaml::Value) -> serde_json::Value {
+    match value {
+        serde_yaml::Value::Null => serde_json::Value::Null,
+        serde_yaml::Value::Bool(b) => serde_json::Value::Bool(b),
+        serde_yaml::Value::Number(n) =>
+            if let Some(i) = n.as_i64() {
+                serde_json::Value::from(i)
+            } else if let Some(u) = n.as_u64() {
+                serde_json::Value::from(u)
+            } else {
+                n.as_f64()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(serde_json::Value::Number)
+                    .unwrap_or(serde_json::Value::Null)
+            },
+        serde_yaml::Value::String(s) => serde_json::Value::String(s),
+        serde_yaml::Value::Sequence(s) => serde_json::Value::Array(s.into_iter().map(to_json).collect()),
+        serde_yaml::Value::Mapping(m) => serde_json::Value::Object(
+            m.into_iter().map(|(k, v)| (key_string(k), to_json(v))).collect()),
+    }
+}
+
+/**
+    A file can contain several documents separated by ---. A single document is returned
+    as is, several documents as a list.
+*/
+pub fn perform(context: ExecutionContext) -> CrushResult<()> {
+    let reader = input_reader(context.arguments, context.input)?;
+    let mut documents = serde_yaml::Deserializer::from_reader(reader)
+        .map(|document| {
+            let value = to_crush_error(serde_yaml::Value::deserialize(document))?;
+            convert_json(&to_json(value))
+        })
+        .collect::<CrushResult<Vec<_>>>()?;
+    let value = match documents.len() {
+        0 => Value::Empty(),
+        1 => documents.remove(0),
+        _ => {
+            let types = documents.iter().map(|d| d.value_type()).collect::<HashSet<_>>();
+            let list_type = match types.len() {
+                1 => types.into_iter().next().unwrap(),
+                _ => ValueType::Any,
+            };
+            Value::List(List::new(list_type, documents))
+        }
+    };
+    context.output.send(value)
+}
+
+pub fn to_yaml(context: ExecutionContext) -> CrushResult<()> {
+    if !context.arguments.is_empty() {
+        return argument_error("to_yaml does not take any arguments");
+    }
+    let value = context.input.recv()?;
+    let res = to_crush_error(serde_yaml::to_string(&Serializable(&value)))?;
+    context.output.send(Value::BinaryStream(BinaryReader::vec(&format!("{}\n", res).into_bytes())))
+}
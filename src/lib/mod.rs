@@ -1,6 +1,8 @@
 pub mod traversal;
 pub mod var;
 pub mod proc;
+pub mod sys;
+pub mod user;
 pub mod input;
 
 #[macro_use]
@@ -13,6 +15,7 @@ pub mod types;
 pub mod control;
 pub mod constants;
 pub mod math;
+pub mod protobuf;
 
 use crate::{lang::scope::Scope, lang::errors::CrushResult};
 
@@ -24,10 +27,13 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
     stream::declare(root)?;
     types::declare(root)?;
     proc::declare(root)?;
+    sys::declare(root)?;
+    user::declare(root)?;
     input::declare(root)?;
     control::declare(root)?;
     constants::declare(root)?;
     math::declare(root)?;
+    protobuf::declare(root)?;
     root.readonly();
     return Ok(());
 }
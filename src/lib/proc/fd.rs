@@ -0,0 +1,283 @@
+use crate::lang::execution_context::{ExecutionContext, ArgumentVector};
+use crate::lang::errors::{CrushResult, argument_error, error, to_crush_error};
+use crate::lang::{table::ColumnType, table::Row, value::Value, value::ValueType};
+use crate::lang::stream::OutputStream;
+use std::collections::HashMap;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const TCP_STATES: [&str; 12] = [
+    "UNKNOWN", "ESTABLISHED", "SYN_SENT", "SYN_RECV", "FIN_WAIT1", "FIN_WAIT2",
+    "TIME_WAIT", "CLOSE", "CLOSE_WAIT", "LAST_ACK", "LISTEN", "CLOSING"];
+
+const UNIX_STATES: [&str; 5] = [
+    "FREE", "UNCONNECTED", "CONNECTING", "CONNECTED", "DISCONNECTING"];
+
+struct Descriptor {
+    fd: i128,
+    target: String,
+    inode: i128,
+}
+
+fn descriptors(pid: i128) -> CrushResult<Vec<Descriptor>> {
+    let mut res = Vec::new();
+    let dir = format!("/proc/{}/fd", pid);
+    for maybe_entry in to_crush_error(fs::read_dir(&dir))? {
+        let entry = to_crush_error(maybe_entry)?;
+        let fd = match entry.file_name().to_str().and_then(|s| s.parse::<i128>().ok()) {
+            Some(fd) => fd,
+            None => continue,
+        };
+        /* The descriptor may have been closed since we listed the directory. */
+        let target = match fs::read_link(entry.path()) {
+            Ok(t) => t.to_string_lossy().to_string(),
+            Err(_) => continue,
+        };
+        let inode = fs::metadata(entry.path()).map(|m| m.ino() as i128).unwrap_or(0);
+        res.push(Descriptor { fd, target, inode });
+    }
+    Ok(res)
+}
+
+fn descriptor_kind(target: &str) -> &'static str {
+    if target.starts_with("socket:[") {
+        "socket"
+    } else if target.starts_with("pipe:[") {
+        "pipe"
+    } else if target.starts_with("anon_inode:") {
+        "anon_inode"
+    } else if target.starts_with("/dev/") {
+        "device"
+    } else if Path::new(target).is_dir() {
+        "directory"
+    } else {
+        "file"
+    }
+}
+
+fn all_pids() -> CrushResult<Vec<i128>> {
+    Ok(to_crush_error(fs::read_dir("/proc"))?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str().and_then(|s| s.parse::<i128>().ok()))
+        .collect())
+}
+
+/**
+    Map socket inodes to the pid and file descriptor of every process holding them. A socket
+    can have several owners, e.g. after a fork. Processes whose descriptors we are not
+    allowed to inspect are skipped.
+*/
+fn socket_owners() -> CrushResult<HashMap<i128, Vec<(i128, i128)>>> {
+    let mut res = HashMap::new();
+    for pid in all_pids()? {
+        if let Ok(fds) = descriptors(pid) {
+            for d in fds {
+                if d.target.starts_with("socket:[") {
+                    res.entry(d.inode).or_insert_with(Vec::new).push((pid, d.fd));
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+fn parse_ipv4(s: &str) -> Option<Ipv4Addr> {
+    u32::from_str_radix(s, 16).ok().map(|v| Ipv4Addr::from(v.to_ne_bytes()))
+}
+
+fn parse_ipv6(s: &str) -> Option<Ipv6Addr> {
+    if s.len() != 32 {
+        return None;
+    }
+    let mut bytes = [0u8; 16];
+    for i in 0..4 {
+        let word = u32::from_str_radix(&s[i * 8..i * 8 + 8], 16).ok()?;
+        bytes[i * 4..i * 4 + 4].copy_from_slice(&word.to_ne_bytes());
+    }
+    Some(Ipv6Addr::from(bytes))
+}
+
+/**
+    Format an address from /proc/net/{tcp,udp}[6], e.g. 0100007F:0050 is 127.0.0.1:80.
+*/
+fn format_address(s: &str) -> CrushResult<String> {
+    let mut parts = s.split(':');
+    match (parts.next(), parts.next().and_then(|p| u16::from_str_radix(p, 16).ok())) {
+        (Some(addr), Some(port)) => {
+            if let Some(ip) = parse_ipv4(addr).filter(|_| addr.len() == 8) {
+                Ok(format!("{}:{}", ip, port))
+            } else if let Some(ip) = parse_ipv6(addr) {
+                Ok(format!("[{}]:{}", ip, port))
+            } else {
+                error(format!("Invalid socket address {}", s).as_str())
+            }
+        }
+        _ => error(format!("Invalid socket address {}", s).as_str()),
+    }
+}
+
+/**
+    The owners of a socket that should be listed, one row each. Sockets without a known
+    owner are listed once with a pid and fd of -1, unless we are filtering by pid.
+*/
+fn matching_owners(owners: &HashMap<i128, Vec<(i128, i128)>>, inode: i128, filter: Option<i128>) -> Vec<(i128, i128)> {
+    let all = owners.get(&inode).cloned().unwrap_or_else(|| vec![(-1, -1)]);
+    all.into_iter()
+        .filter(|(pid, _)| filter.map(|f| f == *pid).unwrap_or(true))
+        .collect()
+}
+
+fn send_socket(
+    output: &OutputStream,
+    owners: &HashMap<i128, Vec<(i128, i128)>>,
+    filter: Option<i128>,
+    protocol: &str,
+    local: String,
+    remote: String,
+    state: &str,
+    inode: i128) -> CrushResult<()> {
+    for (pid, fd) in matching_owners(owners, inode, filter) {
+        output.send(Row::new(vec![
+            Value::Integer(pid),
+            Value::Integer(fd),
+            Value::string(protocol),
+            Value::String(local.clone().into_boxed_str()),
+            Value::String(remote.clone().into_boxed_str()),
+            Value::string(state),
+            Value::Integer(inode),
+        ]))?;
+    }
+    Ok(())
+}
+
+fn inet_sockets(
+    protocol: &str,
+    output: &OutputStream,
+    owners: &HashMap<i128, Vec<(i128, i128)>>,
+    filter: Option<i128>) -> CrushResult<()> {
+    /* Not all kernels have IPv6 enabled, so a missing table is not an error. */
+    let table = match fs::read_to_string(format!("/proc/net/{}", protocol)) {
+        Ok(t) => t,
+        Err(_) => return Ok(()),
+    };
+    for line in table.lines().skip(1) {
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        if parts.len() < 10 {
+            continue;
+        }
+        let state = usize::from_str_radix(parts[3], 16).ok()
+            .and_then(|s| TCP_STATES.get(s))
+            .unwrap_or(&"UNKNOWN");
+        send_socket(
+            output, owners, filter, protocol,
+            format_address(parts[1])?,
+            format_address(parts[2])?,
+            state,
+            to_crush_error(parts[9].parse::<i128>())?)?;
+    }
+    Ok(())
+}
+
+fn unix_sockets(
+    output: &OutputStream,
+    owners: &HashMap<i128, Vec<(i128, i128)>>,
+    filter: Option<i128>) -> CrushResult<()> {
+    let table = to_crush_error(fs::read_to_string("/proc/net/unix"))?;
+    for line in table.lines().skip(1) {
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        if parts.len() < 7 {
+            continue;
+        }
+        let state = usize::from_str_radix(parts[5], 16).ok()
+            .and_then(|s| UNIX_STATES.get(s))
+            .unwrap_or(&"UNKNOWN");
+        send_socket(
+            output, owners, filter, "unix",
+            parts.get(7).unwrap_or(&"").to_string(),
+            String::new(),
+            state,
+            to_crush_error(parts[6].parse::<i128>())?)?;
+    }
+    Ok(())
+}
+
+pub fn files(mut context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(1)?;
+    let pid = context.arguments.integer(0)?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("pid", ValueType::Integer),
+        ColumnType::new("fd", ValueType::Integer),
+        ColumnType::new("kind", ValueType::String),
+        ColumnType::new("path", ValueType::String),
+        ColumnType::new("inode", ValueType::Integer),
+    ])?;
+    for d in descriptors(pid)? {
+        output.send(Row::new(vec![
+            Value::Integer(pid),
+            Value::Integer(d.fd),
+            Value::string(descriptor_kind(d.target.as_str())),
+            Value::String(d.target.into_boxed_str()),
+            Value::Integer(d.inode),
+        ]))?;
+    }
+    Ok(())
+}
+
+pub fn sockets(mut context: ExecutionContext) -> CrushResult<()> {
+    let filter = match context.arguments.len() {
+        0 => None,
+        1 => Some(context.arguments.integer(0)?),
+        _ => return argument_error("Expected at most one pid"),
+    };
+    let output = context.output.initialize(vec![
+        ColumnType::new("pid", ValueType::Integer),
+        ColumnType::new("fd", ValueType::Integer),
+        ColumnType::new("protocol", ValueType::String),
+        ColumnType::new("local_addr", ValueType::String),
+        ColumnType::new("remote_addr", ValueType::String),
+        ColumnType::new("state", ValueType::String),
+        ColumnType::new("inode", ValueType::Integer),
+    ])?;
+    let owners = socket_owners()?;
+    for protocol in &["tcp", "tcp6", "udp", "udp6"] {
+        inet_sockets(protocol, &output, &owners, filter)?;
+    }
+    unix_sockets(&output, &owners, filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn ipv4_addresses_are_formatted() {
+        assert_eq!(format_address("0100007F:0050").unwrap(), "127.0.0.1:80");
+        assert_eq!(format_address("00000000:1F90").unwrap(), "0.0.0.0:8080");
+    }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn ipv6_addresses_are_formatted() {
+        assert_eq!(format_address("00000000000000000000000001000000:0016").unwrap(), "[::1]:22");
+        assert_eq!(format_address("00000000000000000000000000000000:0000").unwrap(), "[::]:0");
+    }
+
+    #[test]
+    fn sockets_are_listed_for_every_owner() {
+        let mut owners = HashMap::new();
+        owners.insert(7, vec![(100, 3), (101, 3)]);
+        assert_eq!(matching_owners(&owners, 7, None), vec![(100, 3), (101, 3)]);
+        assert_eq!(matching_owners(&owners, 7, Some(101)), vec![(101, 3)]);
+        assert_eq!(matching_owners(&owners, 8, None), vec![(-1, -1)]);
+        assert!(matching_owners(&owners, 8, Some(100)).is_empty());
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        assert!(format_address("nonsense").is_err());
+        assert!(format_address("0100007F").is_err());
+    }
+}
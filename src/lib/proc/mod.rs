@@ -0,0 +1,329 @@
+use crate::lang::command::CrushCommand;
+use crate::lang::errors::{CrushResult, argument_error, to_crush_error};
+use crate::{
+    lang::table::Row,
+    lang::value::ValueType,
+    lang::value::Value,
+};
+use crate::util::user_map::{create_user_map, UserMap};
+use crate::util::glob::Glob;
+use psutil::process::State;
+use users::{uid_t, User};
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
+use crate::lang::{table::ColumnType};
+use chrono::Duration;
+use crate::lang::scope::Scope;
+use nix::sys::signal;
+use nix::unistd::Pid;
+use std::str::FromStr;
+use crate::lang::execution_context::{ExecutionContext, ArgumentVector};
+
+mod top;
+mod fd;
+mod wait;
+
+fn state_name(s: psutil::process::State) -> &'static str {
+    match s {
+        State::Running => "Running",
+        State::Sleeping => "Sleeping",
+        State::Waiting => "Waiting",
+        State::Stopped => "Stopped",
+        State::Traced => "Traced",
+        State::Paging => "Paging",
+        State::Dead => "Dead",
+        State::Zombie => "Zombie",
+        State::Idle => "Idle",
+    }
+}
+
+fn ps_columns() -> Vec<ColumnType> {
+    vec![
+        ColumnType::new("pid", ValueType::Integer),
+        ColumnType::new("ppid", ValueType::Integer),
+        ColumnType::new("status", ValueType::String),
+        ColumnType::new("user", ValueType::String),
+        ColumnType::new("cpu", ValueType::Duration),
+        ColumnType::new("name", ValueType::String),
+    ]
+}
+
+fn ps_row(proc: &psutil::process::Process, users: &HashMap<uid_t, User>) -> Row {
+    Row::new(vec![
+        Value::Integer(proc.pid as i128),
+        Value::Integer(proc.ppid as i128),
+        Value::string(state_name(proc.state)),
+        users.get_name(proc.uid as uid_t),
+        Value::Duration(Duration::microseconds((proc.utime*1000000.0) as i64)),
+        Value::string(process_name(proc).as_str()),
+    ])
+}
+
+fn ps(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let output = context.output.initialize(ps_columns())?;
+    let users = create_user_map();
+
+    for proc in &psutil::process::all().unwrap() {
+        output.send(ps_row(proc, &users))?;
+    }
+    Ok(())
+}
+
+fn process_name(proc: &psutil::process::Process) -> String {
+    proc.cmdline_vec().unwrap_or_else(|_| Some(vec!["<Illegal name>".to_string()]))
+        .unwrap_or_else(|| vec![format!("[{}]", proc.comm)])
+        .remove(0)
+}
+
+enum NamePattern {
+    Exact(Box<str>),
+    Glob(Glob),
+    Regex(Regex),
+}
+
+impl NamePattern {
+    fn matches_str(&self, name: &str) -> bool {
+        match self {
+            NamePattern::Exact(s) => s.as_ref() == name,
+            NamePattern::Glob(g) => g.matches(name),
+            NamePattern::Regex(r) => r.is_match(name),
+        }
+    }
+
+    /**
+        A process matches if either its full name, as shown by ps, or the file name part
+        of it matches, so that "cargo" matches /usr/bin/cargo.
+    */
+    fn matches(&self, name: &str) -> bool {
+        self.matches_str(name) ||
+            Path::new(name).file_name()
+                .map(|n| self.matches_str(n.to_string_lossy().as_ref()))
+                .unwrap_or(false)
+    }
+}
+
+fn kill(context: ExecutionContext) -> CrushResult<()> {
+    let mut pids = Vec::new();
+    let mut patterns = Vec::new();
+    let mut sig_to_send = signal::SIGTERM;
+    let mut dry_run = false;
+
+    for arg in context.arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, Value::Integer(pid)) => pids.push(pid as i32),
+            (Some("pid"), Value::Integer(pid)) => pids.push(pid as i32),
+            (None, Value::String(name)) | (Some("name"), Value::String(name)) =>
+                patterns.push(NamePattern::Exact(name)),
+            (None, Value::Glob(g)) | (Some("name"), Value::Glob(g)) =>
+                patterns.push(NamePattern::Glob(g)),
+            (None, Value::Regex(_, r)) | (Some("name"), Value::Regex(_, r)) =>
+                patterns.push(NamePattern::Regex(r)),
+            (Some("signal"), Value::String(sig)) => sig_to_send = to_crush_error(signal::Signal::from_str(sig.as_ref()))?,
+            (Some("dry_run"), Value::Bool(b)) => dry_run = b,
+            _ => return argument_error("Unknown argument")
+        }
+    }
+
+    let mut matched = Vec::new();
+    if !patterns.is_empty() || dry_run {
+        let own_pid = std::process::id() as i32;
+        for proc in to_crush_error(psutil::process::all())? {
+            if pids.contains(&proc.pid) {
+                matched.push(proc);
+            } else if proc.pid != own_pid {
+                let name = process_name(&proc);
+                if patterns.iter().any(|p| p.matches(name.as_str())) {
+                    matched.push(proc);
+                }
+            }
+        }
+    }
+
+    if dry_run {
+        let output = context.output.initialize(ps_columns())?;
+        let users = create_user_map();
+        for proc in &matched {
+            output.send(ps_row(proc, &users))?;
+        }
+        return Ok(());
+    }
+
+    for proc in &matched {
+        if !pids.contains(&proc.pid) {
+            pids.push(proc.pid);
+        }
+    }
+    for pid in pids {
+        to_crush_error(signal::kill(Pid::from_raw(pid), sig_to_send))?;
+    }
+    Ok(())
+}
+
+pub fn declare(root: &Scope) -> CrushResult<()> {
+    let env = root.create_namespace("proc")?;
+    root.r#use(&env);
+
+    env.declare("ps", Value::Command(CrushCommand::command(
+        ps, true,
+        "ps", "Return a table stream containing information on all running processes on the system.", Some(r#"    ps accepts no arguments. Each row contains the following columns:
+
+    * pid:integer the process id of the process
+
+    * ppid:integer the process id of the parent of the process
+
+    * status:string one of the following states:
+      - Running
+      - Sleeping
+      - Waiting
+      - Stopped
+      - Traces
+      - Paging
+      - Dead
+      - Zombie
+      - Idle
+
+    * user:string the username of the process owner
+
+    * cpu:duration the amount of CPU time this process has used since its creation
+
+    * name:string the process name"#))))?;
+    env.declare("top", Value::Command(CrushCommand::command(
+        top::perform, true,
+        "top [interval=interval:duration] [count=count:integer]",
+        "Return a table stream of CPU and memory usage for all processes, sampled over an interval",
+        Some(r#"    top samples every process on the system, waits for the specified interval
+    (one second by default) and samples them again. The difference is used to
+    calculate how much CPU each process used during the interval. This is
+    repeated count times (once by default), and all samples are written to the
+    same stream. Within a sample, rows are sorted by descending CPU usage.
+    Processes that did not exist when the interval started are reported with a
+    cpu of 0, since their usage can't be measured.
+
+    Each row contains the following columns:
+
+    * sample:time the time at which the sample was taken
+
+    * pid:integer the process id of the process
+
+    * ppid:integer the process id of the parent of the process
+
+    * status:string the state of the process, see ps for details
+
+    * user:string the username of the process owner
+
+    * cpu:float the percentage of one CPU core this process used during the interval
+
+    * memory:float the resident memory of the process as a percentage of all memory
+
+    * name:string the process name
+
+    Example:
+
+    # The five processes using the most CPU during the last second
+    top | head 5
+
+    # Report all processes using more than half a core, every 10 seconds
+    top interval=(duration:new 10 "seconds") count=100 | where {cpu > 50.0}"#))))?;
+    env.declare("files", Value::Command(CrushCommand::command(
+        fd::files, true,
+        "proc:files pid:integer",
+        "Return a table stream of all open file descriptors of a process",
+        Some(r#"    Each row contains the following columns:
+
+    * pid:integer the process id of the process
+
+    * fd:integer the file descriptor number
+
+    * kind:string one of file, directory, device, socket, pipe and anon_inode
+
+    * path:string the file the descriptor refers to, or a description like
+      socket:[12345] for descriptors that do not refer to a file
+
+    * inode:integer the inode of the file, socket or pipe
+
+    Example:
+
+    proc:files 1234 | where {kind == "socket"}"#))))?;
+    env.declare("sockets", Value::Command(CrushCommand::command(
+        fd::sockets, true,
+        "proc:sockets [pid:integer]",
+        "Return a table stream of all open network and unix sockets",
+        Some(r#"    If a pid is given, only sockets held by that process are returned.
+    Sockets are read from /proc/net/{tcp,tcp6,udp,udp6,unix} and matched against
+    the open file descriptors of all processes. A socket held by several
+    processes, e.g. after a fork, is returned once for every process holding
+    it. Each row contains the following columns:
+
+    * pid:integer the process holding the socket, or -1 if it could not be
+      determined, e.g. because the process belongs to another user
+
+    * fd:integer the file descriptor of the socket in that process, or -1
+
+    * protocol:string one of tcp, tcp6, udp, udp6 and unix
+
+    * local_addr:string the local address, or the path of a unix socket
+
+    * remote_addr:string the remote address, empty for unix sockets
+
+    * state:string the connection state, e.g. LISTEN or ESTABLISHED
+
+    * inode:integer the inode of the socket
+
+    Example:
+
+    # Who is listening on port 8080?
+    proc:sockets | where {re".*:8080" =~ local_addr and state == "LISTEN"}"#))))?;
+    env.declare("kill", Value::Command(CrushCommand::command(
+        kill, false,
+        "kill [signal=signal:string] [dry_run=dry_run:bool] [pid=pid:integer...] [name=name:(string|glob|regex)...] @pid:(integer|string|glob|regex)",
+        "Send a signal to a set of processes",
+        Some(r#"    Kill accepts the following arguments:
+
+    * signal:string the name of the signal to send. If unspecified, the SIGTERM signal is sent.
+      The set of existing signals is platform dependent, but common signals include
+      SIGHUP, SIGINT, SIGQUIT, SIGILL, SIGTRAP, SIGABRT, SIGBUS, SIGFPE, SIGKILL,
+      SIGUSR1, SIGSEGV, SIGUSR2, SIGPIPE, SIGALRM, SIGTERM, SIGCHLD, SIGCONT and SIGWINCH.
+
+    * pid:integer the process ids of all process to signal.
+
+    * name:string, glob or regex processes to signal by name. The pattern is matched
+      against the process name as shown by ps, and against the file name part of it,
+      so "cargo" matches a process named /usr/bin/cargo. The shell never signals
+      itself by name.
+
+    * dry_run:bool if true, no signal is sent. Instead, the matched processes are
+      returned as a table stream with the same columns as ps.
+
+    Example:
+
+    # Which test runners would be killed?
+    kill dry_run=true %test%
+    # Kill them
+    kill signal="SIGKILL" %test%"#))))?;
+    env.declare("wait", Value::Command(CrushCommand::command(
+        wait::perform, true,
+        "proc:wait [timeout=timeout:duration] @pid:integer",
+        "Wait for a set of processes to exit",
+        Some(r#"    proc:wait blocks until all the specified processes have exited, or until the
+    timeout expires. It returns a table stream with one row per process and the
+    following columns:
+
+    * pid:integer the process id of the process
+
+    * exited:bool true if the process has exited
+
+    * status:string Exited if the process is gone, Zombie if it has exited but not
+      yet been reaped by its parent, otherwise the state of the still running
+      process, see ps for details
+
+    The exit code of a process can only be obtained by its parent, so it is not
+    reported.
+
+    Example:
+
+    proc:wait timeout=(duration:new 30 "seconds") 1234 1235"#))))?;
+    env.readonly();
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{CrushResult, argument_error, to_crush_error};
+use crate::lang::stream::ValueSender;
+use crate::lang::{argument::Argument, table::ColumnType, table::Row, value::Value, value::ValueType};
+use crate::util::user_map::{create_user_map, UserMap};
+use chrono::{Duration, Local};
+use std::collections::HashMap;
+use std::thread;
+use std::time::Instant;
+use users::uid_t;
+use crate::lib::sys::total_memory;
+use super::{process_name, state_name};
+
+pub struct Config {
+    interval: Duration,
+    count: i128,
+}
+
+fn parse(arguments: Vec<Argument>) -> CrushResult<Config> {
+    let mut interval = Duration::seconds(1);
+    let mut count = 1;
+
+    for arg in arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (Some("interval"), Value::Duration(d)) => interval = d,
+            (Some("count"), Value::Integer(c)) => count = c,
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+
+    if interval <= Duration::zero() {
+        return argument_error("The interval must be positive");
+    }
+    if count < 1 {
+        return argument_error("The count must be positive");
+    }
+    Ok(Config { interval, count })
+}
+
+/**
+    Processes are identified by their pid and start time, so that a process reusing the
+    pid of one that exited isn't charged for the time used by its predecessor.
+*/
+fn cpu_times(processes: &[psutil::process::Process]) -> HashMap<(i32, u128), f64> {
+    processes.iter()
+        .map(|p| ((p.pid, p.starttime_ticks), p.utime + p.stime))
+        .collect()
+}
+
+/**
+    The percentage of one core used during the interval. Processes that weren't part of
+    the previous sample have no known starting point, and are reported as idle rather
+    than charged for all the CPU time they have ever used.
+*/
+fn cpu_usage(used: f64, previously_used: Option<f64>, elapsed: f64) -> f64 {
+    match previously_used {
+        Some(previous) => 100.0 * (used - previous).max(0.0) / elapsed,
+        None => 0.0,
+    }
+}
+
+fn run(config: Config, sender: ValueSender) -> CrushResult<()> {
+    let output = sender.initialize(vec![
+        ColumnType::new("sample", ValueType::Time),
+        ColumnType::new("pid", ValueType::Integer),
+        ColumnType::new("ppid", ValueType::Integer),
+        ColumnType::new("status", ValueType::String),
+        ColumnType::new("user", ValueType::String),
+        ColumnType::new("cpu", ValueType::Float),
+        ColumnType::new("memory", ValueType::Float),
+        ColumnType::new("name", ValueType::String),
+    ])?;
+    let users = create_user_map();
+    let total_memory = total_memory()? as f64;
+    let interval = to_crush_error(config.interval.to_std())?;
+
+    let mut previous = cpu_times(&to_crush_error(psutil::process::all())?);
+    let mut previous_time = Instant::now();
+
+    for _ in 0..config.count {
+        thread::sleep(interval);
+
+        let processes = to_crush_error(psutil::process::all())?;
+        let now = Instant::now();
+        let elapsed = now.duration_since(previous_time).as_secs_f64();
+        let sample = Local::now();
+
+        let mut rows = processes.iter()
+            .map(|proc| {
+                let cpu = cpu_usage(
+                    proc.utime + proc.stime,
+                    previous.get(&(proc.pid, proc.starttime_ticks)).cloned(),
+                    elapsed);
+                let resident = proc.memory().map(|m| m.resident as f64).unwrap_or(0.0);
+                (cpu, proc, 100.0 * resident / total_memory)
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        for (cpu, proc, memory) in rows {
+            let row = Row::new(vec![
+                Value::Time(sample),
+                Value::Integer(proc.pid as i128),
+                Value::Integer(proc.ppid as i128),
+                Value::string(state_name(proc.state)),
+                users.get_name(proc.uid as uid_t),
+                Value::Float(cpu),
+                Value::Float(memory),
+                Value::string(process_name(proc).as_str()),
+            ]);
+            if output.send(row).is_err() {
+                /* Whoever is reading our output has stopped listening, e.g. head. */
+                return Ok(());
+            }
+        }
+
+        previous = cpu_times(&processes);
+        previous_time = now;
+    }
+    Ok(())
+}
+
+pub fn perform(context: ExecutionContext) -> CrushResult<()> {
+    let config = parse(context.arguments)?;
+    run(config, context.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_is_relative_to_the_previous_sample() {
+        assert_eq!(cpu_usage(3.0, Some(2.5), 1.0), 50.0);
+        assert_eq!(cpu_usage(12.0, Some(8.0), 2.0), 200.0);
+    }
+
+    #[test]
+    fn new_processes_are_not_charged_for_their_whole_lifetime() {
+        assert_eq!(cpu_usage(1000.0, None, 1.0), 0.0);
+    }
+}
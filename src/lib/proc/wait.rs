@@ -0,0 +1,83 @@
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{CrushResult, argument_error, to_crush_error};
+use crate::lang::{argument::Argument, table::ColumnType, table::Row, value::Value, value::ValueType};
+use chrono::Duration;
+use psutil::process::{Process, State};
+use std::thread;
+use std::time::Instant;
+use super::state_name;
+
+const POLL_INTERVAL_MS: u64 = 50;
+
+struct Config {
+    pids: Vec<i128>,
+    timeout: Option<Duration>,
+}
+
+fn parse(arguments: Vec<Argument>) -> CrushResult<Config> {
+    let mut pids = Vec::new();
+    let mut timeout = None;
+
+    for arg in arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, Value::Integer(pid)) | (Some("pid"), Value::Integer(pid)) => pids.push(pid),
+            (Some("timeout"), Value::Duration(d)) => timeout = Some(d),
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+    if pids.is_empty() {
+        return argument_error("Expected at least one pid");
+    }
+    Ok(Config { pids, timeout })
+}
+
+/**
+    The current status of a process, or None if it has exited. Zombies have exited, they
+    just haven't been reaped by their parent yet.
+*/
+fn status(pid: i128) -> Option<State> {
+    match Process::new(pid as i32) {
+        Ok(proc) => Some(proc.state),
+        Err(_) => None,
+    }
+}
+
+fn has_exited(status: Option<State>) -> bool {
+    match status {
+        None | Some(State::Zombie) | Some(State::Dead) => true,
+        _ => false,
+    }
+}
+
+pub fn perform(context: ExecutionContext) -> CrushResult<()> {
+    let config = parse(context.arguments)?;
+    let deadline = match config.timeout {
+        Some(t) => Some(Instant::now() + to_crush_error(t.to_std())?),
+        None => None,
+    };
+
+    loop {
+        if config.pids.iter().all(|pid| has_exited(status(*pid))) {
+            break;
+        }
+        if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+            break;
+        }
+        thread::sleep(std::time::Duration::from_millis(POLL_INTERVAL_MS));
+    }
+
+    let output = context.output.initialize(vec![
+        ColumnType::new("pid", ValueType::Integer),
+        ColumnType::new("exited", ValueType::Bool),
+        ColumnType::new("status", ValueType::String),
+    ])?;
+    for pid in config.pids {
+        let s = status(pid);
+        output.send(Row::new(vec![
+            Value::Integer(pid),
+            Value::Bool(has_exited(s)),
+            Value::string(s.map(state_name).unwrap_or("Exited")),
+        ]))?;
+    }
+    Ok(())
+}
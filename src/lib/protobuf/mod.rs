@@ -0,0 +1,186 @@
+use std::fs;
+use std::io::{BufReader, Read, Write};
+
+use crate::lang::command::CrushCommand;
+use crate::lang::execution_context::{ArgumentVector, ExecutionContext};
+use crate::lang::errors::{argument_error, error, mandate, to_crush_error, CrushResult};
+use crate::lang::scope::Scope;
+use crate::lang::{argument::Argument, binary::binary_channel};
+use crate::lang::{r#struct::Struct, value::Value};
+use crate::lang::table::{Row, Table, TableReader};
+use crate::lib::input::convert::binary_input;
+use crate::util::compression::Decompress;
+use schema::Schema;
+
+mod schema;
+mod wire;
+
+fn schema(mut context: ExecutionContext) -> CrushResult<()> {
+    let files = context.arguments.files()?;
+    if files.is_empty() {
+        return argument_error("Expected at least one .proto file");
+    }
+    let definitions = files.iter()
+        .map(|f| match fs::read_to_string(f) {
+            Ok(text) => Ok(text),
+            Err(e) => error(format!("{}: {}", f.to_string_lossy(), e).as_str()),
+        })
+        .collect::<CrushResult<Vec<_>>>()?;
+    context.output.send(schema::parse(&definitions)?.to_value())
+}
+
+struct Config {
+    schema: Schema,
+    message: Box<str>,
+    delimited: bool,
+    decompress: Decompress,
+    files: Vec<Box<std::path::Path>>,
+}
+
+fn parse(arguments: Vec<Argument>) -> CrushResult<Config> {
+    let mut schema = None;
+    let mut message = None;
+    let mut delimited = false;
+    let mut decompress = Decompress::None;
+    let mut files = Vec::new();
+    for arg in arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, Value::Struct(s)) | (Some("schema"), Value::Struct(s)) =>
+                schema = Some(Schema::from_value(Value::Struct(s))?),
+            (Some("message"), Value::String(s)) => message = Some(s),
+            (Some("delimited"), Value::Bool(b)) => delimited = b,
+            (Some("decompress"), Value::String(s)) => decompress = Decompress::parse(&s)?,
+            (None, value) => value.file_expand(&mut files)?,
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+    Ok(Config {
+        schema: mandate(schema, "Missing schema")?,
+        message: mandate(message, "Missing message name")?,
+        delimited,
+        decompress,
+        files,
+    })
+}
+
+fn decode(context: ExecutionContext) -> CrushResult<()> {
+    let cfg = parse(context.arguments)?;
+    let message = cfg.schema.find_message(&cfg.message)?;
+    let columns = wire::message_columns(&cfg.schema, message);
+    let mut reader = binary_input(cfg.files, cfg.decompress, context.input)?;
+    if cfg.delimited {
+        let mut reader = BufReader::new(reader);
+        let output = context.output.initialize(columns)?;
+        while let Some(data) = wire::read_delimited(&mut reader)? {
+            if output.send(Row::new(wire::decode(&cfg.schema, message, &data)?)).is_err() {
+                /* Whoever is reading our output has stopped listening. */
+                return Ok(());
+            }
+        }
+        Ok(())
+    } else {
+        let mut data = Vec::new();
+        to_crush_error(reader.read_to_end(&mut data))?;
+        let cells = wire::decode(&cfg.schema, message, &data)?;
+        context.output.send(Value::Struct(Struct::from_vec(cells, columns)))
+    }
+}
+
+fn encode(context: ExecutionContext) -> CrushResult<()> {
+    let cfg = parse(context.arguments)?;
+    if !cfg.files.is_empty() || cfg.decompress != Decompress::None {
+        return argument_error("Unknown argument");
+    }
+    let message = cfg.schema.find_message(&cfg.message)?;
+    match context.input.recv()? {
+        Value::Struct(s) if !cfg.delimited => {
+            let mut data = Vec::new();
+            wire::encode(&cfg.schema, message, &|name| s.get(name), &mut data)?;
+            context.output.send(Value::Binary(data))
+        }
+        value => {
+            let mut rows = match value.readable() {
+                Some(rows) => rows,
+                None => match value {
+                    Value::Struct(s) => Box::new(TableReader::new(Table::new(s.types(), vec![s.into_row()]))),
+                    _ => return argument_error("Expected a struct or a table stream as input"),
+                },
+            };
+            let (mut out, reader) = binary_channel()?;
+            context.output.send(Value::BinaryStream(reader))?;
+            let names = rows.types().iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+            while let Ok(row) = rows.read() {
+                let cells = row.into_vec();
+                let mut data = Vec::new();
+                wire::encode(&cfg.schema, message, &|name| names.iter()
+                    .position(|n| n.as_ref() == name)
+                    .map(|idx| cells[idx].clone()), &mut data)?;
+                let mut delimited = Vec::new();
+                wire::write_varint(data.len() as u64, &mut delimited);
+                delimited.extend_from_slice(&data);
+                to_crush_error(out.write_all(&delimited))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn declare(root: &Scope) -> CrushResult<()> {
+    let env = root.create_namespace("protobuf")?;
+    env.declare("schema", Value::Command(CrushCommand::command(
+        schema, true,
+        "protobuf:schema @file:file", "Compile protocol buffer definitions into a schema",
+        Some(r#"    Both proto2 and proto3 syntax are supported. Imports are not followed,
+    so any files defining types used by the definitions must be passed as well.
+    Services, extensions and options other than packed are ignored.
+
+    The schema is a struct with the members messages and enums. Messages
+    are listed with their fully qualified names and a table of their fields,
+    enums with a table of their values. Map fields refer to a generated entry
+    message with the fields key and value.
+
+    Examples:
+
+    log_schema := (protobuf:schema /tmp/log.proto /tmp/common.proto)"#))))?;
+    env.declare("decode", Value::Command(CrushCommand::command(
+        decode, true,
+        "protobuf:decode schema:struct message=name:string [delimited=delimited:bool] [decompress=format:string] [file:file]",
+        "Decode protocol buffer messages",
+        Some(r#"    Input can either be a binary stream or a file. The message name can be
+    fully qualified or any unambiguous suffix of the full name. decompress
+    works like it does for the lines command.
+
+    By default, the input is a single message which is returned as a struct.
+    If delimited is true, the input is a stream of messages, each prefixed
+    with its length as a varint, and the output is a table stream with one
+    row per message.
+
+    Messages become structs, repeated message fields become tables, other
+    repeated fields become lists and map fields become dicts. Enum values
+    become the names of the values. Missing fields get their default value,
+    except for message fields which become empty, so their columns are of
+    type any. Unknown fields are skipped.
+
+    Examples:
+
+    protobuf:decode $log_schema message=Entry /tmp/entry.bin
+
+    protobuf:decode $log_schema message=log.Entry delimited=true /tmp/requests.log"#))))?;
+    env.declare("encode", Value::Command(CrushCommand::command(
+        encode, true,
+        "protobuf:encode schema:struct message=name:string [delimited=delimited:bool]",
+        "Encode protocol buffer messages",
+        Some(r#"    A struct is encoded as a single message and returned as binary data. A
+    table or a table stream, or a struct if delimited is true, is encoded as a
+    stream of messages, each prefixed with its length as a varint.
+
+    Members and columns are matched to fields by name. Members that are
+    missing or empty are left out. Repeated fields take lists or tables,
+    map fields take dicts, and enum fields take the name or the number of a
+    value.
+
+    Examples:
+
+    protobuf:decode $log_schema message=Entry delimited=true /tmp/requests.log | where {status == 500} | protobuf:encode $log_schema message=Entry | write /tmp/errors.log"#))))?;
+    Ok(())
+}
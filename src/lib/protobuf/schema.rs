@@ -0,0 +1,778 @@
+use std::collections::HashMap;
+
+use crate::lang::errors::{error, mandate, CrushResult};
+use crate::lang::{r#struct::Struct, table::ColumnType, table::Row, table::Table};
+use crate::lang::value::{Value, ValueType};
+
+pub const SCALAR_TYPES: &[&str] = &[
+    "double", "float", "int32", "int64", "uint32", "uint64", "sint32", "sint64", "fixed32",
+    "fixed64", "sfixed32", "sfixed64", "bool", "string", "bytes",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Label {
+    Optional,
+    Required,
+    Repeated,
+    /** A map field, encoded as a repeated message with the fields key and value. */
+    Map,
+}
+
+impl Label {
+    fn name(&self) -> &'static str {
+        match self {
+            Label::Optional => "optional",
+            Label::Required => "required",
+            Label::Repeated => "repeated",
+            Label::Map => "map",
+        }
+    }
+
+    fn parse(name: &str) -> CrushResult<Label> {
+        match name {
+            "optional" => Ok(Label::Optional),
+            "required" => Ok(Label::Required),
+            "repeated" => Ok(Label::Repeated),
+            "map" => Ok(Label::Map),
+            _ => error(format!("Unknown field label {}", name).as_str()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub number: u32,
+    /** Either a scalar type or the fully qualified name of a message or an enum. */
+    pub type_name: String,
+    pub label: Label,
+    pub packed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    /** The fully qualified name, including the package. */
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Enum {
+    pub name: String,
+    pub values: Vec<(String, i32)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    pub messages: Vec<Message>,
+    pub enums: Vec<Enum>,
+}
+
+impl Field {
+    pub fn is_scalar(&self) -> bool {
+        SCALAR_TYPES.contains(&self.type_name.as_str())
+    }
+
+    /** Scalars that can be packed, i.e. everything except strings and bytes. */
+    pub fn is_numeric(&self, schema: &Schema) -> bool {
+        (self.is_scalar() && self.type_name != "string" && self.type_name != "bytes")
+            || schema.enumeration(&self.type_name).is_some()
+    }
+}
+
+impl Schema {
+    pub fn message(&self, name: &str) -> Option<&Message> {
+        self.messages.iter().find(|m| m.name == name)
+    }
+
+    pub fn enumeration(&self, name: &str) -> Option<&Enum> {
+        self.enums.iter().find(|e| e.name == name)
+    }
+
+    /**
+        Find a message by its fully qualified name, or by any unambiguous suffix of it.
+    */
+    pub fn find_message(&self, name: &str) -> CrushResult<&Message> {
+        if let Some(message) = self.message(name) {
+            return Ok(message);
+        }
+        let suffix = format!(".{}", name);
+        let candidates = self.messages.iter()
+            .filter(|m| m.name.ends_with(&suffix))
+            .collect::<Vec<_>>();
+        match candidates.len() {
+            0 => error(format!("Unknown message {}", name).as_str()),
+            1 => Ok(candidates[0]),
+            _ => error(format!(
+                "Ambiguous message name {}, could be any of {}",
+                name,
+                candidates.iter().map(|m| m.name.as_str()).collect::<Vec<_>>().join(", ")).as_str()),
+        }
+    }
+
+    pub fn merge(&mut self, other: Schema) {
+        self.messages.extend(other.messages);
+        self.enums.extend(other.enums);
+    }
+}
+
+/*
+    Conversion to and from crush values. A schema is a struct containing a table of
+    messages and a table of enums, so that it can be inspected and passed around like any
+    other value.
+*/
+
+fn field_columns() -> Vec<ColumnType> {
+    vec![
+        ColumnType::new("name", ValueType::String),
+        ColumnType::new("number", ValueType::Integer),
+        ColumnType::new("type", ValueType::String),
+        ColumnType::new("label", ValueType::String),
+        ColumnType::new("packed", ValueType::Bool),
+    ]
+}
+
+fn message_columns() -> Vec<ColumnType> {
+    vec![
+        ColumnType::new("name", ValueType::String),
+        ColumnType::new("fields", ValueType::Table(field_columns())),
+    ]
+}
+
+fn enum_value_columns() -> Vec<ColumnType> {
+    vec![
+        ColumnType::new("name", ValueType::String),
+        ColumnType::new("number", ValueType::Integer),
+    ]
+}
+
+fn enum_columns() -> Vec<ColumnType> {
+    vec![
+        ColumnType::new("name", ValueType::String),
+        ColumnType::new("values", ValueType::Table(enum_value_columns())),
+    ]
+}
+
+impl Schema {
+    pub fn to_value(&self) -> Value {
+        let messages = self.messages.iter()
+            .map(|m| Row::new(vec![
+                Value::string(&m.name),
+                Value::Table(Table::new(field_columns(), m.fields.iter()
+                    .map(|f| Row::new(vec![
+                        Value::string(&f.name),
+                        Value::Integer(f.number as i128),
+                        Value::string(&f.type_name),
+                        Value::string(f.label.name()),
+                        Value::Bool(f.packed),
+                    ]))
+                    .collect())),
+            ]))
+            .collect();
+        let enums = self.enums.iter()
+            .map(|e| Row::new(vec![
+                Value::string(&e.name),
+                Value::Table(Table::new(enum_value_columns(), e.values.iter()
+                    .map(|(name, number)| Row::new(vec![Value::string(name), Value::Integer(*number as i128)]))
+                    .collect())),
+            ]))
+            .collect();
+        Value::Struct(Struct::new(vec![
+            (Box::from("messages"), Value::Table(Table::new(message_columns(), messages))),
+            (Box::from("enums"), Value::Table(Table::new(enum_columns(), enums))),
+        ], None))
+    }
+
+    pub fn from_value(value: Value) -> CrushResult<Schema> {
+        let schema = match value {
+            Value::Struct(s) => s,
+            _ => return error("Expected a schema created by protobuf:schema"),
+        };
+        let mut res = Schema::default();
+        for message in rows(schema.get("messages"))? {
+            let mut fields = Vec::new();
+            for field in rows(message.get("fields"))? {
+                fields.push(Field {
+                    name: string(field.get("name"))?,
+                    number: integer(field.get("number"))? as u32,
+                    type_name: string(field.get("type"))?,
+                    label: Label::parse(&string(field.get("label"))?)?,
+                    packed: match field.get("packed") {
+                        Some(Value::Bool(b)) => b,
+                        _ => false,
+                    },
+                });
+            }
+            res.messages.push(Message { name: string(message.get("name"))?, fields });
+        }
+        for enumeration in rows(schema.get("enums"))? {
+            let mut values = Vec::new();
+            for value in rows(enumeration.get("values"))? {
+                values.push((string(value.get("name"))?, integer(value.get("number"))? as i32));
+            }
+            res.enums.push(Enum { name: string(enumeration.get("name"))?, values });
+        }
+        Ok(res)
+    }
+}
+
+fn rows(value: Option<Value>) -> CrushResult<Vec<Struct>> {
+    let mut rows = mandate(value.and_then(|v| v.readable()), "Invalid schema")?;
+    let types = rows.types().clone();
+    let mut res = Vec::new();
+    while let Ok(row) = rows.read() {
+        res.push(row.into_struct(&types));
+    }
+    Ok(res)
+}
+
+fn string(value: Option<Value>) -> CrushResult<String> {
+    match value {
+        Some(Value::String(s)) => Ok(s.to_string()),
+        _ => error("Invalid schema, expected a string"),
+    }
+}
+
+fn integer(value: Option<Value>) -> CrushResult<i128> {
+    match value {
+        Some(Value::Integer(i)) => Ok(i),
+        _ => error("Invalid schema, expected an integer"),
+    }
+}
+
+/*
+    Parsing of .proto files. Both proto2 and proto3 syntax are supported. Services,
+    extensions and options other than packed are ignored.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /** Identifiers, keywords and numbers. */
+    Word(String),
+    Str(String),
+    Symbol(char),
+}
+
+struct Tokenizer {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+fn tokenize(text: &str) -> CrushResult<Vec<(usize, Token)>> {
+    let mut res = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            previous = c;
+                        }
+                        None => return error(format!("Line {}: unterminated comment", line).as_str()),
+                    }
+                }
+            }
+            '"' | '\'' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => if let Some(c) = chars.next() {
+                            s.push(match c {
+                                'n' => '\n',
+                                't' => '\t',
+                                'r' => '\r',
+                                c => c,
+                            })
+                        },
+                        Some(q) if q == c => break,
+                        Some(c) => s.push(c),
+                        None => return error(format!("Line {}: unterminated string", line).as_str()),
+                    }
+                }
+                res.push((line, Token::Str(s)));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if !(c.is_alphanumeric() || *c == '_' || *c == '.') {
+                        break;
+                    }
+                    word.push(*c);
+                    chars.next();
+                }
+                res.push((line, Token::Word(word)));
+            }
+            c => res.push((line, Token::Symbol(c))),
+        }
+    }
+    Ok(res)
+}
+
+impl Tokenizer {
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map(|(line, _)| *line)
+            .unwrap_or(1)
+    }
+
+    fn fail<T>(&self, message: &str) -> CrushResult<T> {
+        error(format!("Line {}: {}", self.line(), message).as_str())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> CrushResult<Token> {
+        match self.tokens.get(self.pos) {
+            Some((_, token)) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => self.fail("unexpected end of file"),
+        }
+    }
+
+    fn at_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&Token::Symbol(symbol))
+    }
+
+    fn symbol(&mut self, symbol: char) -> CrushResult<()> {
+        match self.next()? {
+            Token::Symbol(c) if c == symbol => Ok(()),
+            _ => {
+                self.pos -= 1;
+                self.fail(format!("expected '{}'", symbol).as_str())
+            }
+        }
+    }
+
+    fn word(&mut self) -> CrushResult<String> {
+        match self.next()? {
+            Token::Word(w) => Ok(w),
+            _ => {
+                self.pos -= 1;
+                self.fail("expected a name")
+            }
+        }
+    }
+
+    fn string(&mut self) -> CrushResult<String> {
+        match self.next()? {
+            Token::Str(s) => Ok(s),
+            _ => {
+                self.pos -= 1;
+                self.fail("expected a string")
+            }
+        }
+    }
+
+    fn integer(&mut self) -> CrushResult<i64> {
+        let negative = self.at_symbol('-');
+        if negative {
+            self.pos += 1;
+        }
+        let word = self.word()?;
+        let parsed = if word.starts_with("0x") || word.starts_with("0X") {
+            i64::from_str_radix(&word[2..], 16)
+        } else if word.len() > 1 && word.starts_with('0') {
+            i64::from_str_radix(&word[1..], 8)
+        } else {
+            word.parse()
+        };
+        match parsed {
+            Ok(n) if negative => Ok(-n),
+            Ok(n) => Ok(n),
+            Err(_) => {
+                self.pos -= 1;
+                self.fail(format!("expected an integer, got {}", word).as_str())
+            }
+        }
+    }
+
+    /** Skip a statement up to and including the next semicolon. */
+    fn skip_statement(&mut self) -> CrushResult<()> {
+        while self.next()? != Token::Symbol(';') {}
+        Ok(())
+    }
+
+    /** Skip a name followed by a block in braces. */
+    fn skip_block(&mut self) -> CrushResult<()> {
+        while self.next()? != Token::Symbol('{') {}
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next()? {
+                Token::Symbol('{') => depth += 1,
+                Token::Symbol('}') => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /**
+        Parse field options in brackets, if any. Returns the value of the packed option.
+    */
+    fn field_options(&mut self) -> CrushResult<Option<bool>> {
+        let mut packed = None;
+        if !self.at_symbol('[') {
+            return Ok(None);
+        }
+        self.pos += 1;
+        loop {
+            let name = self.next()?;
+            if name == Token::Symbol(']') {
+                break;
+            }
+            if name == Token::Word("packed".to_string()) && self.at_symbol('=') {
+                self.pos += 1;
+                packed = Some(self.word()? == "true");
+            }
+        }
+        Ok(packed)
+    }
+}
+
+/** A field with a type name that has not been resolved yet. */
+struct RawField {
+    field: Field,
+    packed: Option<bool>,
+}
+
+struct RawMessage {
+    name: String,
+    fields: Vec<RawField>,
+}
+
+struct Parser {
+    tokens: Tokenizer,
+    proto3: bool,
+    messages: Vec<RawMessage>,
+    enums: Vec<Enum>,
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+impl Parser {
+    fn file(&mut self) -> CrushResult<()> {
+        let mut package = String::new();
+        while let Some(token) = self.tokens.peek() {
+            match token {
+                Token::Symbol(';') => self.tokens.pos += 1,
+                Token::Word(w) => match w.as_str() {
+                    "syntax" => {
+                        self.tokens.pos += 1;
+                        self.tokens.symbol('=')?;
+                        self.proto3 = self.tokens.string()? == "proto3";
+                        self.tokens.symbol(';')?;
+                    }
+                    "package" => {
+                        self.tokens.pos += 1;
+                        package = self.tokens.word()?;
+                        self.tokens.symbol(';')?;
+                    }
+                    "import" | "option" => self.tokens.skip_statement()?,
+                    "message" => {
+                        self.tokens.pos += 1;
+                        self.message(&package)?;
+                    }
+                    "enum" => {
+                        self.tokens.pos += 1;
+                        self.enumeration(&package)?;
+                    }
+                    "service" | "extend" => self.tokens.skip_block()?,
+                    _ => return self.tokens.fail(format!("unexpected {}", w).as_str()),
+                },
+                _ => return self.tokens.fail("expected a definition"),
+            }
+        }
+        Ok(())
+    }
+
+    fn enumeration(&mut self, scope: &str) -> CrushResult<()> {
+        let name = qualify(scope, &self.tokens.word()?);
+        self.tokens.symbol('{')?;
+        let mut values = Vec::new();
+        loop {
+            match self.tokens.next()? {
+                Token::Symbol('}') => break,
+                Token::Symbol(';') => {}
+                Token::Word(w) if w == "option" || w == "reserved" => self.tokens.skip_statement()?,
+                Token::Word(value) => {
+                    self.tokens.symbol('=')?;
+                    let number = self.tokens.integer()? as i32;
+                    self.tokens.field_options()?;
+                    self.tokens.symbol(';')?;
+                    values.push((value, number));
+                }
+                _ => return self.tokens.fail("expected an enum value"),
+            }
+        }
+        self.enums.push(Enum { name, values });
+        Ok(())
+    }
+
+    fn field(&mut self, label: Label, type_name: String) -> CrushResult<RawField> {
+        let name = self.tokens.word()?;
+        self.tokens.symbol('=')?;
+        let number = self.tokens.integer()?;
+        if !(1..=536_870_911).contains(&number) {
+            return self.tokens.fail(format!("invalid field number {}", number).as_str());
+        }
+        let packed = self.tokens.field_options()?;
+        self.tokens.symbol(';')?;
+        Ok(RawField {
+            field: Field { name, number: number as u32, type_name, label, packed: false },
+            packed,
+        })
+    }
+
+    fn message(&mut self, scope: &str) -> CrushResult<()> {
+        let name = qualify(scope, &self.tokens.word()?);
+        self.tokens.symbol('{')?;
+        let mut fields = Vec::new();
+        let mut in_oneof = false;
+        loop {
+            let word = match self.tokens.next()? {
+                Token::Symbol('}') if in_oneof => {
+                    in_oneof = false;
+                    continue;
+                }
+                Token::Symbol('}') => break,
+                Token::Symbol(';') => continue,
+                Token::Word(w) => w,
+                _ => return self.tokens.fail("expected a field"),
+            };
+            match word.as_str() {
+                "message" => self.message(&name)?,
+                "enum" => self.enumeration(&name)?,
+                "option" | "reserved" | "extensions" => self.tokens.skip_statement()?,
+                "extend" => self.tokens.skip_block()?,
+                "oneof" => {
+                    self.tokens.word()?;
+                    self.tokens.symbol('{')?;
+                    in_oneof = true;
+                }
+                "map" if self.tokens.at_symbol('<') => {
+                    self.tokens.pos += 1;
+                    let key = self.tokens.word()?;
+                    self.tokens.symbol(',')?;
+                    let value = self.tokens.word()?;
+                    self.tokens.symbol('>')?;
+                    let field = self.field(Label::Map, String::new())?;
+                    let entry = qualify(&name, &format!("{}Entry", camel_case(&field.field.name)));
+                    self.messages.push(RawMessage {
+                        name: entry.clone(),
+                        fields: vec![
+                            RawField {
+                                field: Field { name: "key".to_string(), number: 1, type_name: key, label: Label::Optional, packed: false },
+                                packed: None,
+                            },
+                            RawField {
+                                field: Field { name: "value".to_string(), number: 2, type_name: value, label: Label::Optional, packed: false },
+                                packed: None,
+                            },
+                        ],
+                    });
+                    fields.push(RawField {
+                        field: Field { type_name: format!(".{}", entry), ..field.field },
+                        packed: None,
+                    });
+                }
+                "optional" | "required" | "repeated" => {
+                    let label = Label::parse(&word)?;
+                    let type_name = self.tokens.word()?;
+                    if type_name == "group" {
+                        return self.tokens.fail("groups are not supported");
+                    }
+                    fields.push(self.field(label, type_name)?);
+                }
+                type_name => fields.push(self.field(Label::Optional, type_name.to_string())?),
+            }
+        }
+        self.messages.push(RawMessage { name, fields });
+        Ok(())
+    }
+
+    /**
+        Resolve a type name the way protoc does, by looking for it in the scope of the
+        message it is used in, and then in each enclosing scope.
+    */
+    fn resolve(&self, scope: &str, name: &str, known: &HashMap<String, ()>) -> CrushResult<String> {
+        if SCALAR_TYPES.contains(&name) {
+            return Ok(name.to_string());
+        }
+        if let Some(absolute) = name.strip_prefix('.') {
+            return match known.contains_key(absolute) {
+                true => Ok(absolute.to_string()),
+                false => error(format!("Unknown type {}", name).as_str()),
+            };
+        }
+        let mut scope = scope.to_string();
+        loop {
+            let candidate = qualify(&scope, name);
+            if known.contains_key(&candidate) {
+                return Ok(candidate);
+            }
+            if scope.is_empty() {
+                return error(format!("Unknown type {}", name).as_str());
+            }
+            scope = match scope.rfind('.') {
+                Some(idx) => scope[..idx].to_string(),
+                None => String::new(),
+            };
+        }
+    }
+
+    fn finish(self, known: &HashMap<String, ()>) -> CrushResult<Schema> {
+        let mut messages = Vec::new();
+        for message in &self.messages {
+            let mut fields = Vec::new();
+            for raw in &message.fields {
+                let type_name = match self.resolve(&message.name, &raw.field.type_name, known) {
+                    Ok(t) => t,
+                    Err(_) => return error(format!(
+                        "Unknown type {} of field {} in {}",
+                        raw.field.type_name, raw.field.name, message.name).as_str()),
+                };
+                fields.push(Field { type_name, ..raw.field.clone() });
+            }
+            messages.push(Message { name: message.name.clone(), fields });
+        }
+        let mut schema = Schema { messages, enums: self.enums };
+        /* Repeated numeric fields are packed by default in proto3 only. */
+        let packed_by_default = self.proto3;
+        let raw_packed = self.messages.iter()
+            .map(|m| m.fields.iter().map(|f| f.packed).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        for (m, message) in schema.messages.clone().iter().enumerate() {
+            for (f, field) in message.fields.iter().enumerate() {
+                let packed = field.label == Label::Repeated
+                    && field.is_numeric(&schema)
+                    && raw_packed[m][f].unwrap_or(packed_by_default);
+                schema.messages[m].fields[f].packed = packed;
+            }
+        }
+        Ok(schema)
+    }
+}
+
+/**
+    Parse the contents of a set of .proto files. Types may refer to types defined in any of
+    the files.
+*/
+pub fn parse(files: &[String]) -> CrushResult<Schema> {
+    let mut parsers = Vec::new();
+    let mut known = HashMap::new();
+    for text in files {
+        let mut parser = Parser {
+            tokens: Tokenizer { tokens: tokenize(text)?, pos: 0 },
+            proto3: false,
+            messages: Vec::new(),
+            enums: Vec::new(),
+        };
+        parser.file()?;
+        for name in parser.messages.iter().map(|m| &m.name).chain(parser.enums.iter().map(|e| &e.name)) {
+            known.insert(name.clone(), ());
+        }
+        parsers.push(parser);
+    }
+    let mut schema = Schema::default();
+    for parser in parsers {
+        schema.merge(parser.finish(&known)?);
+    }
+    Ok(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proto_files_are_parsed() {
+        let schema = parse(&[r#"
+            syntax = "proto3";
+            package log;
+            import "google/protobuf/timestamp.proto";
+
+            /* An entry in the request log. */
+            message Entry {
+                enum Level { DEBUG = 0; INFO = 1; ERROR = 2; }
+                message Tag { string name = 1; }
+                string host = 1;
+                Level level = 2 [deprecated = true];
+                repeated Tag tags = 3;
+                repeated int64 sizes = 4;
+                repeated int64 unpacked = 5 [packed = false];
+                map<string, int32> counts = 6;
+                oneof payload {
+                    string text = 7;
+                    bytes data = 8;
+                }
+            }
+            service Logger { rpc Log (Entry) returns (Entry) {} }
+        "#.to_string()]).unwrap();
+        let entry = schema.find_message("Entry").unwrap();
+        assert_eq!(entry.name, "log.Entry");
+        let types = entry.fields.iter().map(|f| f.type_name.as_str()).collect::<Vec<_>>();
+        assert_eq!(types, vec![
+            "string", "log.Entry.Level", "log.Entry.Tag", "int64", "int64",
+            "log.Entry.CountsEntry", "string", "bytes"]);
+        assert!(entry.fields[3].packed);
+        assert!(!entry.fields[4].packed);
+        assert_eq!(entry.fields[5].label, Label::Map);
+        assert_eq!(schema.enumeration("log.Entry.Level").unwrap().values[2], ("ERROR".to_string(), 2));
+    }
+
+    #[test]
+    fn schemas_survive_conversion_to_values() {
+        let schema = parse(&["message A { optional B b = 1; } message B { repeated int32 x = 1 [packed=true]; }".to_string()]).unwrap();
+        let schema = Schema::from_value(schema.to_value()).unwrap();
+        assert_eq!(schema.messages.len(), 2);
+        assert_eq!(schema.message("A").unwrap().fields[0].type_name, "B");
+        assert!(schema.message("B").unwrap().fields[0].packed);
+    }
+
+    #[test]
+    fn unknown_types_are_rejected() {
+        assert!(parse(&["message A { optional C c = 1; }".to_string()]).is_err());
+        assert!(parse(&["message A { optional int32 c = 0; }".to_string()]).is_err());
+    }
+}
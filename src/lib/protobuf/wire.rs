@@ -0,0 +1,544 @@
+use std::io::Read;
+
+use crate::lang::errors::{error, mandate, to_crush_error, CrushResult};
+use crate::lang::{dict::Dict, list::List, r#struct::Struct};
+use crate::lang::{table::ColumnType, table::Row, table::Table};
+use crate::lang::value::{Value, ValueType};
+use super::schema::{Field, Label, Message, Schema};
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const FIXED32: u64 = 5;
+
+fn scalar_type(type_name: &str) -> ValueType {
+    match type_name {
+        "double" | "float" => ValueType::Float,
+        "bool" => ValueType::Bool,
+        "string" => ValueType::String,
+        "bytes" => ValueType::Binary,
+        _ => ValueType::Integer,
+    }
+}
+
+fn element_type(schema: &Schema, field: &Field, stack: &mut Vec<String>) -> ValueType {
+    if field.is_scalar() {
+        scalar_type(&field.type_name)
+    } else if schema.enumeration(&field.type_name).is_some() {
+        ValueType::String
+    } else {
+        match schema.message(&field.type_name) {
+            Some(message) if field.label == Label::Repeated && !stack.contains(&message.name) =>
+                ValueType::Table(columns(schema, message, stack)),
+            /* Recursive tables can contain anything, and a message field that is not set is empty. */
+            _ => ValueType::Any,
+        }
+    }
+}
+
+fn field_type(schema: &Schema, field: &Field, stack: &mut Vec<String>) -> ValueType {
+    match field.label {
+        Label::Map => match map_entry(schema, field) {
+            Ok((_, key, value)) => ValueType::Dict(
+                Box::from(element_type(schema, key, stack)),
+                Box::from(element_type(schema, value, stack))),
+            Err(_) => ValueType::Any,
+        },
+        Label::Repeated if field.is_scalar() || schema.message(&field.type_name).is_none() =>
+            ValueType::List(Box::from(element_type(schema, field, stack))),
+        _ => element_type(schema, field, stack),
+    }
+}
+
+fn columns(schema: &Schema, message: &Message, stack: &mut Vec<String>) -> Vec<ColumnType> {
+    stack.push(message.name.clone());
+    let res = message.fields.iter()
+        .map(|f| ColumnType::new(&f.name, field_type(schema, f, stack)))
+        .collect();
+    stack.pop();
+    res
+}
+
+/**
+    The column types of a decoded message. Repeated message fields are tables, except when
+    a message contains itself, in which case the nested table can contain anything. Other
+    message fields are of type any, since they are empty when not set.
+*/
+pub fn message_columns(schema: &Schema, message: &Message) -> Vec<ColumnType> {
+    columns(schema, message, &mut Vec::new())
+}
+
+fn map_entry<'a>(schema: &'a Schema, field: &Field) -> CrushResult<(&'a Message, &'a Field, &'a Field)> {
+    let entry = mandate(schema.message(&field.type_name), "Unknown map entry type")?;
+    match (entry.fields.iter().find(|f| f.number == 1), entry.fields.iter().find(|f| f.number == 2)) {
+        (Some(key), Some(value)) => Ok((entry, key, value)),
+        _ => error(format!("Invalid map entry type {}", entry.name).as_str()),
+    }
+}
+
+fn wire_type(schema: &Schema, field: &Field) -> u64 {
+    match field.type_name.as_str() {
+        "fixed64" | "sfixed64" | "double" => FIXED64,
+        "fixed32" | "sfixed32" | "float" => FIXED32,
+        "string" | "bytes" => LENGTH_DELIMITED,
+        _ if field.is_scalar() || schema.enumeration(&field.type_name).is_some() => VARINT,
+        _ => LENGTH_DELIMITED,
+    }
+}
+
+fn default_value(schema: &Schema, field: &Field) -> Value {
+    match field.type_name.as_str() {
+        "double" | "float" => Value::Float(0.0),
+        "bool" => Value::Bool(false),
+        "string" => Value::string(""),
+        "bytes" => Value::Binary(Vec::new()),
+        _ if field.is_scalar() => Value::Integer(0),
+        name => match schema.enumeration(name) {
+            Some(e) => Value::string(e.values.first().map(|v| v.0.as_str()).unwrap_or("0")),
+            None => Value::Empty(),
+        },
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn done(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> CrushResult<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return error("Unexpected end of message");
+        }
+        self.pos += len;
+        Ok(&self.data[self.pos - len..self.pos])
+    }
+
+    fn varint(&mut self) -> CrushResult<u64> {
+        let mut res = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            res |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(res);
+            }
+        }
+        error("Invalid varint")
+    }
+
+    fn fixed32(&mut self) -> CrushResult<[u8; 4]> {
+        let mut res = [0u8; 4];
+        res.copy_from_slice(self.bytes(4)?);
+        Ok(res)
+    }
+
+    fn fixed64(&mut self) -> CrushResult<[u8; 8]> {
+        let mut res = [0u8; 8];
+        res.copy_from_slice(self.bytes(8)?);
+        Ok(res)
+    }
+
+    fn length_delimited(&mut self) -> CrushResult<&'a [u8]> {
+        let len = self.varint()? as usize;
+        self.bytes(len)
+    }
+
+    fn skip(&mut self, wire_type: u64) -> CrushResult<()> {
+        match wire_type {
+            VARINT => self.varint().map(|_| ()),
+            FIXED64 => self.bytes(8).map(|_| ()),
+            LENGTH_DELIMITED => self.length_delimited().map(|_| ()),
+            FIXED32 => self.bytes(4).map(|_| ()),
+            _ => error(format!("Unsupported wire type {}", wire_type).as_str()),
+        }
+    }
+}
+
+fn decode_value(schema: &Schema, field: &Field, reader: &mut Reader) -> CrushResult<Value> {
+    Ok(match field.type_name.as_str() {
+        "int32" => Value::Integer(reader.varint()? as i32 as i128),
+        "int64" => Value::Integer(reader.varint()? as i64 as i128),
+        "uint32" => Value::Integer(reader.varint()? as u32 as i128),
+        "uint64" => Value::Integer(reader.varint()? as i128),
+        "sint32" | "sint64" => {
+            let n = reader.varint()?;
+            Value::Integer(((n >> 1) as i64 ^ -((n & 1) as i64)) as i128)
+        }
+        "bool" => Value::Bool(reader.varint()? != 0),
+        "fixed32" => Value::Integer(u32::from_le_bytes(reader.fixed32()?) as i128),
+        "sfixed32" => Value::Integer(i32::from_le_bytes(reader.fixed32()?) as i128),
+        "float" => Value::Float(f32::from_le_bytes(reader.fixed32()?) as f64),
+        "fixed64" => Value::Integer(u64::from_le_bytes(reader.fixed64()?) as i128),
+        "sfixed64" => Value::Integer(i64::from_le_bytes(reader.fixed64()?) as i128),
+        "double" => Value::Float(f64::from_le_bytes(reader.fixed64()?)),
+        "string" => Value::string(&to_crush_error(String::from_utf8(reader.length_delimited()?.to_vec()))?),
+        "bytes" => Value::Binary(reader.length_delimited()?.to_vec()),
+        name => match (schema.enumeration(name), schema.message(name)) {
+            (Some(e), _) => {
+                let number = reader.varint()? as i32;
+                match e.values.iter().find(|v| v.1 == number) {
+                    Some((name, _)) => Value::string(name),
+                    /* Unknown enum values are kept, as required by the specification. */
+                    None => Value::string(&number.to_string()),
+                }
+            }
+            (None, Some(message)) => Value::Struct(Struct::from_vec(
+                decode(schema, message, reader.length_delimited()?)?,
+                message_columns(schema, message))),
+            (None, None) => return error(format!("Unknown type {}", name).as_str()),
+        },
+    })
+}
+
+/**
+    Decode a message into one cell per field, in the order the fields are declared.
+*/
+pub fn decode(schema: &Schema, message: &Message, data: &[u8]) -> CrushResult<Vec<Value>> {
+    let mut values: Vec<Vec<Value>> = message.fields.iter().map(|_| Vec::new()).collect();
+    let mut reader = Reader { data, pos: 0 };
+    while !reader.done() {
+        let key = reader.varint()?;
+        let (number, wire) = (key >> 3, key & 7);
+        let idx = match message.fields.iter().position(|f| f.number as u64 == number) {
+            Some(idx) => idx,
+            None => {
+                reader.skip(wire)?;
+                continue;
+            }
+        };
+        let field = &message.fields[idx];
+        let expected = wire_type(schema, field);
+        if wire == LENGTH_DELIMITED && expected != LENGTH_DELIMITED && field.label == Label::Repeated {
+            /* Parsers must accept both packed and unpacked repeated fields. */
+            let mut packed = Reader { data: reader.length_delimited()?, pos: 0 };
+            while !packed.done() {
+                values[idx].push(decode_value(schema, field, &mut packed)?);
+            }
+        } else if wire != expected {
+            return error(format!(
+                "Field {} of {} has wire type {}, expected {}",
+                field.name, message.name, wire, expected).as_str());
+        } else {
+            values[idx].push(decode_value(schema, field, &mut reader)?);
+        }
+    }
+
+    let mut res = Vec::new();
+    for (field, mut values) in message.fields.iter().zip(values) {
+        res.push(match field.label {
+            Label::Map => {
+                let (_, key, value) = map_entry(schema, field)?;
+                let mut stack = vec![message.name.clone()];
+                let dict = Dict::new(
+                    element_type(schema, key, &mut stack),
+                    element_type(schema, value, &mut stack));
+                for e in values {
+                    if let Value::Struct(s) = e {
+                        dict.insert(
+                            s.get(&key.name).unwrap_or_else(|| default_value(schema, key)),
+                            s.get(&value.name).unwrap_or_else(|| default_value(schema, value)))?;
+                    }
+                }
+                Value::Dict(dict)
+            }
+            Label::Repeated => match schema.message(&field.type_name) {
+                Some(m) => Value::Table(Table::new(
+                    message_columns(schema, m),
+                    values.into_iter()
+                        .map(|v| match v {
+                            Value::Struct(s) => Row::new(s.into_vec()),
+                            v => Row::new(vec![v]),
+                        })
+                        .collect())),
+                None => Value::List(List::new(element_type(schema, field, &mut Vec::new()), values)),
+            },
+            _ => values.pop().unwrap_or_else(|| default_value(schema, field)),
+        });
+    }
+    Ok(res)
+}
+
+/**
+    Read a length-delimited message from a stream. Returns None at the end of the stream.
+*/
+pub fn read_delimited(input: &mut dyn Read) -> CrushResult<Option<Vec<u8>>> {
+    let mut len = 0u64;
+    let mut byte = [0u8; 1];
+    for shift in (0..64).step_by(7) {
+        if to_crush_error(input.read(&mut byte))? == 0 {
+            return if shift == 0 { Ok(None) } else { error("Unexpected end of stream") };
+        }
+        len |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            /* Don't trust the length before the data has arrived, it could be anything. */
+            let mut res = Vec::new();
+            to_crush_error(input.take(len).read_to_end(&mut res))?;
+            return if res.len() as u64 == len { Ok(Some(res)) } else { error("Unexpected end of stream") };
+        }
+    }
+    error("Invalid message length")
+}
+
+pub fn write_varint(mut n: u64, out: &mut Vec<u8>) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn write_delimited(data: &[u8], out: &mut Vec<u8>) {
+    write_varint(data.len() as u64, out);
+    out.extend_from_slice(data);
+}
+
+fn integer(field: &Field, value: &Value, min: i128, max: i128) -> CrushResult<i128> {
+    match value {
+        Value::Integer(i) if *i >= min && *i <= max => Ok(*i),
+        Value::Integer(i) => error(format!("Value {} is out of range for field {}", i, field.name).as_str()),
+        v => error(format!(
+            "Expected an integer for field {}, got {}", field.name, v.value_type().to_string()).as_str()),
+    }
+}
+
+fn float(field: &Field, value: &Value) -> CrushResult<f64> {
+    match value {
+        Value::Float(f) => Ok(*f),
+        Value::Integer(i) => Ok(*i as f64),
+        v => error(format!(
+            "Expected a float for field {}, got {}", field.name, v.value_type().to_string()).as_str()),
+    }
+}
+
+fn encode_value(schema: &Schema, field: &Field, value: &Value, out: &mut Vec<u8>) -> CrushResult<()> {
+    let i32_range = (i32::MIN as i128, i32::MAX as i128);
+    let i64_range = (i64::MIN as i128, i64::MAX as i128);
+    match field.type_name.as_str() {
+        "int32" => write_varint(integer(field, value, i32_range.0, i32_range.1)? as i64 as u64, out),
+        "int64" => write_varint(integer(field, value, i64_range.0, i64_range.1)? as i64 as u64, out),
+        "uint32" => write_varint(integer(field, value, 0, u32::MAX as i128)? as u64, out),
+        "uint64" => write_varint(integer(field, value, 0, u64::MAX as i128)? as u64, out),
+        "sint32" | "sint64" => {
+            let range = if field.type_name == "sint32" { i32_range } else { i64_range };
+            let n = integer(field, value, range.0, range.1)? as i64;
+            write_varint(((n << 1) ^ (n >> 63)) as u64, out)
+        }
+        "bool" => match value {
+            Value::Bool(b) => write_varint(*b as u64, out),
+            _ => return error(format!("Expected a bool for field {}", field.name).as_str()),
+        },
+        "fixed32" => out.extend_from_slice(&(integer(field, value, 0, u32::MAX as i128)? as u32).to_le_bytes()),
+        "sfixed32" => out.extend_from_slice(&(integer(field, value, i32_range.0, i32_range.1)? as i32).to_le_bytes()),
+        "float" => out.extend_from_slice(&(float(field, value)? as f32).to_le_bytes()),
+        "fixed64" => out.extend_from_slice(&(integer(field, value, 0, u64::MAX as i128)? as u64).to_le_bytes()),
+        "sfixed64" => out.extend_from_slice(&(integer(field, value, i64_range.0, i64_range.1)? as i64).to_le_bytes()),
+        "double" => out.extend_from_slice(&float(field, value)?.to_le_bytes()),
+        "string" | "bytes" => match value {
+            Value::String(s) => write_delimited(s.as_bytes(), out),
+            Value::Binary(b) if field.type_name == "bytes" => write_delimited(b, out),
+            v => return error(format!(
+                "Expected a {} for field {}, got {}",
+                field.type_name, field.name, v.value_type().to_string()).as_str()),
+        },
+        name => match (schema.enumeration(name), schema.message(name)) {
+            (Some(e), _) => {
+                let number = match value {
+                    Value::String(s) => match e.values.iter().find(|v| v.0 == s.as_ref()) {
+                        Some((_, number)) => *number as i128,
+                        None => match s.parse() {
+                            Ok(number) => number,
+                            Err(_) => return error(format!("Unknown value {} for enum {}", s, e.name).as_str()),
+                        },
+                    },
+                    v => integer(field, v, i32_range.0, i32_range.1)?,
+                };
+                write_varint(number as i64 as u64, out)
+            }
+            (None, Some(message)) => {
+                let mut data = Vec::new();
+                match value {
+                    Value::Struct(s) => encode(schema, message, &|name| s.get(name), &mut data)?,
+                    v => return error(format!(
+                        "Expected a struct for field {}, got {}", field.name, v.value_type().to_string()).as_str()),
+                }
+                write_delimited(&data, out)
+            }
+            (None, None) => return error(format!("Unknown type {}", name).as_str()),
+        },
+    }
+    Ok(())
+}
+
+fn write_key(field: &Field, wire_type: u64, out: &mut Vec<u8>) {
+    write_varint(((field.number as u64) << 3) | wire_type, out)
+}
+
+/**
+    The elements of a repeated field, which can be given as a list or, for messages, as a
+    table or a table stream.
+*/
+fn elements(field: &Field, value: Value) -> CrushResult<Vec<Value>> {
+    match value {
+        Value::List(l) => Ok(l.dump()),
+        value => match value.readable() {
+            Some(mut rows) => {
+                let types = rows.types().clone();
+                let mut res = Vec::new();
+                while let Ok(row) = rows.read() {
+                    res.push(Value::Struct(row.into_struct(&types)));
+                }
+                Ok(res)
+            }
+            None => error(format!(
+                "Expected a list or a table for field {}, got {}",
+                field.name, value.value_type().to_string()).as_str()),
+        },
+    }
+}
+
+/**
+    Encode a message. Fields that are missing or empty are left out.
+*/
+pub fn encode(
+    schema: &Schema,
+    message: &Message,
+    member: &dyn Fn(&str) -> Option<Value>,
+    out: &mut Vec<u8>,
+) -> CrushResult<()> {
+    for field in &message.fields {
+        let value = match member(&field.name) {
+            None | Some(Value::Empty()) => continue,
+            Some(value) => value,
+        };
+        match field.label {
+            Label::Map => {
+                let (entry, key, value_field) = map_entry(schema, field)?;
+                let dict = match value {
+                    Value::Dict(d) => d,
+                    v => return error(format!(
+                        "Expected a dict for field {}, got {}", field.name, v.value_type().to_string()).as_str()),
+                };
+                for (k, v) in dict.elements() {
+                    let mut data = Vec::new();
+                    encode(schema, entry, &|name| match name {
+                        n if n == key.name => Some(k.clone()),
+                        n if n == value_field.name => Some(v.clone()),
+                        _ => None,
+                    }, &mut data)?;
+                    write_key(field, LENGTH_DELIMITED, out);
+                    write_delimited(&data, out);
+                }
+            }
+            Label::Repeated if field.packed => {
+                let mut data = Vec::new();
+                for element in elements(field, value)? {
+                    encode_value(schema, field, &element, &mut data)?;
+                }
+                if !data.is_empty() {
+                    write_key(field, LENGTH_DELIMITED, out);
+                    write_delimited(&data, out);
+                }
+            }
+            Label::Repeated => for element in elements(field, value)? {
+                write_key(field, wire_type(schema, field), out);
+                encode_value(schema, field, &element, out)?;
+            },
+            _ => {
+                write_key(field, wire_type(schema, field), out);
+                encode_value(schema, field, &value, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::schema::parse;
+
+    fn schema() -> Schema {
+        parse(&[r#"
+            syntax = "proto3";
+            message Entry {
+                enum Level { DEBUG = 0; INFO = 1; }
+                message Tag { string name = 1; }
+                int32 id = 1;
+                Level level = 2;
+                repeated Tag tags = 3;
+                repeated sint64 deltas = 4;
+                map<string, double> weights = 5;
+                bytes data = 6;
+            }
+        "#.to_string()]).unwrap()
+    }
+
+    #[test]
+    fn known_messages_are_decoded() {
+        let schema = schema();
+        let entry = schema.find_message("Entry").unwrap();
+        /* id = 150, level = INFO, an unknown field 9 and an unpacked delta of -2. */
+        let cells = decode(&schema, entry, &[0x08, 0x96, 0x01, 0x10, 0x01, 0x48, 0x05, 0x20, 0x03]).unwrap();
+        assert!(cells[0] == Value::Integer(150));
+        assert!(cells[1] == Value::string("INFO"));
+        assert!(cells[3] == Value::List(List::new(ValueType::Integer, vec![Value::Integer(-2)])));
+        assert!(cells[5] == Value::Binary(Vec::new()));
+    }
+
+    #[test]
+    fn messages_survive_encoding() {
+        let schema = schema();
+        let entry = schema.find_message("Entry").unwrap();
+        let tag = Struct::new(vec![(Box::from("name"), Value::string("web"))], None);
+        let weights = Dict::new(ValueType::String, ValueType::Float);
+        weights.insert(Value::string("a"), Value::Float(0.5)).unwrap();
+        let input = Struct::new(vec![
+            (Box::from("id"), Value::Integer(-7)),
+            (Box::from("level"), Value::string("INFO")),
+            (Box::from("tags"), Value::List(List::new(ValueType::Struct, vec![Value::Struct(tag)]))),
+            (Box::from("deltas"), Value::List(List::new(ValueType::Integer, vec![Value::Integer(1), Value::Integer(-300)]))),
+            (Box::from("weights"), Value::Dict(weights)),
+            (Box::from("data"), Value::Binary(vec![1, 2, 3])),
+        ], None);
+        let mut data = Vec::new();
+        encode(&schema, entry, &|name| input.get(name), &mut data).unwrap();
+        let cells = decode(&schema, entry, &data).unwrap();
+        assert!(cells[0] == Value::Integer(-7));
+        assert!(cells[1] == Value::string("INFO"));
+        match &cells[2] {
+            Value::Table(t) => assert!(t.rows()[0].cells()[0] == Value::string("web")),
+            _ => panic!("Expected a table"),
+        }
+        assert!(cells[3] == Value::List(List::new(ValueType::Integer, vec![Value::Integer(1), Value::Integer(-300)])));
+        match &cells[4] {
+            Value::Dict(d) => assert!(d.get(&Value::string("a")) == Some(Value::Float(0.5))),
+            _ => panic!("Expected a dict"),
+        }
+        assert!(cells[5] == Value::Binary(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        let schema = schema();
+        let entry = schema.find_message("Entry").unwrap();
+        assert!(decode(&schema, entry, &[0x08, 0x96]).is_err());
+        assert!(decode(&schema, entry, &[0x32, 0x05, 0x01]).is_err());
+    }
+
+    #[test]
+    fn delimited_lengths_are_checked_against_the_data() {
+        let mut data = Vec::new();
+        write_delimited(b"abc", &mut data);
+        let mut input = &data[..];
+        assert_eq!(read_delimited(&mut input).unwrap(), Some(b"abc".to_vec()));
+        assert_eq!(read_delimited(&mut input).unwrap(), None);
+        let mut huge = Vec::new();
+        write_varint(u64::MAX >> 1, &mut huge);
+        huge.push(1);
+        assert!(read_delimited(&mut &huge[..]).is_err());
+    }
+}
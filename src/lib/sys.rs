@@ -0,0 +1,333 @@
+use crate::lang::command::CrushCommand;
+use crate::lang::errors::{CrushResult, error, to_crush_error, mandate};
+use crate::lang::execution_context::{ExecutionContext, ArgumentVector};
+use crate::lang::{r#struct::Struct, table::ColumnType, table::Row, value::Value, value::ValueType};
+use crate::lang::scope::Scope;
+use chrono::Duration;
+use nix::sys::statvfs::statvfs;
+use nix::unistd::{sysconf, SysconfVar};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/**
+    Parse /proc/meminfo into a map from field name to size in bytes.
+*/
+fn meminfo() -> CrushResult<HashMap<String, u64>> {
+    let mut res = HashMap::new();
+    for line in to_crush_error(fs::read_to_string("/proc/meminfo"))?.lines() {
+        let mut parts = line.split(':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            let mut value_parts = value.split_whitespace();
+            let amount = to_crush_error(mandate(value_parts.next(), "Invalid meminfo line")?.parse::<u64>())?;
+            let factor = match value_parts.next() {
+                Some("kB") => 1024,
+                _ => 1,
+            };
+            res.insert(name.to_string(), amount * factor);
+        }
+    }
+    Ok(res)
+}
+
+pub fn total_memory() -> CrushResult<u64> {
+    Ok(*mandate(meminfo()?.get("MemTotal"), "Could not find total memory in /proc/meminfo")?)
+}
+
+fn clock_ticks() -> CrushResult<i64> {
+    match to_crush_error(sysconf(SysconfVar::CLK_TCK))? {
+        Some(ticks) if ticks > 0 => Ok(ticks as i64),
+        _ => error("Could not determine the number of clock ticks per second"),
+    }
+}
+
+/**
+    Mount points in /proc/mounts use octal escapes for whitespace and backslashes.
+*/
+fn unescape_mount_field(s: &str) -> String {
+    let mut res = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let code: String = chars.clone().take(3).collect();
+            match u8::from_str_radix(code.as_str(), 8) {
+                Ok(v) if code.len() == 3 => {
+                    res.push(v as char);
+                    chars.nth(2);
+                }
+                _ => res.push(c),
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+struct Mount {
+    device: String,
+    mountpoint: String,
+    fs_type: String,
+    options: String,
+}
+
+fn read_mounts() -> CrushResult<Vec<Mount>> {
+    let mut res = Vec::new();
+    for line in to_crush_error(fs::read_to_string("/proc/mounts"))?.lines() {
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        if parts.len() < 4 {
+            continue;
+        }
+        res.push(Mount {
+            device: unescape_mount_field(parts[0]),
+            mountpoint: unescape_mount_field(parts[1]),
+            fs_type: parts[2].to_string(),
+            options: parts[3].to_string(),
+        });
+    }
+    Ok(res)
+}
+
+fn mem(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let info = meminfo()?;
+    let get = |name: &str| Value::Integer(info.get(name).cloned().unwrap_or(0) as i128);
+    context.output.send(Value::Struct(Struct::new(
+        vec![
+            (Box::from("total"), get("MemTotal")),
+            (Box::from("free"), get("MemFree")),
+            (Box::from("available"), get("MemAvailable")),
+            (Box::from("buffers"), get("Buffers")),
+            (Box::from("cached"), get("Cached")),
+            (Box::from("swap_total"), get("SwapTotal")),
+            (Box::from("swap_free"), get("SwapFree")),
+        ],
+        None,
+    )))
+}
+
+fn cpus(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("core", ValueType::Integer),
+        ColumnType::new("model", ValueType::String),
+        ColumnType::new("mhz", ValueType::Float),
+        ColumnType::new("user", ValueType::Duration),
+        ColumnType::new("nice", ValueType::Duration),
+        ColumnType::new("system", ValueType::Duration),
+        ColumnType::new("idle", ValueType::Duration),
+        ColumnType::new("iowait", ValueType::Duration),
+    ])?;
+
+    let mut models = HashMap::new();
+    let mut current_core = None;
+    for line in to_crush_error(fs::read_to_string("/proc/cpuinfo"))?.lines() {
+        let mut parts = line.splitn(2, ':');
+        match (parts.next().map(|s| s.trim()), parts.next().map(|s| s.trim())) {
+            (Some("processor"), Some(v)) => current_core = v.parse::<i128>().ok(),
+            (Some("model name"), Some(v)) => {
+                if let Some(core) = current_core {
+                    models.entry(core).or_insert((v.to_string(), 0.0)).0 = v.to_string();
+                }
+            }
+            (Some("cpu MHz"), Some(v)) => {
+                if let Some(core) = current_core {
+                    models.entry(core).or_insert((String::new(), 0.0)).1 = v.parse::<f64>().unwrap_or(0.0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let ticks = clock_ticks()?;
+    let to_duration = |v: Option<&&str>| {
+        Value::Duration(Duration::microseconds(
+            v.and_then(|s| s.parse::<i64>().ok()).unwrap_or(0) * 1_000_000 / ticks))
+    };
+
+    for line in to_crush_error(fs::read_to_string("/proc/stat"))?.lines() {
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        if parts.is_empty() || !parts[0].starts_with("cpu") || parts[0] == "cpu" {
+            continue;
+        }
+        let core = to_crush_error(parts[0][3..].parse::<i128>())?;
+        let (model, mhz) = models.get(&core).cloned().unwrap_or((String::new(), 0.0));
+        output.send(Row::new(vec![
+            Value::Integer(core),
+            Value::string(model.as_str()),
+            Value::Float(mhz),
+            to_duration(parts.get(1)),
+            to_duration(parts.get(2)),
+            to_duration(parts.get(3)),
+            to_duration(parts.get(4)),
+            to_duration(parts.get(5)),
+        ]))?;
+    }
+    Ok(())
+}
+
+fn load(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let loadavg = to_crush_error(fs::read_to_string("/proc/loadavg"))?;
+    let parts = loadavg.split_whitespace().collect::<Vec<&str>>();
+    if parts.len() < 4 {
+        return error("Invalid format of /proc/loadavg");
+    }
+    let tasks = parts[3].split('/').collect::<Vec<&str>>();
+    let task_count = |idx: usize| Value::Integer(tasks.get(idx).and_then(|s| s.parse::<i128>().ok()).unwrap_or(0));
+    context.output.send(Value::Struct(Struct::new(
+        vec![
+            (Box::from("one"), Value::Float(to_crush_error(parts[0].parse::<f64>())?)),
+            (Box::from("five"), Value::Float(to_crush_error(parts[1].parse::<f64>())?)),
+            (Box::from("fifteen"), Value::Float(to_crush_error(parts[2].parse::<f64>())?)),
+            (Box::from("running"), task_count(0)),
+            (Box::from("tasks"), task_count(1)),
+        ],
+        None,
+    )))
+}
+
+fn uptime(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let uptime = to_crush_error(fs::read_to_string("/proc/uptime"))?;
+    let seconds = to_crush_error(
+        mandate(uptime.split_whitespace().next(), "Invalid format of /proc/uptime")?.parse::<f64>())?;
+    context.output.send(Value::Duration(Duration::microseconds((seconds * 1_000_000.0) as i64)))
+}
+
+fn mounts(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("device", ValueType::String),
+        ColumnType::new("mountpoint", ValueType::File),
+        ColumnType::new("type", ValueType::String),
+        ColumnType::new("options", ValueType::String),
+    ])?;
+    for mount in read_mounts()? {
+        output.send(Row::new(vec![
+            Value::string(mount.device.as_str()),
+            Value::File(Box::from(Path::new(mount.mountpoint.as_str()))),
+            Value::string(mount.fs_type.as_str()),
+            Value::string(mount.options.as_str()),
+        ]))?;
+    }
+    Ok(())
+}
+
+fn df(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("device", ValueType::String),
+        ColumnType::new("mountpoint", ValueType::File),
+        ColumnType::new("type", ValueType::String),
+        ColumnType::new("size", ValueType::Integer),
+        ColumnType::new("used", ValueType::Integer),
+        ColumnType::new("available", ValueType::Integer),
+        ColumnType::new("inodes", ValueType::Integer),
+        ColumnType::new("inodes_free", ValueType::Integer),
+    ])?;
+    for mount in read_mounts()? {
+        /* Mounts we can't stat, e.g. because of permissions, are silently skipped, like df does. */
+        if let Ok(stat) = statvfs(Path::new(mount.mountpoint.as_str())) {
+            if stat.blocks() == 0 {
+                continue;
+            }
+            let block_size = stat.fragment_size() as i128;
+            output.send(Row::new(vec![
+                Value::string(mount.device.as_str()),
+                Value::File(Box::from(Path::new(mount.mountpoint.as_str()))),
+                Value::string(mount.fs_type.as_str()),
+                Value::Integer(stat.blocks() as i128 * block_size),
+                Value::Integer((stat.blocks() - stat.blocks_free()) as i128 * block_size),
+                Value::Integer(stat.blocks_available() as i128 * block_size),
+                Value::Integer(stat.files() as i128),
+                Value::Integer(stat.files_free() as i128),
+            ]))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn declare(root: &Scope) -> CrushResult<()> {
+    let env = root.create_namespace("sys")?;
+    env.declare("mem", Value::Command(CrushCommand::command(
+        mem, false,
+        "sys:mem", "Return a struct with information about memory usage",
+        Some(r#"    All values are in bytes. The struct contains the following fields:
+
+    * total:integer the total amount of physical memory
+    * free:integer physical memory that is not used for anything
+    * available:integer memory available for starting new applications
+    * buffers:integer memory used for block device buffers
+    * cached:integer memory used for the page cache
+    * swap_total:integer the total amount of swap space
+    * swap_free:integer swap space that is not in use"#))))?;
+    env.declare("cpus", Value::Command(CrushCommand::command(
+        cpus, true,
+        "sys:cpus", "Return a table stream with one row per CPU core",
+        Some(r#"    Each row contains the following columns:
+
+    * core:integer the number of the core
+    * model:string the model name of the CPU
+    * mhz:float the current clock frequency of the core
+    * user:duration time spent executing in user mode
+    * nice:duration time spent executing low priority processes in user mode
+    * system:duration time spent executing in kernel mode
+    * idle:duration time spent idle
+    * iowait:duration time spent waiting for I/O to complete"#))))?;
+    env.declare("load", Value::Command(CrushCommand::command(
+        load, false,
+        "sys:load", "Return a struct with the system load averages",
+        Some(r#"    The struct contains the following fields:
+
+    * one:float the load average over the last minute
+    * five:float the load average over the last five minutes
+    * fifteen:float the load average over the last fifteen minutes
+    * running:integer the number of currently runnable tasks
+    * tasks:integer the total number of tasks"#))))?;
+    env.declare("uptime", Value::Command(CrushCommand::command(
+        uptime, false,
+        "sys:uptime", "Return the amount of time since the system was started", None)))?;
+    env.declare("mounts", Value::Command(CrushCommand::command(
+        mounts, true,
+        "sys:mounts", "Return a table stream of all mounted file systems",
+        Some(r#"    Each row contains the following columns:
+
+    * device:string the mounted device
+    * mountpoint:file the directory where the device is mounted
+    * type:string the file system type
+    * options:string the mount options"#))))?;
+    env.declare("df", Value::Command(CrushCommand::command(
+        df, true,
+        "sys:df", "Return a table stream with the disk usage of all mounted file systems",
+        Some(r#"    File systems without any blocks, like proc and sysfs, are not included.
+    All sizes are in bytes. Each row contains the following columns:
+
+    * device:string the mounted device
+    * mountpoint:file the directory where the device is mounted
+    * type:string the file system type
+    * size:integer the total size of the file system
+    * used:integer the number of bytes in use
+    * available:integer the number of bytes available to unprivileged users
+    * inodes:integer the total number of inodes
+    * inodes_free:integer the number of free inodes
+
+    Example:
+
+    sys:df | where {used * 10 > size * 9}"#))))?;
+    env.readonly();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_fields_are_unescaped() {
+        assert_eq!(unescape_mount_field("/mnt/my\\040disk"), "/mnt/my disk");
+        assert_eq!(unescape_mount_field("/mnt/tab\\011"), "/mnt/tab\t");
+        assert_eq!(unescape_mount_field("/mnt/plain"), "/mnt/plain");
+        assert_eq!(unescape_mount_field("/mnt/odd\\x"), "/mnt/odd\\x");
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{argument_error, CrushResult};
+use crate::lang::{argument::Argument, table::ColumnType, table::Row, value::Value, value::ValueType};
+use super::walk::{walk, Options, Visit};
+
+#[derive(Default, Clone, Copy)]
+struct Usage {
+    size: u64,
+    blocks: u64,
+    count: u64,
+}
+
+impl Usage {
+    fn add(&mut self, meta: &Metadata) {
+        self.size += meta.len();
+        self.blocks += meta.blocks();
+        self.count += 1;
+    }
+}
+
+struct Config {
+    files: Vec<Box<Path>>,
+    depth: usize,
+    options: Options,
+}
+
+fn parse(arguments: Vec<Argument>) -> CrushResult<Config> {
+    let mut files = Vec::new();
+    let mut depth = 0;
+    let mut options = Options::new(None);
+    for arg in arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, value) => value.file_expand(&mut files)?,
+            (Some("depth"), Value::Integer(d)) if d >= 0 => depth = d as usize,
+            (Some("depth"), _) => return argument_error("depth must be a non-negative integer"),
+            (Some("max_depth"), _) | (Some("min_depth"), _) | (Some("sort"), _) =>
+                return argument_error("du does not support the max_depth, min_depth and sort arguments"),
+            (Some(name), value) => options.set(name, value)?,
+        }
+    }
+    if files.is_empty() {
+        files.push(Box::from(Path::new(".")));
+    }
+    Ok(Config { files, depth, options })
+}
+
+/**
+    Files with more than one hard link are only counted the first time they are seen.
+*/
+fn first_link(seen: &Mutex<HashSet<(u64, u64)>>, meta: &Metadata) -> bool {
+    meta.nlink() <= 1 || meta.is_dir() || seen.lock().unwrap().insert((meta.dev(), meta.ino()))
+}
+
+/**
+    Add the usage of a file to the totals of every reported directory it is inside of, and
+    to its own total if it is a reported directory itself.
+*/
+fn add_usage(
+    totals: &Mutex<HashMap<PathBuf, Usage>>,
+    root: &Path,
+    relative: &Path,
+    meta: &Metadata,
+    depth: usize) {
+    let components = relative.components().collect::<Vec<_>>();
+    let mut totals = totals.lock().unwrap();
+    let mut key = root.to_path_buf();
+    totals.entry(key.clone()).or_default().add(meta);
+    for (idx, component) in components.iter().enumerate().take(depth) {
+        if idx == components.len() - 1 && !meta.is_dir() {
+            break;
+        }
+        key.push(component);
+        totals.entry(key.clone()).or_default().add(meta);
+    }
+}
+
+pub fn perform(context: ExecutionContext) -> CrushResult<()> {
+    let cfg = parse(context.arguments)?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("file", ValueType::File),
+        ColumnType::new("size", ValueType::Integer),
+        ColumnType::new("blocks", ValueType::Integer),
+        ColumnType::new("count", ValueType::Integer),
+    ])?;
+
+    let seen = Mutex::new(HashSet::new());
+    let totals = Mutex::new(HashMap::new());
+    for root in &cfg.files {
+        if let Ok(meta) = root.metadata() {
+            if meta.is_dir() && first_link(&seen, &meta) {
+                totals.lock().unwrap().entry(root.to_path_buf()).or_insert_with(Usage::default).add(&meta);
+            }
+        }
+        walk(&cfg.options, vec![root.clone()], &|path: &Path, meta: &Metadata, _depth: usize| {
+            if first_link(&seen, meta) {
+                add_usage(&totals, root, path.strip_prefix(root).unwrap_or(Path::new("")), meta, cfg.depth);
+            }
+            Ok(Visit::Descend)
+        })?;
+    }
+
+    let mut totals = totals.into_inner().unwrap().into_iter().collect::<Vec<_>>();
+    totals.sort_by(|a, b| a.0.cmp(&b.0));
+    for (file, usage) in totals {
+        let file = match file.strip_prefix(".") {
+            Ok(f) if !f.as_os_str().is_empty() => f.to_path_buf(),
+            _ => file,
+        };
+        output.send(Row::new(vec![
+            Value::File(file.into_boxed_path()),
+            Value::Integer(usage.size as i128),
+            Value::Integer(usage.blocks as i128),
+            Value::Integer(usage.count as i128),
+        ]))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::lang::scope::Scope;
+    use crate::lang::stream::{channels, empty_channel};
+
+    /**
+        Run du on the specified root and return the file name and count of every row.
+    */
+    fn du(root: &Path, mut arguments: Vec<Argument>) -> Vec<(String, i128)> {
+        arguments.push(Argument::unnamed(Value::File(Box::from(root))));
+        let (sender, receiver) = channels();
+        let context = ExecutionContext { input: empty_channel(), output: sender, arguments, env: Scope::new(), this: None };
+        let worker = std::thread::spawn(move || perform(context));
+        let mut res = Vec::new();
+        if let Value::TableStream(rows) = receiver.recv().unwrap() {
+            while let Ok(row) = rows.recv() {
+                match (&row.cells()[0], &row.cells()[3]) {
+                    (Value::File(f), Value::Integer(count)) =>
+                        res.push((f.strip_prefix(root).unwrap().to_string_lossy().to_string(), *count)),
+                    _ => panic!("Unexpected row"),
+                }
+            }
+        }
+        worker.join().unwrap().unwrap();
+        res
+    }
+
+    fn tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("crush-du-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a"), "").unwrap();
+        fs::write(root.join("sub/b"), "").unwrap();
+        fs::write(root.join("sub/c"), "").unwrap();
+        root
+    }
+
+    #[test]
+    fn usage_is_reported_down_to_the_depth() {
+        let root = tree("depth");
+        assert_eq!(du(&root, vec![]), vec![("".to_string(), 5)]);
+        assert_eq!(
+            du(&root, vec![Argument::named("depth", Value::Integer(1))]),
+            vec![("".to_string(), 5), ("sub".to_string(), 3)]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn hard_links_are_counted_once() {
+        let root = tree("links");
+        fs::write(root.join("data"), "0123456789").unwrap();
+        fs::hard_link(root.join("data"), root.join("sub/link")).unwrap();
+        /* With a single thread the walk is breadth first, so the link in sub is the second one seen. */
+        let totals = du(&root, vec![
+            Argument::named("depth", Value::Integer(1)),
+            Argument::named("threads", Value::Integer(1)),
+        ]);
+        assert_eq!(totals, vec![("".to_string(), 6), ("sub".to_string(), 3)]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn unsupported_arguments_are_rejected() {
+        assert!(parse(vec![Argument::named("depth", Value::Integer(-1))]).is_err());
+        assert!(parse(vec![Argument::named("sort", Value::Bool(true))]).is_err());
+    }
+}
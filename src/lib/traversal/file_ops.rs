@@ -0,0 +1,643 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nix::sys::stat::utimes;
+use nix::sys::time::{TimeVal, TimeValLike};
+
+use crate::lang::argument::Argument;
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{argument_error, error, to_crush_error, CrushResult};
+use crate::lang::{table::ColumnType, table::Row, value::Value, value::ValueType};
+use crate::lang::stream::{OutputStream, ValueReceiver};
+use super::find::{mode_struct, permissions};
+
+/**
+    The arguments accepted by a file manipulation command, apart from the files.
+*/
+struct Spec {
+    flags: &'static [&'static str],
+    /** Whether the last unnamed argument is the destination, as for cp, mv and ln. */
+    destination: bool,
+    /** Whether the first unnamed argument is a mode, as for chmod. */
+    mode: bool,
+}
+
+#[derive(Default)]
+struct Args {
+    files: Vec<Box<Path>>,
+    destination: Option<Box<Path>>,
+    mode: Option<String>,
+    recursive: bool,
+    force: bool,
+    dry_run: bool,
+    symbolic: bool,
+}
+
+/**
+    Parse the arguments of a file manipulation command. If no files are given as arguments,
+    they are read from the input, which must be a table stream with a column of type file,
+    e.g. the output of find.
+*/
+fn parse(arguments: Vec<Argument>, input: ValueReceiver, spec: &Spec) -> CrushResult<Args> {
+    let mut res = Args::default();
+    let mut unnamed = Vec::new();
+    for arg in arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, value) => unnamed.push(value),
+            (Some(name), Value::Bool(b)) if spec.flags.iter().any(|f| *f == name) => match name {
+                "recursive" => res.recursive = b,
+                "force" => res.force = b,
+                "dry_run" => res.dry_run = b,
+                "symbolic" => res.symbolic = b,
+                _ => return argument_error("Unknown argument"),
+            },
+            (Some("destination"), value) if spec.destination => {
+                let mut destination = Vec::new();
+                value.file_expand(&mut destination)?;
+                if destination.len() != 1 {
+                    return argument_error("Expected exactly one destination");
+                }
+                res.destination = Some(destination.remove(0));
+            }
+            (Some("mode"), Value::String(mode)) if spec.mode => res.mode = Some(mode.to_string()),
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+
+    if spec.mode && res.mode.is_none() {
+        if unnamed.is_empty() {
+            return argument_error("Missing mode");
+        }
+        match unnamed.remove(0) {
+            Value::String(mode) => res.mode = Some(mode.to_string()),
+            _ => return argument_error("Expected the mode to be a string"),
+        }
+    }
+    if spec.destination && res.destination.is_none() {
+        match unnamed.pop() {
+            Some(value) => {
+                let mut destination = Vec::new();
+                value.file_expand(&mut destination)?;
+                if destination.len() != 1 {
+                    return argument_error("Expected exactly one destination");
+                }
+                res.destination = Some(destination.remove(0));
+            }
+            None => return argument_error("Missing destination"),
+        }
+    }
+
+    if unnamed.is_empty() {
+        input.recv()?.file_expand(&mut res.files)?;
+    } else {
+        for value in unnamed {
+            value.file_expand(&mut res.files)?;
+        }
+    }
+    if res.files.is_empty() {
+        return argument_error("No files specified");
+    }
+    Ok(res)
+}
+
+fn file_value(path: &Path) -> Value {
+    Value::File(Box::from(path))
+}
+
+fn send(output: &OutputStream, action: &str, cells: Vec<Value>) -> CrushResult<()> {
+    let mut row = vec![Value::string(action)];
+    row.extend(cells);
+    output.send(Row::new(row))
+}
+
+/**
+    Like send, but for operations that are sometimes performed without reporting them.
+*/
+fn report(output: Option<&OutputStream>, action: &str, cells: Vec<Value>) -> CrushResult<()> {
+    match output {
+        Some(output) => send(output, action, cells),
+        None => Ok(()),
+    }
+}
+
+fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+fn path_error<T>(path: &Path, err: std::io::Error) -> CrushResult<T> {
+    error(format!("{}: {}", path.to_string_lossy(), err).as_str())
+}
+
+/**
+    Where a source file ends up. If the destination is a directory, the file is placed
+    inside of it, otherwise the destination is the new name of the file, which only makes
+    sense for a single source.
+*/
+fn target(source: &Path, destination: &Path, sources: usize) -> CrushResult<PathBuf> {
+    if destination.is_dir() {
+        match source.file_name() {
+            Some(name) => Ok(destination.join(name)),
+            None => argument_error(format!("Invalid file name {}", source.to_string_lossy()).as_str()),
+        }
+    } else if sources > 1 {
+        argument_error("The destination must be a directory when there are multiple files")
+    } else {
+        Ok(destination.to_path_buf())
+    }
+}
+
+fn check_overwrite(target: &Path, args: &Args) -> CrushResult<()> {
+    if exists(target) && !args.force {
+        argument_error(format!("{} already exists, use force=true to overwrite it", target.to_string_lossy()).as_str())
+    } else {
+        Ok(())
+    }
+}
+
+fn canonical(path: &Path) -> Option<PathBuf> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() =>
+            parent.canonicalize().ok().and_then(|p| path.file_name().map(|n| p.join(n))),
+        _ => std::env::current_dir().ok().map(|d| d.join(path)),
+    }
+}
+
+fn remove(path: &Path, args: &Args, output: Option<&OutputStream>) -> CrushResult<()> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(_) if args.force => return Ok(()),
+        Err(e) => return path_error(path, e),
+    };
+    if meta.is_dir() {
+        if !args.recursive {
+            return argument_error(
+                format!("{} is a directory, use recursive=true to remove it", path.to_string_lossy()).as_str());
+        }
+        for entry in to_crush_error(fs::read_dir(path))? {
+            remove(&to_crush_error(entry)?.path(), args, output)?;
+        }
+        if !args.dry_run {
+            if let Err(e) = fs::remove_dir(path) {
+                return path_error(path, e);
+            }
+        }
+    } else if !args.dry_run {
+        if let Err(e) = fs::remove_file(path) {
+            return path_error(path, e);
+        }
+    }
+    report(output, "remove", vec![file_value(path)])
+}
+
+pub fn rm(context: ExecutionContext) -> CrushResult<()> {
+    let args = parse(context.arguments, context.input, &Spec {
+        flags: &["recursive", "force", "dry_run"],
+        destination: false,
+        mode: false,
+    })?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("action", ValueType::String),
+        ColumnType::new("file", ValueType::File),
+    ])?;
+    /*
+        When the input comes from e.g. find, a directory is followed by its contents, which are
+        already gone once the directory has been removed recursively.
+    */
+    let mut removed_dirs: Vec<&Path> = Vec::new();
+    for file in &args.files {
+        if file.parent().is_none() || file.ends_with(".") || file.ends_with("..") {
+            return argument_error(format!("Refusing to remove {}", file.to_string_lossy()).as_str());
+        }
+        if already_removed(file, &removed_dirs, args.dry_run) {
+            continue;
+        }
+        let is_dir = fs::symlink_metadata(file).map(|m| m.is_dir()).unwrap_or(false);
+        remove(file, &args, Some(&output))?;
+        if is_dir {
+            removed_dirs.push(file);
+        }
+    }
+    Ok(())
+}
+
+/**
+    True if the path was inside of a directory that has been removed. In a dry run nothing
+    is actually removed, so the path is skipped even if it still exists.
+*/
+fn already_removed(path: &Path, removed_dirs: &[&Path], dry_run: bool) -> bool {
+    removed_dirs.iter().any(|d| path.starts_with(d)) && (dry_run || !exists(path))
+}
+
+pub fn mkdir(context: ExecutionContext) -> CrushResult<()> {
+    let args = parse(context.arguments, context.input, &Spec {
+        flags: &["recursive", "force", "dry_run"],
+        destination: false,
+        mode: false,
+    })?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("action", ValueType::String),
+        ColumnType::new("file", ValueType::File),
+    ])?;
+    for dir in &args.files {
+        if dir.is_dir() && (args.recursive || args.force) {
+            continue;
+        }
+        if exists(dir) && !dir.is_dir() {
+            return argument_error(format!("{} exists and is not a directory", dir.to_string_lossy()).as_str());
+        }
+        let mut missing = if args.recursive {
+            dir.ancestors()
+                .take_while(|d| !d.as_os_str().is_empty() && !exists(d))
+                .collect::<Vec<_>>()
+        } else {
+            vec![&**dir]
+        };
+        missing.reverse();
+        for d in missing {
+            if !args.dry_run {
+                if let Err(e) = fs::create_dir(d) {
+                    return path_error(d, e);
+                }
+            }
+            send(&output, "create", vec![file_value(d)])?;
+        }
+    }
+    Ok(())
+}
+
+pub fn touch(context: ExecutionContext) -> CrushResult<()> {
+    let args = parse(context.arguments, context.input, &Spec {
+        flags: &["dry_run"],
+        destination: false,
+        mode: false,
+    })?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("action", ValueType::String),
+        ColumnType::new("file", ValueType::File),
+    ])?;
+    let now = to_crush_error(SystemTime::now().duration_since(UNIX_EPOCH))?;
+    let now = TimeVal::microseconds(now.as_micros() as i64);
+    for file in &args.files {
+        if exists(file) {
+            if !args.dry_run {
+                to_crush_error(utimes(&**file, &now, &now))?;
+            }
+            send(&output, "touch", vec![file_value(file)])?;
+        } else {
+            if !args.dry_run {
+                if let Err(e) = OpenOptions::new().create(true).write(true).open(file) {
+                    return path_error(file, e);
+                }
+            }
+            send(&output, "create", vec![file_value(file)])?;
+        }
+    }
+    Ok(())
+}
+
+fn source_destination_columns() -> Vec<ColumnType> {
+    vec![
+        ColumnType::new("action", ValueType::String),
+        ColumnType::new("source", ValueType::File),
+        ColumnType::new("destination", ValueType::File),
+    ]
+}
+
+fn copy(source: &Path, destination: &Path, args: &Args, output: Option<&OutputStream>) -> CrushResult<()> {
+    let meta = match fs::symlink_metadata(source) {
+        Ok(meta) => meta,
+        Err(e) => return path_error(source, e),
+    };
+    if meta.is_dir() {
+        if !args.recursive {
+            return argument_error(
+                format!("{} is a directory, use recursive=true to copy it", source.to_string_lossy()).as_str());
+        }
+        if let (Ok(s), Some(d)) = (source.canonicalize(), canonical(destination)) {
+            if d.starts_with(&s) {
+                return argument_error(
+                    format!("Can't copy {} into itself", source.to_string_lossy()).as_str());
+            }
+        }
+        if !args.dry_run && !destination.is_dir() {
+            if let Err(e) = fs::create_dir(destination) {
+                return path_error(destination, e);
+            }
+            to_crush_error(fs::set_permissions(destination, meta.permissions()))?;
+        }
+        report(output, "copy", vec![file_value(source), file_value(destination)])?;
+        for entry in to_crush_error(fs::read_dir(source))? {
+            let entry = to_crush_error(entry)?;
+            copy(&entry.path(), &destination.join(entry.file_name()), args, output)?;
+        }
+        Ok(())
+    } else {
+        check_overwrite(destination, args)?;
+        if !args.dry_run {
+            if meta.file_type().is_symlink() {
+                let link = to_crush_error(fs::read_link(source))?;
+                if exists(destination) {
+                    to_crush_error(fs::remove_file(destination))?;
+                }
+                if let Err(e) = std::os::unix::fs::symlink(link, destination) {
+                    return path_error(destination, e);
+                }
+            } else if let Err(e) = fs::copy(source, destination) {
+                return path_error(destination, e);
+            }
+        }
+        report(output, "copy", vec![file_value(source), file_value(destination)])
+    }
+}
+
+pub fn cp(context: ExecutionContext) -> CrushResult<()> {
+    let args = parse(context.arguments, context.input, &Spec {
+        flags: &["recursive", "force", "dry_run"],
+        destination: true,
+        mode: false,
+    })?;
+    let output = context.output.initialize(source_destination_columns())?;
+    let destination = args.destination.clone().unwrap();
+    for file in &args.files {
+        copy(file, &target(file, &destination, args.files.len())?, &args, Some(&output))?;
+    }
+    Ok(())
+}
+
+/** The error returned by rename when moving a file to another file system. */
+const EXDEV: i32 = 18;
+
+pub fn mv(context: ExecutionContext) -> CrushResult<()> {
+    let args = parse(context.arguments, context.input, &Spec {
+        flags: &["force", "dry_run"],
+        destination: true,
+        mode: false,
+    })?;
+    let output = context.output.initialize(source_destination_columns())?;
+    let destination = args.destination.clone().unwrap();
+    for file in &args.files {
+        let target = target(file, &destination, args.files.len())?;
+        check_overwrite(&target, &args)?;
+        if !args.dry_run {
+            match fs::rename(file, &target) {
+                Ok(_) => {}
+                Err(e) if e.raw_os_error() == Some(EXDEV) => {
+                    /* Fall back to copying and removing. Only the move itself is reported. */
+                    let copy_args = Args { recursive: true, force: true, ..Args::default() };
+                    copy(file, &target, &copy_args, None)?;
+                    remove(file, &copy_args, None)?;
+                }
+                Err(e) => return path_error(file, e),
+            }
+        }
+        send(&output, "move", vec![file_value(file), file_value(&target)])?;
+    }
+    Ok(())
+}
+
+pub fn ln(context: ExecutionContext) -> CrushResult<()> {
+    let args = parse(context.arguments, context.input, &Spec {
+        flags: &["symbolic", "force", "dry_run"],
+        destination: true,
+        mode: false,
+    })?;
+    let output = context.output.initialize(source_destination_columns())?;
+    let destination = args.destination.clone().unwrap();
+    for file in &args.files {
+        let target = target(file, &destination, args.files.len())?;
+        check_overwrite(&target, &args)?;
+        if !args.dry_run {
+            if exists(&target) {
+                to_crush_error(fs::remove_file(&target))?;
+            }
+            let res = if args.symbolic {
+                std::os::unix::fs::symlink(file, &target)
+            } else {
+                fs::hard_link(file, &target)
+            };
+            if let Err(e) = res {
+                return path_error(&target, e);
+            }
+        }
+        send(&output, if args.symbolic { "symlink" } else { "link" }, vec![file_value(file), file_value(&target)])?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Absolute(u32),
+    /** A list of clauses like u+x, each a set of affected bits and a list of operations. */
+    Symbolic(Vec<(u32, Vec<(char, String)>)>),
+}
+
+fn parse_ls_mode(s: &str) -> Option<u32> {
+    let chars = s.chars().collect::<Vec<_>>();
+    if chars.len() != 9 {
+        return None;
+    }
+    let mut mode = 0;
+    for (idx, c) in chars.iter().enumerate() {
+        let shift = 3 * (2 - idx as u32 / 3);
+        let special = [0o4000, 0o2000, 0o1000][idx / 3];
+        mode |= match (idx % 3, *c) {
+            (_, '-') => 0,
+            (0, 'r') => 4 << shift,
+            (1, 'w') => 2 << shift,
+            (2, 'x') => 1 << shift,
+            (2, 's') if idx < 6 => special | 1 << shift,
+            (2, 'S') if idx < 6 => special,
+            (2, 't') if idx == 8 => special | 1,
+            (2, 'T') if idx == 8 => special,
+            _ => return None,
+        };
+    }
+    Some(mode)
+}
+
+fn parse_mode(s: &str) -> CrushResult<Mode> {
+    if !s.is_empty() && s.len() <= 4 && s.chars().all(|c| c >= '0' && c <= '7') {
+        return Ok(Mode::Absolute(u32::from_str_radix(s, 8).unwrap()));
+    }
+    if let Some(mode) = parse_ls_mode(s) {
+        return Ok(Mode::Absolute(mode));
+    }
+    let mut clauses = Vec::new();
+    for clause in s.split(',') {
+        let mut who = 0;
+        let mut chars = clause.chars().peekable();
+        while let Some(c) = chars.peek() {
+            who |= match *c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => break,
+            };
+            chars.next();
+        }
+        if who == 0 {
+            who = 0o7777;
+        }
+        let mut operations = Vec::new();
+        while let Some(op) = chars.next() {
+            if op != '+' && op != '-' && op != '=' {
+                return argument_error(format!("Invalid mode {}", s).as_str());
+            }
+            let mut permissions = String::new();
+            while let Some(c) = chars.peek() {
+                match *c {
+                    'r' | 'w' | 'x' | 'X' | 's' | 't' => permissions.push(*c),
+                    _ => break,
+                }
+                chars.next();
+            }
+            operations.push((op, permissions));
+        }
+        if operations.is_empty() {
+            return argument_error(format!("Invalid mode {}", s).as_str());
+        }
+        clauses.push((who, operations));
+    }
+    Ok(Mode::Symbolic(clauses))
+}
+
+fn apply_mode(mode: &Mode, current: u32, is_dir: bool) -> u32 {
+    match mode {
+        Mode::Absolute(m) => *m,
+        Mode::Symbolic(clauses) => {
+            let mut res = current & 0o7777;
+            for (who, operations) in clauses {
+                for (op, permissions) in operations {
+                    let mut bits = 0;
+                    for c in permissions.chars() {
+                        bits |= match c {
+                            'r' => 0o444,
+                            'w' => 0o222,
+                            'x' => 0o111,
+                            'X' if is_dir || current & 0o111 != 0 => 0o111,
+                            's' => 0o6000,
+                            't' => 0o1000,
+                            _ => 0,
+                        };
+                    }
+                    bits &= who;
+                    res = match op {
+                        '+' => res | bits,
+                        '-' => res & !bits,
+                        _ => (res & !who) | bits,
+                    };
+                }
+            }
+            res
+        }
+    }
+}
+
+fn chmod_file(path: &Path, mode: &Mode, args: &Args, output: &OutputStream) -> CrushResult<()> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) => return path_error(path, e),
+    };
+    /* Changing the mode of a symlink changes its target, which may be outside of the tree. */
+    if meta.file_type().is_symlink() {
+        return Ok(());
+    }
+    let new_mode = apply_mode(mode, meta.permissions().mode(), meta.is_dir());
+    if !args.dry_run {
+        if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(new_mode)) {
+            return path_error(path, e);
+        }
+    }
+    send(output, "chmod", vec![file_value(path), permissions(new_mode), mode_struct(new_mode)])?;
+    if meta.is_dir() && args.recursive {
+        for entry in to_crush_error(fs::read_dir(path))? {
+            chmod_file(&to_crush_error(entry)?.path(), mode, args, output)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn chmod(context: ExecutionContext) -> CrushResult<()> {
+    let args = parse(context.arguments, context.input, &Spec {
+        flags: &["recursive", "dry_run"],
+        destination: false,
+        mode: true,
+    })?;
+    let mode = parse_mode(args.mode.as_ref().unwrap())?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("action", ValueType::String),
+        ColumnType::new("file", ValueType::File),
+        ColumnType::new("permissions", ValueType::String),
+        ColumnType::new("mode", ValueType::Struct),
+    ])?;
+    for file in &args.files {
+        chmod_file(file, &mode, &args, &output)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(mode: &str, current: u32, is_dir: bool) -> u32 {
+        apply_mode(&parse_mode(mode).unwrap(), current, is_dir)
+    }
+
+    #[test]
+    fn contents_of_removed_directories_are_skipped() {
+        let root = std::env::temp_dir().join(format!("crush-rm-{}", std::process::id()));
+        let removed = root.join("gone");
+        let dirs = vec![removed.as_path()];
+        assert!(already_removed(&removed.join("child"), &dirs, false));
+        assert!(already_removed(&removed.join("child"), &dirs, true));
+        assert!(!already_removed(&root.join("other"), &dirs, false));
+        fs::create_dir_all(&removed).unwrap();
+        fs::write(removed.join("child"), "").unwrap();
+        assert!(!already_removed(&removed.join("child"), &dirs, false));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn octal_modes_are_absolute() {
+        assert_eq!(parse_mode("755").unwrap(), Mode::Absolute(0o755));
+        assert_eq!(parse_mode("0644").unwrap(), Mode::Absolute(0o644));
+        assert_eq!(parse_mode("4755").unwrap(), Mode::Absolute(0o4755));
+    }
+
+    #[test]
+    fn ls_style_modes_are_absolute() {
+        assert_eq!(parse_mode("rwxr-xr-x").unwrap(), Mode::Absolute(0o755));
+        assert_eq!(parse_mode("rw-r-----").unwrap(), Mode::Absolute(0o640));
+        assert_eq!(parse_mode("rwsr-xr-x").unwrap(), Mode::Absolute(0o4755));
+        assert_eq!(parse_mode("rwxrwxrwt").unwrap(), Mode::Absolute(0o1777));
+    }
+
+    #[test]
+    fn symbolic_modes_modify_the_current_mode() {
+        assert_eq!(apply("u+x", 0o644, false), 0o744);
+        assert_eq!(apply("go-w", 0o666, false), 0o644);
+        assert_eq!(apply("a=r", 0o755, false), 0o444);
+        assert_eq!(apply("+x", 0o644, false), 0o755);
+        assert_eq!(apply("u+rw,o-r", 0o404, false), 0o600);
+        assert_eq!(apply("u=rwx-w", 0o000, false), 0o500);
+    }
+
+    #[test]
+    fn capital_x_only_applies_to_directories_and_executables() {
+        assert_eq!(apply("a+X", 0o644, false), 0o644);
+        assert_eq!(apply("a+X", 0o744, false), 0o755);
+        assert_eq!(apply("a+X", 0o644, true), 0o755);
+    }
+
+    #[test]
+    fn invalid_modes_are_rejected() {
+        assert!(parse_mode("u+q").is_err());
+        assert!(parse_mode("u").is_err());
+        assert!(parse_mode("888").is_err());
+    }
+}
@@ -1,140 +1,407 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::fs;
-use std::fs::Metadata;
-use std::os::unix::fs::MetadataExt;
+use std::fs::{FileType, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use chrono::{DateTime, Local};
-use users::uid_t;
+use users::{gid_t, uid_t};
 use users::User;
 
+use crate::lang::execution_context::{ArgumentVector, ExecutionContext, This};
+use crate::lang::r#struct::{Struct, ROOT};
 use lazy_static::lazy_static;
-
-use crate::lang::execution_context::{ExecutionContext, ArgumentVector};
-use crate::util::user_map::{create_user_map, UserMap};
+use crate::util::user_map::{create_user_map, create_group_map, UserMap, GroupMap, GroupEntry};
 use crate::lang::{argument::Argument, value::Value, value::ValueType, table::ColumnType, table::Row};
-use crate::lang::errors::{error, CrushError, CrushResult, to_crush_error};
-use crate::lang::stream::OutputStream;
+use crate::lang::errors::{argument_error, error, CrushError, CrushResult, to_crush_error};
+use crate::lang::stream::{ValueSender, channels, empty_channel};
+use crate::lang::command::CrushCommand;
+use crate::lang::scope::Scope;
+use super::walk::{walk, Options, Visit};
 
-lazy_static! {
-    static ref OUTPUT_TYPE: Vec<ColumnType> = vec![
-        ColumnType::new("user", ValueType::String),
-        ColumnType::new("size", ValueType::Integer),
-        ColumnType::new("modified", ValueType::Time),
-        ColumnType::new("type", ValueType::String),
-        ColumnType::new("file", ValueType::File),
-    ];
+/**
+    All columns that find and ls can output, in output order.
+*/
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Column {
+    Permissions,
+    Mode,
+    Inode,
+    Nlink,
+    User,
+    Group,
+    Size,
+    Modified,
+    Accessed,
+    Created,
+    Device,
+    Type,
+    SymlinkTarget,
+    File,
 }
 
-fn insert_entity(
-    meta: &Metadata,
-    file: Box<Path>,
-    users: &HashMap<uid_t, User>,
-    output: &mut OutputStream) -> CrushResult<()> {
-    let modified_system = to_crush_error(meta.modified())?;
-    let modified_datetime: DateTime<Local> = DateTime::from(modified_system);
-    let f = if file.starts_with("./") {
-        let b = file.to_str().map(|s| Box::from(Path::new(&s[2..])));
-        b.unwrap_or(file)
-    } else {
-        file
-    };
-    let file_type = meta.file_type();
-    let ftype = if file_type.is_dir() {
+const ALL_COLUMNS: [Column; 14] = [
+    Column::Permissions, Column::Mode, Column::Inode, Column::Nlink, Column::User, Column::Group,
+    Column::Size, Column::Modified, Column::Accessed, Column::Created, Column::Device,
+    Column::Type, Column::SymlinkTarget, Column::File,
+];
+
+impl Column {
+    fn name(&self) -> &'static str {
+        match self {
+            Column::Permissions => "permissions",
+            Column::Mode => "mode",
+            Column::Inode => "inode",
+            Column::Nlink => "nlink",
+            Column::User => "user",
+            Column::Group => "group",
+            Column::Size => "size",
+            Column::Modified => "modified",
+            Column::Accessed => "accessed",
+            Column::Created => "created",
+            Column::Device => "device",
+            Column::Type => "type",
+            Column::SymlinkTarget => "symlink_target",
+            Column::File => "file",
+        }
+    }
+
+    fn value_type(&self) -> ValueType {
+        match self {
+            Column::Inode | Column::Nlink | Column::Size | Column::Device => ValueType::Integer,
+            Column::Modified | Column::Accessed | Column::Created => ValueType::Time,
+            Column::File => ValueType::File,
+            Column::Mode => ValueType::Struct,
+            Column::Permissions | Column::User | Column::Group | Column::Type | Column::SymlinkTarget =>
+                ValueType::String,
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        match self {
+            Column::User | Column::Group | Column::Size | Column::Modified | Column::Type |
+            Column::File => true,
+            _ => false,
+        }
+    }
+
+    fn parse(name: &str) -> CrushResult<Column> {
+        match ALL_COLUMNS.iter().find(|c| c.name() == name) {
+            Some(c) => Ok(*c),
+            None => argument_error(format!("Unknown column {}", name).as_str()),
+        }
+    }
+}
+
+fn column_types(columns: &Vec<Column>) -> Vec<ColumnType> {
+    columns.iter()
+        .map(|c| ColumnType::new(c.name(), c.value_type()))
+        .collect()
+}
+
+fn file_type_name(file_type: &FileType) -> &'static str {
+    if file_type.is_dir() {
         "directory"
+    } else if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_fifo() {
+        "fifo"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_block_device() {
+        "block_device"
+    } else if file_type.is_char_device() {
+        "char_device"
     } else {
-        if file_type.is_symlink() {
-            "symlink"
-        } else {
-            "file"
+        "file"
+    }
+}
+
+/**
+    Format the permission bits of a mode the way ls does, e.g. rwxr-xr-x, including the
+    setuid, setgid and sticky bits.
+*/
+pub fn permission_string(mode: u32) -> String {
+    let special = |bit: u32, exec: bool, set: char, unset: char| {
+        match (mode & bit != 0, exec) {
+            (true, true) => set,
+            (true, false) => unset,
+            (false, true) => 'x',
+            (false, false) => '-',
         }
     };
+    let flag = |bit: u32, c: char| if mode & bit != 0 { c } else { '-' };
+    vec![
+        flag(0o400, 'r'), flag(0o200, 'w'), special(0o4000, mode & 0o100 != 0, 's', 'S'),
+        flag(0o040, 'r'), flag(0o020, 'w'), special(0o2000, mode & 0o010 != 0, 's', 'S'),
+        flag(0o004, 'r'), flag(0o002, 'w'), special(0o1000, mode & 0o001 != 0, 't', 'T'),
+    ].into_iter().collect()
+}
 
-    output.send(Row ::new(vec![
-        users.get_name(meta.uid()),
-        Value::Integer(i128::from(meta.len())),
-        Value::Time(modified_datetime),
-        Value::string(ftype),
-        Value::File(f)]))?;
-    Ok(())
+const PERMISSION_BITS: [(&str, u32); 12] = [
+    ("user_read", 0o400), ("user_write", 0o200), ("user_execute", 0o100),
+    ("group_read", 0o040), ("group_write", 0o020), ("group_execute", 0o010),
+    ("other_read", 0o004), ("other_write", 0o002), ("other_execute", 0o001),
+    ("setuid", 0o4000), ("setgid", 0o2000), ("sticky", 0o1000),
+];
+
+fn has(mut context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(1)?;
+    let this = context.this.r#struct()?;
+    let name = context.arguments.string(0)?;
+    let mode = match this.get("bits") {
+        Some(Value::Integer(m)) => m as u32,
+        _ => return argument_error("Expected a mode struct"),
+    };
+    match PERMISSION_BITS.iter().find(|(n, _)| *n == name.as_ref()) {
+        Some((_, bit)) => context.output.send(Value::Bool(mode & bit != 0)),
+        None => argument_error(format!(
+            "Unknown permission {}, expected one of {}",
+            name,
+            PERMISSION_BITS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", ")).as_str()),
+    }
 }
 
-fn run_for_single_directory_or_file(
-    path: Box<Path>,
+lazy_static! {
+    static ref MODE: Struct = Struct::new(vec![
+        (Box::from("has"), Value::Command(CrushCommand::command(
+            has, false,
+            "mode:has bit:string",
+            "True if the specified permission bit is set",
+            Some(r#"    bit is one of user_read, user_write, user_execute, group_read,
+    group_write, group_execute, other_read, other_write, other_execute,
+    setuid, setgid and sticky.
+
+    Example:
+
+    find columns="mode" | where {mode:has "other_write"}"#)))),
+    ], Some(ROOT.clone()));
+}
+
+pub fn permissions(mode: u32) -> Value {
+    Value::String(permission_string(mode).into_boxed_str())
+}
+
+/**
+    The permission bits of a file as a struct with the bits formatted like ls does in text,
+    and as an integer in bits. Use the has method to check individual bits.
+*/
+pub fn mode_struct(mode: u32) -> Value {
+    Value::Struct(Struct::new(vec![
+        (Box::from("text"), Value::String(permission_string(mode).into_boxed_str())),
+        (Box::from("bits"), Value::Integer(i128::from(mode & 0o7777))),
+    ], Some(MODE.clone())))
+}
+
+fn time_value(time: std::io::Result<SystemTime>) -> CrushResult<Value> {
+    let datetime: DateTime<Local> = DateTime::from(to_crush_error(time)?);
+    Ok(Value::Time(datetime))
+}
+
+fn entity_row(
+    meta: &Metadata,
+    file: &Path,
+    columns: &Vec<Column>,
     users: &HashMap<uid_t, User>,
-    recursive: bool,
-    q: &mut VecDeque<Box<Path>>,
-    output: &mut OutputStream) -> CrushResult<()> {
-    if path.is_dir() {
-        let dirs = fs::read_dir(path);
-        for maybe_entry in to_crush_error(dirs)? {
-            let entry = to_crush_error(maybe_entry)?;
-            insert_entity(
-                &to_crush_error(entry.metadata())?,
-                entry.path().into_boxed_path(),
-                &users,
-                output)?;
-            if recursive && entry.path().is_dir() {
-                if !(entry.file_name().eq(".") || entry.file_name().eq("..")) {
-                    q.push_back(entry.path().into_boxed_path());
-                }
-            }
-        }
-    } else {
-        match path.file_name() {
-            Some(_) => {
-                insert_entity(
-                    &to_crush_error(path.metadata())?,
-                    path,
-                    &users,
-                    output)?;
-            }
-            None => {
-                return error("Invalid file name");
-            }
-        }
+    groups: &HashMap<gid_t, GroupEntry>) -> CrushResult<Row> {
+    let mut cells = Vec::with_capacity(columns.len());
+    for column in columns {
+        cells.push(match column {
+            Column::Permissions => permissions(meta.mode()),
+            Column::Mode => mode_struct(meta.mode()),
+            Column::Inode => Value::Integer(i128::from(meta.ino())),
+            Column::Nlink => Value::Integer(i128::from(meta.nlink())),
+            Column::User => users.get_name(meta.uid()),
+            Column::Group => groups.get_name(meta.gid()),
+            Column::Size => Value::Integer(i128::from(meta.len())),
+            Column::Modified => time_value(meta.modified())?,
+            Column::Accessed => time_value(meta.accessed())?,
+            /* Not all file systems record the creation time, fall back to the status change time. */
+            Column::Created => time_value(meta.created().or_else(|_| Ok(
+                UNIX_EPOCH + std::time::Duration::new(meta.ctime() as u64, meta.ctime_nsec() as u32))))?,
+            Column::Device => Value::Integer(i128::from(meta.dev())),
+            Column::Type => Value::string(file_type_name(&meta.file_type())),
+            Column::SymlinkTarget => Value::String(
+                if meta.file_type().is_symlink() {
+                    fs::read_link(file).map(|t| t.to_string_lossy().to_string()).unwrap_or_default()
+                } else {
+                    String::new()
+                }.into_boxed_str()),
+            Column::File => Value::File(Box::from(file.strip_prefix(".").unwrap_or(file))),
+        });
     }
-    Ok(())
+    Ok(Row::new(cells))
 }
 
-pub fn run(mut config: Config) -> CrushResult<()> {
-    let users = create_user_map();
-    let mut q = VecDeque::new();
-    for dir in config.dirs {
-        q.push_back(dir);
+/**
+    Call the prune closure with the columns of the row as named arguments, the same way
+    where does.
+*/
+fn prune(condition: &Box<dyn CrushCommand + Send + Sync>, row: &Row, columns: &Vec<Column>, env: &Scope) -> CrushResult<bool> {
+    let arguments = row.clone().into_vec()
+        .drain(..)
+        .zip(columns.iter())
+        .map(|(c, t)| Argument::named(t.name(), c))
+        .collect();
+
+    let (sender, receiver) = channels();
+
+    condition.invoke(ExecutionContext {
+        input: empty_channel(),
+        output: sender,
+        arguments,
+        env: env.clone(),
+        this: None,
+    })?;
+
+    match receiver.recv()? {
+        Value::Bool(b) => Ok(b),
+        _ => error("Expected the prune closure to return a boolean"),
     }
-    loop {
-        if q.is_empty() {
-            break;
+}
+
+pub fn run(config: Config) -> CrushResult<()> {
+    let users = create_user_map();
+    let groups = create_group_map();
+    let Config { dirs, options, columns, prune: condition, env, output } = config;
+    let output = output.initialize(column_types(&columns))?;
+
+    walk(&options, dirs, &|path: &Path, meta: &Metadata, depth: usize| {
+        let row = entity_row(meta, path, &columns, &users, &groups)?;
+        let action = match &condition {
+            Some(c) if meta.is_dir() && prune(c, &row, &columns, &env)? => Visit::Skip,
+            _ => Visit::Descend,
+        };
+        if depth >= options.min_depth && output.send(row).is_err() {
+            /* Whoever is reading our output has stopped listening, e.g. head. */
+            return Ok(Visit::Stop);
         }
-        let dir = q.pop_front().unwrap();
-        let _ = run_for_single_directory_or_file(dir, &users, config.recursive, &mut q, &mut config.output);
-    }
-    return Ok(());
+        Ok(action)
+    })
 }
 
 pub struct Config {
     dirs: Vec<Box<Path>>,
-    recursive: bool,
-    output: OutputStream,
+    options: Options,
+    columns: Vec<Column>,
+    prune: Option<Box<dyn CrushCommand + Send + Sync>>,
+    env: Scope,
+    output: ValueSender,
 }
 
-fn parse(output: OutputStream, mut arguments: Vec<Argument>, recursive: bool) -> Result<Config, CrushError> {
-    if arguments.is_empty() {
-        Ok(Config { dirs: vec![Box::from(Path::new("."))], recursive, output })
-    } else {
-    Ok(Config { dirs: arguments.files()?, recursive, output })
+fn add_column(selected: &mut Vec<Column>, value: Value) -> CrushResult<()> {
+    match value {
+        Value::String(name) => selected.push(Column::parse(name.as_ref())?),
+        Value::List(names) => {
+            for name in names.dump() {
+                add_column(selected, name)?;
+            }
+        }
+        _ => return argument_error("Expected a column name or a list of column names"),
+    }
+    Ok(())
+}
+
+fn parse(output: ValueSender, arguments: Vec<Argument>, env: Scope, max_depth: Option<usize>) -> Result<Config, CrushError> {
+    let mut options = Options::new(max_depth);
+    let mut prune = None;
+    let mut dirs = Vec::new();
+    let mut long = false;
+    let mut selected = Vec::new();
+    for arg in arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, value) => value.file_expand(&mut dirs)?,
+            (Some("prune"), Value::Command(c)) => prune = Some(c),
+            (Some("long"), Value::Bool(b)) => long = b,
+            (Some("columns"), value) => add_column(&mut selected, value)?,
+            (Some(name), value) => options.set(name, value)?,
+        }
+    }
+    if dirs.is_empty() {
+        dirs.push(Box::from(Path::new(".")));
     }
+    let columns = ALL_COLUMNS.iter()
+        .filter(|c| long || c.is_default() || selected.contains(c))
+        .cloned()
+        .collect();
+    Ok(Config { dirs, options, columns, prune, env, output })
 }
 
 pub fn perform_ls(context: ExecutionContext) -> CrushResult<()> {
-    let output = context.output.initialize(OUTPUT_TYPE.clone())?;
-    let cfg = parse(output, context.arguments, false)?;
+    let cfg = parse(context.output, context.arguments, context.env, Some(1))?;
     run(cfg)
 }
 
 pub fn perform_find(context: ExecutionContext) -> CrushResult<()> {
-    let output = context.output.initialize(OUTPUT_TYPE.clone())?;
-    let cfg = parse(output, context.arguments, true)?;
+    let cfg = parse(context.output, context.arguments, context.env, None)?;
     run(cfg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+        Run find on a fresh tree containing a/b/c and d, and return the file names it outputs.
+    */
+    fn find(name: &str, mut arguments: Vec<Argument>) -> Vec<String> {
+        let root = std::env::temp_dir().join(format!("crush-find-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/c"), "").unwrap();
+        fs::write(root.join("d"), "").unwrap();
+        arguments.push(Argument::unnamed(Value::File(Box::from(root.as_path()))));
+
+        let (sender, receiver) = channels();
+        let context = ExecutionContext { input: empty_channel(), output: sender, arguments, env: Scope::new(), this: None };
+        let finder = std::thread::spawn(move || perform_find(context));
+        let mut res = Vec::new();
+        if let Value::TableStream(rows) = receiver.recv().unwrap() {
+            while let Ok(row) = rows.recv() {
+                if let Some(Value::File(f)) = row.cells().last() {
+                    res.push(f.file_name().unwrap().to_string_lossy().to_string());
+                }
+            }
+        }
+        finder.join().unwrap().unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn depth_limits_select_entries() {
+        assert_eq!(find("max", vec![Argument::named("max_depth", Value::Integer(1))]), vec!["a", "d"]);
+        assert_eq!(find("min", vec![Argument::named("min_depth", Value::Integer(2))]), vec!["b", "c"]);
+        assert_eq!(
+            find("both", vec![
+                Argument::named("min_depth", Value::Integer(2)),
+                Argument::named("max_depth", Value::Integer(2)),
+            ]),
+            vec!["b"]);
+    }
+
+    #[test]
+    fn modes_are_structs() {
+        assert!(permissions(0o104755) == Value::string("rwsr-xr-x"));
+        match mode_struct(0o104755) {
+            Value::Struct(s) => {
+                assert!(s.get("text") == Some(Value::string("rwsr-xr-x")));
+                assert!(s.get("bits") == Some(Value::Integer(0o4755)));
+                assert!(s.get("has").is_some());
+            }
+            _ => panic!("Expected a struct"),
+        }
+    }
+
+    #[test]
+    fn permissions_are_formatted_like_ls() {
+        assert_eq!(permission_string(0o755), "rwxr-xr-x");
+        assert_eq!(permission_string(0o640), "rw-r-----");
+        assert_eq!(permission_string(0o4755), "rwsr-xr-x");
+        assert_eq!(permission_string(0o2745), "rwxr-Sr-x");
+        assert_eq!(permission_string(0o1777), "rwxrwxrwt");
+        assert_eq!(permission_string(0o1776), "rwxrwxrwT");
+    }
+}
@@ -1,32 +1,21 @@
 use crate::lang::scope::Scope;
-use crate::lang::errors::{CrushResult, error, to_crush_error, argument_error};
-use crate::lang::{value::Value};
+use crate::lang::errors::{CrushResult, argument_error};
+use crate::lang::{value::Value, value::ValueType};
 use crate::lang::command::CrushCommand;
-use crate::util::file::{home, cwd};
-use std::path::Path;
+use crate::util::file::cwd;
 use crate::lang::printer::printer;
 use crate::lang::execution_context::ExecutionContext;
 use crate::lang::execution_context::ArgumentVector;
 use crate::lang::help::Help;
 
 mod find;
+mod du;
+mod file_ops;
+mod watch;
+mod walk;
+mod navigation;
 
-pub fn cd(context: ExecutionContext) -> CrushResult<()> {
-    let dir = match context.arguments.len() {
-        0 => home(),
-        1 => {
-            let dir = &context.arguments[0];
-            match &dir.value {
-                Value::String(val) => Ok(Box::from(Path::new(val.as_ref()))),
-                Value::File(val) => Ok(val.clone()),
-                Value::Glob(val) => val.glob_to_single_file(&cwd()?),
-                _ => error(format!("Wrong parameter type, expected text or file, found {}", &dir.value.value_type().to_string()).as_str())
-            }
-        }
-        _ => error("Wrong number of arguments")
-    }?;
-    to_crush_error(std::env::set_current_dir(dir))
-}
+pub use navigation::cd;
 
 pub fn pwd(context: ExecutionContext) -> CrushResult<()> {
     context.output.send(Value::File(cwd()?))
@@ -65,6 +54,10 @@ members of a value, write "dir <value>".
                 Value::Command(cmd) =>
                     halp(cmd.help()),
                 Value::Type(t) => halp(&t),
+                Value::Scope(s) => match s.get("__call__") {
+                    Some(Value::Command(cmd)) => halp(cmd.help()),
+                    _ => halp(&ValueType::Scope),
+                },
                 v => halp(&v.value_type()),
             }
             Ok(())
@@ -79,15 +72,324 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
     root.r#use(&env);
     env.declare("ls", Value::Command(CrushCommand::command(
         find::perform_ls, true,
-        "ls @file:file", "Non-recursively list files", None)))?;
+        "ls [long=long:bool] [columns=columns:(string|list)...] [max_depth=max_depth:integer] [min_depth=min_depth:integer] [follow_symlinks=follow_symlinks:bool] [hidden=hidden:bool] [prune=prune:command] [ignore=ignore:string] [errors=errors:string] [threads=threads:integer] [sort=sort:bool] @file:file",
+        "Non-recursively list files",
+        Some(r#"    ls accepts the same named arguments as find, but max_depth defaults to 1.
+    If no files are given, the current directory is listed.
+
+    Example:
+
+    ls long=true
+    ls ignore=git"#))))?;
     env.declare("find", Value::Command(CrushCommand::command(
         find::perform_find, true,
-        "find @file:file",
-        "Recursively list files", None)))?;
-    env.declare("cd", Value::Command(CrushCommand::command(
-        cd, true,
-        "cd directory:(file,string,glob)",
-        "Change to the specified working directory", None)))?;
+        "find [long=long:bool] [columns=columns:(string|list)...] [max_depth=max_depth:integer] [min_depth=min_depth:integer] [follow_symlinks=follow_symlinks:bool] [hidden=hidden:bool] [prune=prune:command] [ignore=ignore:string] [errors=errors:string] [threads=threads:integer] [sort=sort:bool] @file:file",
+        "Recursively list files",
+        Some(r#"    If no files are given, the current directory is searched. Directories are
+    read in parallel by a pool of threads, so files from different directories
+    are interleaved in the output.
+
+    By default, each row contains the columns user, group, size, modified, type
+    and file. The type is one of file, directory, symlink, fifo, socket,
+    block_device and char_device. The following extra columns are available:
+
+    * permissions:string the permission bits formatted like ls does, e.g.
+      rwxr-xr-x
+
+    * mode:struct the permission bits, with the members text, formatted like
+      the permissions column, and bits, the bits as an integer, e.g. 493. Use
+      the method has to check a single bit, e.g. mode:has "user_write". The
+      bits are user_read, user_write, user_execute, group_read, group_write,
+      group_execute, other_read, other_write, other_execute, setuid, setgid
+      and sticky.
+
+    * inode:integer the inode number
+
+    * nlink:integer the number of hard links to the file
+
+    * accessed:time the time of last access
+
+    * created:time the creation time, or the time of the last status change on
+      file systems that do not record creation times
+
+    * device:integer the id of the device containing the file
+
+    * symlink_target:string the target of a symlink, empty for other files
+
+    The following named arguments are accepted:
+
+    * long:bool if true, output all available columns.
+
+    * columns:string the name of an extra column to output. Can be repeated, or
+      given a list of names.
+
+    * max_depth:integer do not list files more than this many levels below the
+      specified directories. The contents of a specified directory are at depth 1.
+
+    * min_depth:integer do not list files less than this many levels below the
+      specified directories. Shallower directories are still searched.
+
+    * follow_symlinks:bool if true, symlinks to directories are searched. Every
+      directory is searched at most once, so symlink loops are harmless. The
+      default is false.
+
+    * hidden:bool if false, files whose names start with a dot are skipped, and
+      hidden directories are not searched. The default is true.
+
+    * prune:command a closure that is called for every directory with the
+      columns of the row as named arguments, like where. If it returns true, the
+      directory is listed but not searched.
+
+    * ignore:string either none (the default) or git. If git, files matched by
+      .gitignore and .ignore files, .git/info/exclude and the global git
+      excludes file are skipped, as are .git directories. Ignore files in parent
+      directories up to the root of the repository are honoured. Skipped
+      directories are never opened.
+
+    * errors:string what to do with files and directories that can't be read,
+      and with errors from prune. One of report (print an error and continue,
+      the default), ignore and fail. A directory that causes an error is not
+      searched.
+
+    * threads:integer the number of directories to read in parallel, at most 64.
+      The default is 4. Use more for slow network file systems.
+
+    * sort:bool if true, the files in each directory are listed together, sorted
+      by name. With more than one thread, the order in which the directories
+      are listed still varies between runs. Only together with threads=1 is the
+      output order fully deterministic, breadth first.
+
+    Example:
+
+    # Search the whole file system, except for the kernel's virtual file systems
+    find / prune={file == /proc or file == /sys} errors=ignore
+
+    # All files in a repository that are not build artifacts
+    find ignore=git
+
+    # Everything at most two levels down, excluding dot files
+    find / max_depth=2 hidden=false errors=ignore
+
+    # All files with more than one hard link
+    find columns="nlink" columns="inode" | where {type == "file" and nlink > 1}"#))))?;
+    env.declare("du", Value::Command(CrushCommand::command(
+        du::perform, true,
+        "du [depth=depth:integer] [follow_symlinks=follow_symlinks:bool] [hidden=hidden:bool] [ignore=ignore:string] [errors=errors:string] [threads=threads:integer] @file:file",
+        "Return the disk usage of files and directories",
+        Some(r#"    du searches the specified files, or the current directory if none are
+    given, and sums up the disk usage of everything in them. Files with
+    multiple hard links are only counted once. By default, one row is returned
+    per specified file. If depth is given, a row is also returned for every
+    directory up to that many levels below the specified files, containing the
+    totals for that directory. Rows are sorted by file name, so every directory
+    is listed just before its contents.
+
+    Each row contains the following columns:
+
+    * file:file the file or directory
+
+    * size:integer the total apparent size in bytes
+
+    * blocks:integer the total number of 512 byte blocks allocated on disk
+
+    * count:integer the number of files and directories, including the directory itself
+
+    The arguments follow_symlinks, hidden, ignore, errors and threads work like
+    they do for find.
+
+    Example:
+
+    # What is eating my disk?
+    du depth=1 | sort %size"#))))?;
+    env.declare("rm", Value::Command(CrushCommand::command(
+        file_ops::rm, true,
+        "rm [recursive=recursive:bool] [force=force:bool] [dry_run=dry_run:bool] @file:(file|glob)",
+        "Remove files",
+        Some(r#"    If no files are given as arguments, they are read from the input, which
+    must be a table stream with a column of type file, like the output of find.
+    Returns a table stream with the columns action and file, one row per removed
+    file.
+
+    * recursive:bool remove directories and their contents.
+
+    * force:bool silently skip files that don't exist.
+
+    * dry_run:bool don't remove anything, only return what would be removed.
+
+    Example:
+
+    find . | where {type == "file" and modified < (time:now) - (duration:new 30 "days")} | rm"#))))?;
+    env.declare("mkdir", Value::Command(CrushCommand::command(
+        file_ops::mkdir, true,
+        "mkdir [recursive=recursive:bool] [force=force:bool] [dry_run=dry_run:bool] @directory:(file|glob)",
+        "Create directories",
+        Some(r#"    Returns a table stream with the columns action and file, one row per
+    created directory.
+
+    * recursive:bool create missing parent directories too, and don't fail if
+      the directory already exists.
+
+    * force:bool don't fail if the directory already exists.
+
+    * dry_run:bool don't create anything, only return what would be created."#))))?;
+    env.declare("touch", Value::Command(CrushCommand::command(
+        file_ops::touch, true,
+        "touch [dry_run=dry_run:bool] @file:(file|glob)",
+        "Create files, or update their modification time",
+        Some(r#"    Returns a table stream with the columns action and file. The action is
+    either create or touch.
+
+    * dry_run:bool don't change anything, only return what would be done."#))))?;
+    env.declare("cp", Value::Command(CrushCommand::command(
+        file_ops::cp, true,
+        "cp [recursive=recursive:bool] [force=force:bool] [dry_run=dry_run:bool] [destination=destination:file] @file:(file|glob)",
+        "Copy files",
+        Some(r#"    The last argument is the destination, unless it is given as a named
+    argument. If the destination is a directory, the files are copied into it,
+    otherwise the single source file is copied to the destination. If no source
+    files are given as arguments, they are read from the input, which must be a
+    table stream with a column of type file. Returns a table stream with the
+    columns action, source and destination, one row per copied file.
+
+    * recursive:bool copy directories and their contents.
+
+    * force:bool overwrite existing files. Without it, copying onto an existing
+      file is an error.
+
+    * dry_run:bool don't copy anything, only return what would be copied.
+
+    Example:
+
+    find src | where {type == "file"} | cp destination=/tmp/backup"#))))?;
+    env.declare("mv", Value::Command(CrushCommand::command(
+        file_ops::mv, true,
+        "mv [force=force:bool] [dry_run=dry_run:bool] [destination=destination:file] @file:(file|glob)",
+        "Move or rename files",
+        Some(r#"    The destination is handled like for cp. Directories are always moved with
+    their contents. Moving files between file systems falls back to copying and
+    removing them. Returns a table stream with the columns action, source and
+    destination.
+
+    * force:bool overwrite existing files. Without it, moving onto an existing
+      file is an error.
+
+    * dry_run:bool don't move anything, only return what would be moved."#))))?;
+    env.declare("ln", Value::Command(CrushCommand::command(
+        file_ops::ln, true,
+        "ln [symbolic=symbolic:bool] [force=force:bool] [dry_run=dry_run:bool] [destination=destination:file] @file:(file|glob)",
+        "Create links to files",
+        Some(r#"    The destination is handled like for cp. Returns a table stream with the
+    columns action, source and destination.
+
+    * symbolic:bool create symbolic links instead of hard links. The source is
+      used as the link target exactly as given.
+
+    * force:bool replace existing files.
+
+    * dry_run:bool don't create anything, only return what would be created."#))))?;
+    env.declare("chmod", Value::Command(CrushCommand::command(
+        file_ops::chmod, true,
+        "chmod [recursive=recursive:bool] [dry_run=dry_run:bool] mode:string @file:(file|glob)",
+        "Change the permissions of files",
+        Some(r#"    The mode is one of:
+
+    * an octal number, e.g. "755"
+    * a permission string like the one returned by ls, e.g. "rwxr-xr-x"
+    * a comma separated list of symbolic changes, e.g. "u+x,go-w", where each
+      change is any of u, g, o and a, followed by one or more of +, - and =,
+      each followed by any of r, w, x, X, s and t.
+
+    Symlinks are skipped. Returns a table stream with the columns action, file,
+    permissions and mode, containing the new permissions of every changed file,
+    in the same form as the columns with the same names in find.
+
+    * recursive:bool also change the contents of directories.
+
+    * dry_run:bool don't change anything, only return what would be done.
+
+    Example:
+
+    find bin | where {type == "file"} | chmod "a+x""#))))?;
+    env.declare("watch", Value::Command(CrushCommand::command(
+        watch::perform, true,
+        "watch [recursive=recursive:bool] @file:(file|glob)",
+        "Return a table stream of changes to files",
+        Some(r#"    watch uses inotify to watch the specified files and directories, and emits
+    a row every time one of them, or a file in one of the directories, changes.
+    It runs until cancelled, or until whoever reads its output stops, even if
+    no files change. watch accepts the following named arguments:
+
+    * recursive:bool also watch all subdirectories, including ones created
+      while watching.
+
+    Each row contains the following columns:
+
+    * time:time the time the event was received
+
+    * event:string one of create, modify, delete and rename. A rename is
+      reported twice, once for the old name and once for the new one.
+
+    * file:file the file that changed
+
+    Example:
+
+    # Rebuild whenever a Rust file changes
+    watch src recursive=true | where {%.rs =~ file} | for {cmd cargo build}"#))))?;
+    let cd = env.create_namespace("cd")?;
+    cd.declare("__call__", Value::Command(CrushCommand::command(
+        navigation::cd, true,
+        "cd [directory:(file,string,glob)]",
+        "Change to the specified working directory",
+        Some(r#"    Without arguments, change to the home directory of the current user. If the
+    directory is "-", change to the previous working directory.
+
+    Every visited directory is recorded in a history, which is saved between
+    sessions and shared by all running shells. Use cd:history to see it and cd:jump to go back to a directory
+    in it without typing the whole path."#))))?;
+    cd.declare("history", Value::Command(CrushCommand::command(
+        navigation::history_command, false,
+        "cd:history",
+        "Return a table stream of recently visited directories",
+        Some(r#"    Rows are sorted by score, highest first. Each row contains the following
+    columns:
+
+    * directory:file the directory
+
+    * visits:integer the number of times the directory has been visited
+
+    * last_visit:time the time of the most recent visit
+
+    * score:float the frecency score used by cd:jump. Directories visited in the
+      last hour count four times as much, in the last day twice as much, in the
+      last week half as much and older visits a quarter as much."#))))?;
+    cd.declare("jump", Value::Command(CrushCommand::command(
+        navigation::jump, true,
+        "cd:jump pattern:(glob|regex|string)",
+        "Change to the highest ranked directory in the history matching the pattern",
+        Some(r#"    A string matches any directory containing it, globs and regexes are
+    matched against the whole path. The current directory is never chosen.
+
+    Example:
+
+    cd:jump "worktree-2"
+    cd:jump %/crush"#))))?;
+    cd.readonly();
+    env.declare("pushd", Value::Command(CrushCommand::command(
+        navigation::pushd, true,
+        "pushd [directory:(file,string,glob)]",
+        "Push the working directory onto the directory stack and change to the specified directory",
+        Some(r#"    Without arguments, swap the working directory with the top of the directory
+    stack."#))))?;
+    env.declare("popd", Value::Command(CrushCommand::command(
+        navigation::popd, true,
+        "popd",
+        "Change to the directory on the top of the directory stack and remove it from the stack",
+        None)))?;
+    env.declare("dirs", Value::Command(CrushCommand::command(
+        navigation::dirs, false,
+        "dirs",
+        "Return a list of the working directory followed by the directory stack, top first",
+        None)))?;
     env.declare("pwd", Value::Command(CrushCommand::command(
         pwd, false,
         "pwd", "Return the current working directory", None)))?;
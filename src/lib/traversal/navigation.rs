@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local, TimeZone};
+use lazy_static::lazy_static;
+use nix::fcntl::{flock, FlockArg};
+
+use crate::lang::execution_context::{ExecutionContext, ArgumentVector};
+use crate::lang::errors::{argument_error, error, mandate, to_crush_error, CrushResult};
+use crate::lang::{list::List, table::ColumnType, table::Row, value::Value, value::ValueType};
+use crate::util::file::{cwd, home};
+
+/** The number of directories to remember in the history. */
+const HISTORY_SIZE: usize = 1000;
+
+#[derive(Clone, Copy)]
+struct Visits {
+    count: u64,
+    /** Seconds since the epoch. */
+    last: i64,
+}
+
+impl Visits {
+    /**
+        Rank directories by how often and how recently they were visited.
+    */
+    fn frecency(&self, now: i64) -> f64 {
+        let age = now - self.last;
+        let weight = if age < 3600 {
+            4.0
+        } else if age < 24 * 3600 {
+            2.0
+        } else if age < 7 * 24 * 3600 {
+            0.5
+        } else {
+            0.25
+        };
+        self.count as f64 * weight
+    }
+}
+
+struct DirectoryState {
+    previous: Option<PathBuf>,
+    stack: Vec<PathBuf>,
+    history: Option<HashMap<PathBuf, Visits>>,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<DirectoryState> = Mutex::new(DirectoryState {
+        previous: None,
+        stack: Vec::new(),
+        history: None,
+    });
+}
+
+fn history_file() -> CrushResult<PathBuf> {
+    Ok(home()?.join(".crush_cd_history"))
+}
+
+/**
+    The history is stored as one line per directory, containing the number of visits, the
+    time of the last visit and the directory, separated by tabs.
+*/
+fn load_history() -> HashMap<PathBuf, Visits> {
+    let mut res = HashMap::new();
+    if let Ok(content) = history_file().and_then(|f| to_crush_error(fs::read_to_string(f))) {
+        for line in content.lines() {
+            let parts = line.splitn(3, '\t').collect::<Vec<_>>();
+            if parts.len() != 3 {
+                continue;
+            }
+            if let (Ok(count), Ok(last)) = (parts[0].parse(), parts[1].parse()) {
+                res.insert(PathBuf::from(parts[2]), Visits { count, last });
+            }
+        }
+    }
+    res
+}
+
+/**
+    Write to a temporary file and rename it, so that other shells never see a partially
+    written history.
+*/
+fn save_history(history: &HashMap<PathBuf, Visits>) -> CrushResult<()> {
+    let content = history.iter()
+        .map(|(dir, v)| format!("{}\t{}\t{}\n", v.count, v.last, dir.to_string_lossy()))
+        .collect::<String>();
+    let file = history_file()?;
+    let tmp = file.with_file_name(format!(".crush_cd_history.{}", std::process::id()));
+    to_crush_error(fs::write(&tmp, content))?;
+    if let Err(e) = fs::rename(&tmp, &file) {
+        let _ = fs::remove_file(&tmp);
+        return to_crush_error(Err(e));
+    }
+    Ok(())
+}
+
+/**
+    Take an exclusive lock on the history, which is released when the returned file is
+    closed. The history file itself is replaced on every save, so the lock is held on a
+    separate file.
+*/
+fn lock_history() -> CrushResult<fs::File> {
+    let lock = history_file()?.with_file_name(".crush_cd_history.lock");
+    let file = to_crush_error(fs::OpenOptions::new().create(true).write(true).truncate(false).open(lock))?;
+    to_crush_error(flock(file.as_raw_fd(), FlockArg::LockExclusive))?;
+    Ok(file)
+}
+
+fn history(state: &mut DirectoryState) -> &mut HashMap<PathBuf, Visits> {
+    if state.history.is_none() {
+        state.history = Some(load_history());
+    }
+    state.history.as_mut().unwrap()
+}
+
+/**
+    Other shells may have saved visits of their own since we loaded the history, so the
+    history file is read again, updated with the visit and written back while holding the
+    history lock. Without the lock, e.g. on file systems that don't support it, a visit
+    saved by another shell at the same time can be lost.
+*/
+fn record_visit(state: &mut DirectoryState, dir: PathBuf) {
+    let _lock = lock_history().ok();
+    let now = Local::now().timestamp();
+    let mut history = load_history();
+    let visits = history.entry(dir).or_insert(Visits { count: 0, last: now });
+    visits.count += 1;
+    visits.last = now;
+    if history.len() > HISTORY_SIZE {
+        let mut ranked = history.iter()
+            .map(|(dir, v)| (v.frecency(now), dir.clone()))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        for (_, dir) in ranked.iter().take(history.len() - HISTORY_SIZE) {
+            history.remove(dir);
+        }
+    }
+    /* Failing to save the history should never make cd fail. */
+    let _ = save_history(&history);
+    state.history = Some(history);
+}
+
+/**
+    Change the working directory, remembering where we came from.
+*/
+fn change_dir(state: &mut DirectoryState, dir: &Path) -> CrushResult<()> {
+    let old = cwd()?;
+    if let Err(e) = std::env::set_current_dir(dir) {
+        return error(format!("{}: {}", dir.to_string_lossy(), e).as_str());
+    }
+    state.previous = Some(old.to_path_buf());
+    let new = cwd()?;
+    record_visit(state, new.to_path_buf());
+    Ok(())
+}
+
+fn directory_argument(value: Value) -> CrushResult<PathBuf> {
+    match value {
+        Value::String(val) => Ok(PathBuf::from(val.as_ref())),
+        Value::File(val) => Ok(val.to_path_buf()),
+        Value::Glob(val) => Ok(val.glob_to_single_file(&cwd()?)?.to_path_buf()),
+        v => error(format!("Wrong parameter type, expected text or file, found {}", v.value_type().to_string()).as_str()),
+    }
+}
+
+pub fn cd(mut context: ExecutionContext) -> CrushResult<()> {
+    let mut state = STATE.lock().unwrap();
+    let dir = match context.arguments.len() {
+        0 => home()?.to_path_buf(),
+        1 => match context.arguments.value(0)? {
+            Value::String(s) if s.as_ref() == "-" =>
+                mandate(state.previous.clone(), "No previous directory")?,
+            v => directory_argument(v)?,
+        },
+        _ => return error("Wrong number of arguments"),
+    };
+    change_dir(&mut state, &dir)
+}
+
+pub fn pushd(mut context: ExecutionContext) -> CrushResult<()> {
+    let mut state = STATE.lock().unwrap();
+    let current = cwd()?.to_path_buf();
+    match context.arguments.len() {
+        0 => {
+            /* Swap the current directory with the top of the stack. */
+            let top = mandate(state.stack.last().cloned(), "The directory stack is empty")?;
+            change_dir(&mut state, &top)?;
+            state.stack.pop();
+        }
+        1 => {
+            let dir = directory_argument(context.arguments.value(0)?)?;
+            change_dir(&mut state, &dir)?;
+        }
+        _ => return error("Wrong number of arguments"),
+    }
+    state.stack.push(current);
+    Ok(())
+}
+
+pub fn popd(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let mut state = STATE.lock().unwrap();
+    let top = mandate(state.stack.pop(), "The directory stack is empty")?;
+    if let Err(e) = change_dir(&mut state, &top) {
+        state.stack.push(top);
+        return Err(e);
+    }
+    Ok(())
+}
+
+pub fn dirs(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let state = STATE.lock().unwrap();
+    let mut res = vec![Value::File(cwd()?)];
+    for dir in state.stack.iter().rev() {
+        res.push(Value::File(dir.clone().into_boxed_path()));
+    }
+    context.output.send(Value::List(List::new(ValueType::File, res)))
+}
+
+fn ranked_history(state: &mut DirectoryState) -> Vec<(f64, PathBuf, Visits)> {
+    let now = Local::now().timestamp();
+    let mut res = history(state).iter()
+        .map(|(dir, v)| (v.frecency(now), dir.clone(), *v))
+        .collect::<Vec<_>>();
+    res.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    res
+}
+
+pub fn history_command(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("directory", ValueType::File),
+        ColumnType::new("visits", ValueType::Integer),
+        ColumnType::new("last_visit", ValueType::Time),
+        ColumnType::new("score", ValueType::Float),
+    ])?;
+    let ranked = ranked_history(&mut STATE.lock().unwrap());
+    for (score, dir, visits) in ranked {
+        let last: DateTime<Local> = mandate(
+            Local.timestamp_opt(visits.last, 0).single(),
+            "Invalid time in the directory history")?;
+        output.send(Row::new(vec![
+            Value::File(dir.into_boxed_path()),
+            Value::Integer(visits.count as i128),
+            Value::Time(last),
+            Value::Float(score),
+        ]))?;
+    }
+    Ok(())
+}
+
+pub fn jump(mut context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(1)?;
+    let pattern = context.arguments.value(0)?;
+    let mut state = STATE.lock().unwrap();
+    let current = cwd()?;
+    let matches = |dir: &str| match &pattern {
+        Value::Glob(g) => Ok(g.matches(dir)),
+        Value::Regex(_, r) => Ok(r.is_match(dir)),
+        Value::String(s) => Ok(dir.contains(s.as_ref())),
+        _ => argument_error("Expected a glob, a regex or a string"),
+    };
+    for (_, dir, _) in ranked_history(&mut state) {
+        if dir.as_path() != &*current && dir.is_dir() && matches(dir.to_string_lossy().as_ref())? {
+            return change_dir(&mut state, &dir);
+        }
+    }
+    error("No matching directory in the history")
+}
@@ -0,0 +1,495 @@
+use std::collections::HashSet;
+use std::fs;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crossbeam::deque::{Injector, Steal, Stealer, Worker};
+
+use crate::lang::errors::{argument_error, error, CrushError, CrushResult};
+use crate::lang::printer::printer;
+use crate::lang::value::Value;
+use crate::util::ignore::IgnoreRules;
+
+/**
+    What to do when a file or directory can't be read during a walk.
+*/
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ErrorMode {
+    /** Print the error and keep going. */
+    Report,
+    /** Silently skip the file. */
+    Ignore,
+    /** Abort the walk. */
+    Fail,
+}
+
+/**
+    What a visitor wants the walker to do after it has seen an entry.
+*/
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Visit {
+    /** Keep going, and if the entry is a directory, walk into it. */
+    Descend,
+    /** Keep going, but don't walk into the entry. */
+    Skip,
+    /** Stop the whole walk, e.g. because nobody is reading our output any more. */
+    Stop,
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub max_depth: Option<usize>,
+    pub min_depth: usize,
+    pub follow_symlinks: bool,
+    pub hidden: bool,
+    pub errors: ErrorMode,
+    pub threads: usize,
+    pub sort: bool,
+    /** Skip files ignored by .gitignore, .ignore and the global git excludes. */
+    pub ignore: bool,
+}
+
+const DEFAULT_THREADS: usize = 4;
+const MAX_THREADS: usize = 64;
+
+impl Options {
+    pub fn new(max_depth: Option<usize>) -> Options {
+        Options {
+            max_depth,
+            min_depth: 0,
+            follow_symlinks: false,
+            hidden: true,
+            errors: ErrorMode::Report,
+            threads: DEFAULT_THREADS,
+            sort: false,
+            ignore: false,
+        }
+    }
+
+    /**
+        Set the option with the specified name. Fails on unknown options.
+    */
+    pub fn set(&mut self, name: &str, value: Value) -> CrushResult<()> {
+        match (name, value) {
+            ("max_depth", Value::Integer(d)) if d >= 0 => self.max_depth = Some(d as usize),
+            ("min_depth", Value::Integer(d)) if d >= 0 => self.min_depth = d as usize,
+            ("follow_symlinks", Value::Bool(b)) => self.follow_symlinks = b,
+            ("hidden", Value::Bool(b)) => self.hidden = b,
+            ("errors", Value::String(s)) => self.errors = match s.as_ref() {
+                "report" => ErrorMode::Report,
+                "ignore" => ErrorMode::Ignore,
+                "fail" => ErrorMode::Fail,
+                _ => return argument_error("errors must be one of report, ignore and fail"),
+            },
+            ("threads", Value::Integer(t)) if t >= 1 && t <= MAX_THREADS as i128 => self.threads = t as usize,
+            ("threads", Value::Integer(_)) =>
+                return argument_error(format!("threads must be between 1 and {}", MAX_THREADS).as_str()),
+            ("sort", Value::Bool(b)) => self.sort = b,
+            ("ignore", Value::String(s)) => self.ignore = match s.as_ref() {
+                "git" => true,
+                "none" => false,
+                _ => return argument_error("ignore must be one of git and none"),
+            },
+            _ => return argument_error("Unknown argument"),
+        }
+        Ok(())
+    }
+
+    fn handle_error(&self, path: &Path, err: std::io::Error) -> CrushResult<()> {
+        self.report(path, err.to_string())
+    }
+
+    fn report(&self, path: &Path, err: String) -> CrushResult<()> {
+        let message = format!("{}: {}", path.to_string_lossy(), err);
+        match self.errors {
+            ErrorMode::Report => {
+                printer().error(message.as_str());
+                Ok(())
+            }
+            ErrorMode::Ignore => Ok(()),
+            ErrorMode::Fail => error(message.as_str()),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> std::io::Result<Metadata> {
+        if self.follow_symlinks {
+            /* Fall back to the link itself for dangling symlinks. */
+            fs::metadata(path).or_else(|_| fs::symlink_metadata(path))
+        } else {
+            fs::symlink_metadata(path)
+        }
+    }
+}
+
+/**
+    Call the visitor, handling its errors like any other error during the walk. An entry
+    the visitor failed on is not descended into.
+*/
+fn visit_entry(options: &Options, visit: Visitor<'_>, path: &Path, meta: &Metadata, depth: usize) -> CrushResult<Visit> {
+    match visit(path, meta, depth) {
+        Ok(v) => Ok(v),
+        Err(e) => {
+            options.report(path, e.message)?;
+            Ok(Visit::Skip)
+        }
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .map(|n| n.to_string_lossy().starts_with('.'))
+        .unwrap_or(false)
+}
+
+struct Task {
+    dir: Box<Path>,
+    depth: usize,
+    /** The absolute path of the directory and the ignore rules of its parent, if ignoring. */
+    ignore: Option<(PathBuf, IgnoreRules)>,
+}
+type Visitor<'a> = &'a (dyn Fn(&Path, &Metadata, usize) -> CrushResult<Visit> + Sync);
+
+/**
+    State shared between all the threads of a walk.
+*/
+struct Walk<'a> {
+    options: &'a Options,
+    visit: Visitor<'a>,
+    injector: Injector<Task>,
+    /** The number of directories that have been queued but not yet fully read. */
+    pending: AtomicUsize,
+    stopped: AtomicBool,
+    failure: Mutex<Option<CrushError>>,
+    /** Directories we have entered, only used when following symlinks. */
+    seen: Mutex<HashSet<(u64, u64)>>,
+    /** Held while visiting the entries of a directory when sorting, to keep them together. */
+    batch: Mutex<()>,
+    /** Idle workers wait on this until more work is queued or the walk is done. */
+    idle: Mutex<()>,
+    wakeup: Condvar,
+}
+
+fn find_task(local: &Worker<Task>, global: &Injector<Task>, stealers: &[Stealer<Task>]) -> Option<Task> {
+    local.pop().or_else(|| {
+        std::iter::repeat_with(|| {
+            global.steal_batch_and_pop(local)
+                .or_else(|| stealers.iter().map(|s| s.steal()).collect())
+        })
+            .find(|s: &Steal<Task>| !s.is_retry())
+            .and_then(|s| s.success())
+    })
+}
+
+impl<'a> Walk<'a> {
+    fn first_visit(&self, meta: &Metadata) -> bool {
+        !self.options.follow_symlinks || self.seen.lock().unwrap().insert((meta.dev(), meta.ino()))
+    }
+
+    fn stop(&self, err: Option<CrushError>) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(e) = err {
+            let mut failure = self.failure.lock().unwrap();
+            if failure.is_none() {
+                *failure = Some(e);
+            }
+        }
+        self.wake(true);
+    }
+
+    /**
+        Wake idle workers. Taking the lock makes sure a worker that has just found no work
+        is already waiting, so the wakeup can't get lost.
+    */
+    fn wake(&self, all: bool) {
+        let _idle = self.idle.lock().unwrap();
+        if all {
+            self.wakeup.notify_all();
+        } else {
+            self.wakeup.notify_one();
+        }
+    }
+
+    fn queue(&self, local: Option<&Worker<Task>>, task: Task) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        match local {
+            Some(w) => w.push(task),
+            None => self.injector.push(task),
+        }
+        self.wake(false);
+    }
+
+    fn has_work(&self, stealers: &[Stealer<Task>]) -> bool {
+        !self.injector.is_empty() || stealers.iter().any(|s| !s.is_empty())
+    }
+
+    fn entries(&self, dir: &Path, ignore: &Option<(PathBuf, IgnoreRules)>) -> CrushResult<Vec<(PathBuf, Metadata)>> {
+        let mut res = Vec::new();
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.options.handle_error(dir, e)?;
+                return Ok(res);
+            }
+        };
+        for maybe_entry in entries {
+            let entry = match maybe_entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.options.handle_error(dir, e)?;
+                    continue;
+                }
+            };
+            let path = entry.path();
+            if !self.options.hidden && is_hidden(&path) {
+                continue;
+            }
+            if let Some((absolute, rules)) = ignore {
+                /* Check before calling stat, the file type from the directory entry is enough. */
+                let name = entry.file_name();
+                let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                if name == ".git" || rules.is_ignored(&absolute.join(&name), is_dir) {
+                    continue;
+                }
+            }
+            match self.options.metadata(&path) {
+                Ok(meta) => res.push((path, meta)),
+                Err(e) => self.options.handle_error(&path, e)?,
+            }
+        }
+        if self.options.sort {
+            res.sort_by(|a, b| a.0.file_name().cmp(&b.0.file_name()));
+        }
+        Ok(res)
+    }
+
+    /**
+        Visit all the entries in a directory, queueing subdirectories. Returns false if the
+        walk should stop.
+    */
+    fn read_directory(&self, local: &Worker<Task>, task: Task) -> CrushResult<bool> {
+        let ignore = task.ignore.map(|(absolute, rules)| {
+            let rules = rules.for_directory(&absolute);
+            (absolute, rules)
+        });
+        let entries = self.entries(&task.dir, &ignore)?;
+        let _batch = if self.options.sort { Some(self.batch.lock().unwrap()) } else { None };
+        let entry_depth = task.depth + 1;
+        for (path, meta) in entries {
+            match visit_entry(self.options, self.visit, &path, &meta, entry_depth)? {
+                Visit::Stop => return Ok(false),
+                Visit::Skip => {}
+                Visit::Descend => {
+                    if meta.is_dir()
+                        && self.options.max_depth.map(|m| entry_depth < m).unwrap_or(true)
+                        && self.first_visit(&meta) {
+                        let child_ignore = match (&ignore, path.file_name()) {
+                            (Some((absolute, rules)), Some(name)) => Some((absolute.join(name), rules.clone())),
+                            _ => None,
+                        };
+                        self.queue(Some(local), Task {
+                            dir: path.into_boxed_path(),
+                            depth: entry_depth,
+                            ignore: child_ignore,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn run(&self, local: Worker<Task>, stealers: &[Stealer<Task>]) {
+        while !self.stopped.load(Ordering::SeqCst) {
+            match find_task(&local, &self.injector, stealers) {
+                Some(task) => {
+                    let res = self.read_directory(&local, task);
+                    if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                        /* That was the last directory, let everybody else exit. */
+                        self.wake(true);
+                    }
+                    match res {
+                        Ok(true) => {}
+                        Ok(false) => self.stop(None),
+                        Err(e) => self.stop(Some(e)),
+                    }
+                }
+                None => {
+                    let idle = self.idle.lock().unwrap();
+                    if self.pending.load(Ordering::SeqCst) == 0 {
+                        return;
+                    }
+                    if !self.stopped.load(Ordering::SeqCst) && !self.has_work(stealers) {
+                        /*
+                            Somebody else is still reading a directory and may queue more work.
+                            The timeout is only a safety net, queueing work wakes us up.
+                        */
+                        let _ = self.wakeup.wait_timeout(idle, Duration::from_millis(100)).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/**
+    Walk the specified roots, calling visit for every file found. It is up to the visitor to
+    honour min_depth, since it may still want to see shallower directories, e.g. in order to
+    prune them.
+
+    A root that is a directory is not itself visited, only its contents are, at depth 1 and
+    deeper. Roots that are not directories are visited at depth 0. Roots are always
+    followed if they are symlinks, the follow_symlinks option only affects what is found
+    during the walk. When following symlinks, every directory is only entered once, so
+    symlink loops are harmless.
+
+    Errors returned by visit are handled according to the errors option, like errors reading
+    the file system.
+
+    If the ignore option is set, ignore files are read as the walk goes down, and ignored
+    files are never visited. Ignored directories are never opened.
+
+    Directories are read by a pool of worker threads that steal work from each other, so
+    visit is called from multiple threads, and entries from different directories are
+    interleaved. If the sort option is set, the entries of each directory are visited
+    together in order of file name, but the order of the directories themselves depends on
+    thread scheduling. A walk with a single thread is breadth first, and with sorting its
+    output is fully deterministic.
+*/
+pub fn walk(options: &Options, roots: Vec<Box<Path>>, visit: Visitor<'_>) -> CrushResult<()> {
+    let state = Walk {
+        options,
+        visit,
+        injector: Injector::new(),
+        pending: AtomicUsize::new(0),
+        stopped: AtomicBool::new(false),
+        failure: Mutex::new(None),
+        seen: Mutex::new(HashSet::new()),
+        batch: Mutex::new(()),
+        idle: Mutex::new(()),
+        wakeup: Condvar::new(),
+    };
+
+    for root in roots {
+        match root.metadata() {
+            Ok(meta) => {
+                if meta.is_dir() {
+                    if options.max_depth != Some(0) && state.first_visit(&meta) {
+                        let ignore = if options.ignore {
+                            match root.canonicalize() {
+                                Ok(absolute) => {
+                                    let rules = IgnoreRules::for_root(&absolute);
+                                    Some((absolute, rules))
+                                }
+                                Err(e) => {
+                                    options.handle_error(&root, e)?;
+                                    continue;
+                                }
+                            }
+                        } else {
+                            None
+                        };
+                        state.queue(None, Task { dir: root, depth: 0, ignore });
+                    }
+                } else if visit_entry(options, visit, &root, &meta, 0)? == Visit::Stop {
+                    return Ok(());
+                }
+            }
+            Err(e) => options.handle_error(&root, e)?,
+        }
+    }
+
+    let workers = (0..options.threads).map(|_| Worker::new_fifo()).collect::<Vec<_>>();
+    let stealers = workers.iter().map(|w| w.stealer()).collect::<Vec<_>>();
+    let scope_result = crossbeam::scope(|s| {
+        for worker in workers {
+            let state = &state;
+            let stealers = &stealers;
+            s.spawn(move |_| state.run(worker, stealers));
+        }
+    });
+    if scope_result.is_err() {
+        return error("A traversal thread panicked");
+    }
+
+    match state.failure.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("crush-walk-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/c"), "").unwrap();
+        fs::write(root.join("d"), "").unwrap();
+        root
+    }
+
+    fn names(options: &Options, root: &Path, fail_on: &str) -> CrushResult<Vec<String>> {
+        let seen = Mutex::new(Vec::new());
+        walk(options, vec![Box::from(root)], &|path: &Path, _meta: &Metadata, _depth: usize| {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if name == fail_on {
+                return error("broken");
+            }
+            seen.lock().unwrap().push(name);
+            Ok(Visit::Descend)
+        })?;
+        let mut res = seen.into_inner().unwrap();
+        res.sort();
+        Ok(res)
+    }
+
+    #[test]
+    fn parallel_walks_visit_everything() {
+        let root = tree("parallel");
+        let mut options = Options::new(None);
+        options.threads = 8;
+        assert_eq!(names(&options, &root, "").unwrap(), vec!["a", "b", "c", "d"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sorted_walks_with_one_thread_are_deterministic() {
+        let root = tree("sorted");
+        fs::write(root.join("a/0"), "").unwrap();
+        let mut options = Options::new(None);
+        options.sort = true;
+        options.threads = 1;
+        let seen = Mutex::new(Vec::new());
+        walk(&options, vec![Box::from(root.as_path())], &|path: &Path, _meta: &Metadata, _depth: usize| {
+            seen.lock().unwrap().push(path.strip_prefix(&root).unwrap().to_string_lossy().to_string());
+            Ok(Visit::Descend)
+        }).unwrap();
+        assert_eq!(seen.into_inner().unwrap(), vec!["a", "d", "a/0", "a/b", "a/b/c"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn max_depth_stops_the_walk() {
+        let root = tree("depth");
+        assert_eq!(names(&Options::new(Some(1)), &root, "").unwrap(), vec!["a", "d"]);
+        assert_eq!(names(&Options::new(Some(2)), &root, "").unwrap(), vec!["a", "b", "d"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn visitor_errors_follow_the_error_mode() {
+        let root = tree("errors");
+        let mut options = Options::new(None);
+        options.errors = ErrorMode::Ignore;
+        assert_eq!(names(&options, &root, "a").unwrap(), vec!["d"]);
+        options.errors = ErrorMode::Fail;
+        assert!(names(&options, &root, "a").is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor};
+
+use crate::lang::execution_context::ExecutionContext;
+use crate::lang::errors::{argument_error, to_crush_error, CrushResult};
+use crate::lang::printer::printer;
+use crate::lang::{table::ColumnType, table::Row, value::Value, value::ValueType};
+
+/* How often, in milliseconds, to check if anyone is still reading our output when no files change. */
+const POLL_TIMEOUT: i32 = 500;
+
+struct Watcher {
+    inotify: Inotify,
+    watches: HashMap<WatchDescriptor, PathBuf>,
+    recursive: bool,
+}
+
+fn flags() -> AddWatchFlags {
+    AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MODIFY | AddWatchFlags::IN_DELETE |
+        AddWatchFlags::IN_DELETE_SELF | AddWatchFlags::IN_MOVED_FROM | AddWatchFlags::IN_MOVED_TO
+}
+
+fn event_name(mask: AddWatchFlags) -> Option<&'static str> {
+    if mask.contains(AddWatchFlags::IN_CREATE) {
+        Some("create")
+    } else if mask.contains(AddWatchFlags::IN_MODIFY) {
+        Some("modify")
+    } else if mask.intersects(AddWatchFlags::IN_DELETE | AddWatchFlags::IN_DELETE_SELF) {
+        Some("delete")
+    } else if mask.intersects(AddWatchFlags::IN_MOVED_FROM | AddWatchFlags::IN_MOVED_TO) {
+        Some("rename")
+    } else {
+        None
+    }
+}
+
+impl Watcher {
+    fn add(&mut self, path: &Path) -> CrushResult<()> {
+        let wd = to_crush_error(self.inotify.add_watch(path, flags()))?;
+        self.watches.insert(wd, path.to_path_buf());
+        if self.recursive && path.is_dir() {
+            for entry in to_crush_error(fs::read_dir(path))? {
+                let entry = to_crush_error(entry)?;
+                if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                    /* Directories come and go, don't give up on the whole watch if one does. */
+                    if let Err(e) = self.add(&entry.path()) {
+                        printer().crush_error(e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+        Wait for events, giving up after the poll timeout. Returns an empty list on timeout.
+    */
+    fn events(&self) -> CrushResult<Vec<InotifyEvent>> {
+        let mut fds = [PollFd::new(self.inotify.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, POLL_TIMEOUT) {
+            Ok(0) | Err(nix::Error::Sys(Errno::EINTR)) => return Ok(vec![]),
+            Ok(_) => {}
+            Err(e) => return to_crush_error(Err(e)),
+        }
+        match self.inotify.read_events() {
+            Err(nix::Error::Sys(Errno::EAGAIN)) => Ok(vec![]),
+            res => to_crush_error(res),
+        }
+    }
+}
+
+pub fn perform(context: ExecutionContext) -> CrushResult<()> {
+    let mut files = Vec::new();
+    let mut recursive = false;
+    for arg in context.arguments {
+        match (arg.argument_type.as_deref(), arg.value) {
+            (None, value) => value.file_expand(&mut files)?,
+            (Some("recursive"), Value::Bool(b)) => recursive = b,
+            _ => return argument_error("Unknown argument"),
+        }
+    }
+    if files.is_empty() {
+        return argument_error("No files to watch");
+    }
+
+    let mut watcher = Watcher {
+        inotify: to_crush_error(Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK))?,
+        watches: HashMap::new(),
+        recursive,
+    };
+    for file in &files {
+        watcher.add(file)?;
+    }
+
+    let output = context.output.initialize(vec![
+        ColumnType::new("time", ValueType::Time),
+        ColumnType::new("event", ValueType::String),
+        ColumnType::new("file", ValueType::File),
+    ])?;
+
+    loop {
+        if output.is_closed() {
+            /* Whoever is reading our output has stopped listening. */
+            return Ok(());
+        }
+        for event in watcher.events()? {
+            let dir = match watcher.watches.get(&event.wd) {
+                Some(dir) => dir.clone(),
+                None => continue,
+            };
+            let file = match &event.name {
+                Some(name) => dir.join(name),
+                None => dir,
+            };
+            if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                watcher.watches.remove(&event.wd);
+            }
+            if recursive && event.mask.contains(AddWatchFlags::IN_ISDIR)
+                && event.mask.intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO) {
+                if let Err(e) = watcher.add(&file) {
+                    printer().crush_error(e);
+                }
+            }
+            if let Some(name) = event_name(event.mask) {
+                let row = Row::new(vec![
+                    Value::Time(Local::now()),
+                    Value::string(name),
+                    Value::File(file.into_boxed_path()),
+                ]);
+                if output.send(row).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
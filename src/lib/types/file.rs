@@ -1,9 +1,13 @@
 use crate::lang::execution_context::{ExecutionContext, This, ArgumentVector};
-use crate::lang::errors::{CrushResult, to_crush_error};
+use crate::lang::errors::{CrushResult, to_crush_error, argument_error, error, mandate};
 use crate::lang::r#struct::Struct;
-use crate::lang::value::Value;
-use std::fs::metadata;
+use crate::lang::value::{Value, ValueType};
+use crate::lang::binary::BinaryReader;
+use crate::lang::{table::ColumnType, table::Row};
+use std::fs::{metadata, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
 use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 use crate::lang::command::CrushCommand;
@@ -35,6 +39,85 @@ lazy_static! {
             "file[name:string]",
             "Return a file or subdirectory in the specified base directory",
             None));
+        res.insert(Box::from("read"), CrushCommand::command(
+            read, true,
+            "file:read",
+            "Return the contents of this file as a binary stream",
+            None));
+        res.insert(Box::from("write"), CrushCommand::command(
+            write, true,
+            "file:write value:(string|binary|binary_stream)",
+            "Replace the contents of this file with the specified value",
+            Some(r#"    The file is created if it does not exist. Strings are written as utf-8
+    without a trailing newline.
+
+    Example:
+
+    (/tmp/notes.txt):write "hello""#)));
+        res.insert(Box::from("append"), CrushCommand::command(
+            append, true,
+            "file:append value:(string|binary|binary_stream)",
+            "Append the specified value to the end of this file",
+            Some(r#"    The file is created if it does not exist."#)));
+        res.insert(Box::from("lines"), CrushCommand::command(
+            lines, true,
+            "file:lines",
+            "Return a table stream with one line of text from this file per row",
+            None));
+        res.insert(Box::from("parent"), CrushCommand::command(
+            parent, false,
+            "file:parent",
+            "Return the directory containing this file",
+            Some(r#"    This is a purely lexical operation, the file system is not accessed. The
+    root directory has no parent."#)));
+        res.insert(Box::from("name"), CrushCommand::command(
+            name, false,
+            "file:name",
+            "Return the final component of this path as a string",
+            None));
+        res.insert(Box::from("stem"), CrushCommand::command(
+            stem, false,
+            "file:stem",
+            "Return the name of this file without its extension",
+            Some(r#"    Example:
+
+    (/tmp/archive.tar.gz):stem  # archive.tar"#)));
+        res.insert(Box::from("extension"), CrushCommand::command(
+            extension, false,
+            "file:extension",
+            "Return the extension of this file, without the leading dot",
+            Some(r#"    Files without an extension, and files whose name starts with a dot and
+    contains no other dots, return an empty string."#)));
+        res.insert(Box::from("with_extension"), CrushCommand::command(
+            with_extension, false,
+            "file:with_extension extension:string",
+            "Return this file with its extension replaced",
+            Some(r#"    An empty extension removes the current one.
+
+    Example:
+
+    (/tmp/main.rs):with_extension "o"  # /tmp/main.o"#)));
+        res.insert(Box::from("canonical"), CrushCommand::command(
+            canonical, true,
+            "file:canonical",
+            "Return the absolute path of this file with all symbolic links resolved",
+            Some(r#"    The file must exist."#)));
+        res.insert(Box::from("relative_to"), CrushCommand::command(
+            relative_to, false,
+            "file:relative_to base:(file|string)",
+            "Return this file as a path relative to the specified base directory",
+            Some(r#"    This is a purely lexical operation, the file system is not accessed, so
+    both paths must either be absolute or relative. Parent directories are
+    written as "..".
+
+    Example:
+
+    (/usr/lib/x86_64):relative_to /usr/bin  # ../lib/x86_64"#)));
+        res.insert(Box::from("is_absolute"), CrushCommand::command(
+            is_absolute, false,
+            "file:is_absolute",
+            "Return true if this path is absolute",
+            None));
         res
     };
 }
@@ -70,3 +153,176 @@ pub fn getitem(mut context: ExecutionContext) -> CrushResult<()> {
     let sub = context.arguments.string(0)?;
     context.output.send(Value::File(base_directory.join(sub.as_ref()).into_boxed_path()))
 }
+
+fn read(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let file = context.this.file()?;
+    context.output.send(Value::BinaryStream(BinaryReader::paths(vec![file])?))
+}
+
+fn write_value(file: &Path, value: Value, append: bool) -> CrushResult<()> {
+    let mut out = to_crush_error(
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(file))?;
+    match value {
+        Value::String(s) => to_crush_error(out.write_all(s.as_bytes())),
+        Value::Binary(b) => to_crush_error(out.write_all(&b)),
+        Value::BinaryStream(mut b) => to_crush_error(std::io::copy(&mut b, &mut out)).map(|_| ()),
+        v => argument_error(
+            format!("Expected a string or binary data, found {}", v.value_type().to_string()).as_str()),
+    }
+}
+
+fn write(mut context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(1)?;
+    let value = context.arguments.value(0)?;
+    write_value(&context.this.file()?, value, false)
+}
+
+fn append(mut context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(1)?;
+    let value = context.arguments.value(0)?;
+    write_value(&context.this.file()?, value, true)
+}
+
+fn lines(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let file = context.this.file()?;
+    let mut reader = BufReader::new(BinaryReader::paths(vec![file])?);
+    let output = context.output.initialize(vec![ColumnType::new("line", ValueType::String)])?;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if to_crush_error(reader.read_line(&mut line))? == 0 {
+            return Ok(());
+        }
+        let text = line.strip_suffix('\n').unwrap_or(&line);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        if output.send(Row::new(vec![Value::string(text)])).is_err() {
+            /* Whoever is reading our output has stopped listening. */
+            return Ok(());
+        }
+    }
+}
+
+fn parent(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let file = context.this.file()?;
+    let parent = mandate(file.parent(), "File has no parent")?;
+    /* The parent of a relative file name without any slashes is the current directory. */
+    let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+    context.output.send(Value::File(Box::from(parent)))
+}
+
+fn name(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let file = context.this.file()?;
+    let name = mandate(file.file_name(), "File has no name")?;
+    context.output.send(Value::string(name.to_string_lossy().as_ref()))
+}
+
+fn stem(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let file = context.this.file()?;
+    let stem = mandate(file.file_stem(), "File has no name")?;
+    context.output.send(Value::string(stem.to_string_lossy().as_ref()))
+}
+
+fn extension(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let file = context.this.file()?;
+    let extension = file.extension()
+        .map(|e| e.to_string_lossy().to_string())
+        .unwrap_or_default();
+    context.output.send(Value::string(extension.as_str()))
+}
+
+fn with_extension(mut context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(1)?;
+    let extension = context.arguments.string(0)?;
+    let file = context.this.file()?;
+    if file.file_name().is_none() {
+        return error("File has no name");
+    }
+    context.output.send(Value::File(file.with_extension(extension.as_ref()).into_boxed_path()))
+}
+
+fn canonical(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let file = context.this.file()?;
+    context.output.send(Value::File(to_crush_error(file.canonicalize())?.into_boxed_path()))
+}
+
+fn normalized_components(path: &Path) -> Vec<Component<'_>> {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+/**
+    Compute the path that leads from the directory base to file, without looking at the
+    file system.
+*/
+fn relative_path(file: &Path, base: &Path) -> CrushResult<PathBuf> {
+    if file.is_absolute() != base.is_absolute() {
+        return argument_error("Both paths must be absolute or both must be relative");
+    }
+    let file = normalized_components(file);
+    let base = normalized_components(base);
+    let common = file.iter().zip(base.iter()).take_while(|(a, b)| a == b).count();
+    if base[common..].iter().any(|c| *c == Component::ParentDir) {
+        return argument_error("Can't compute a relative path from a base containing \"..\"");
+    }
+    let mut res = PathBuf::new();
+    for _ in common..base.len() {
+        res.push("..");
+    }
+    for c in &file[common..] {
+        res.push(c);
+    }
+    if res.as_os_str().is_empty() {
+        res.push(".");
+    }
+    Ok(res)
+}
+
+fn relative_to(mut context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(1)?;
+    let base: Box<Path> = match context.arguments.value(0)? {
+        Value::File(f) => f,
+        Value::String(s) => Box::from(Path::new(s.as_ref())),
+        v => return argument_error(
+            format!("Expected a file, found {}", v.value_type().to_string()).as_str()),
+    };
+    let file = context.this.file()?;
+    context.output.send(Value::File(relative_path(&file, &base)?.into_boxed_path()))
+}
+
+fn is_absolute(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    context.output.send(Value::Bool(context.this.file()?.is_absolute()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relative(file: &str, base: &str) -> String {
+        relative_path(Path::new(file), Path::new(base)).unwrap().to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative("/usr/lib/x86_64", "/usr/bin"), "../lib/x86_64");
+        assert_eq!(relative("/usr/lib", "/usr"), "lib");
+        assert_eq!(relative("/usr", "/usr/lib"), "..");
+        assert_eq!(relative("/usr", "/usr/"), ".");
+        assert_eq!(relative("a/./b", "a"), "b");
+        assert!(relative_path(Path::new("/a"), Path::new("a")).is_err());
+        assert!(relative_path(Path::new("a"), Path::new("../b")).is_err());
+    }
+}
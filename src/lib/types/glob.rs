@@ -11,10 +11,10 @@ lazy_static! {
         let mut res: HashMap<Box<str>, Box<dyn CrushCommand +  Send + Sync>> = HashMap::new();
         res.insert(Box::from("match"), CrushCommand::command(
             r#match, false,
-            "glob:match input:string", "True if the input matches the pattern", None));
+            "glob:match input:(string|file)", "True if the input matches the pattern", None));
         res.insert(Box::from("not_match"), CrushCommand::command(
             not_match, false,
-            "glob:not_match input:string", "True if the input does not match the pattern", None));
+            "glob:not_match input:(string|file)", "True if the input does not match the pattern", None));
         res.insert(Box::from("new"), CrushCommand::command(
             new, false,
             "glob:new pattern:string", "Return a new glob", None));
@@ -29,12 +29,12 @@ fn new(mut context: ExecutionContext) -> CrushResult<()> {
 
 fn r#match(mut context: ExecutionContext) -> CrushResult<()> {
     let g = context.this.glob()?;
-    let needle = context.arguments.string(0)?;
+    let needle = context.arguments.string_or_file(0)?;
     context.output.send(Value::Bool(g.matches(&needle)))
 }
 
 fn not_match(mut context: ExecutionContext) -> CrushResult<()> {
     let g = context.this.glob()?;
-    let needle = context.arguments.string(0)?;
+    let needle = context.arguments.string_or_file(0)?;
     context.output.send(Value::Bool(!g.matches(&needle)))
 }
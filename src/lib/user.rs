@@ -0,0 +1,141 @@
+use crate::lang::command::CrushCommand;
+use crate::lang::errors::{CrushResult, argument_error, mandate};
+use crate::lang::execution_context::{ExecutionContext, ArgumentVector};
+use crate::lang::{list::List, table::ColumnType, table::Row, value::Value, value::ValueType};
+use crate::lang::scope::Scope;
+use crate::util::user_map::{all_groups, create_user_map, GroupEntry};
+use users::User;
+use users::os::unix::UserExt;
+
+fn user_columns() -> Vec<ColumnType> {
+    vec![
+        ColumnType::new("name", ValueType::String),
+        ColumnType::new("uid", ValueType::Integer),
+        ColumnType::new("gid", ValueType::Integer),
+        ColumnType::new("home", ValueType::File),
+        ColumnType::new("shell", ValueType::File),
+        ColumnType::new("groups", ValueType::List(Box::from(ValueType::String))),
+    ]
+}
+
+/**
+    The names of all groups the user is a member of, starting with the primary group.
+*/
+fn user_groups(user: &User, groups: &Vec<GroupEntry>) -> Value {
+    let name = user.name().to_string_lossy();
+    let mut res = Vec::new();
+    for g in groups.iter().filter(|g| g.gid == user.primary_group_id()) {
+        res.push(Value::string(g.name.as_str()));
+    }
+    for g in groups.iter().filter(|g| g.gid != user.primary_group_id() && g.members.iter().any(|m| *m == name)) {
+        res.push(Value::string(g.name.as_str()));
+    }
+    Value::List(List::new(ValueType::String, res))
+}
+
+fn user_cells(user: &User, groups: &Vec<GroupEntry>) -> Vec<Value> {
+    vec![
+        Value::String(Box::from(user.name().to_string_lossy().as_ref())),
+        Value::Integer(user.uid() as i128),
+        Value::Integer(user.primary_group_id() as i128),
+        Value::File(Box::from(user.home_dir())),
+        Value::File(Box::from(user.shell())),
+        user_groups(user, groups),
+    ]
+}
+
+fn user_struct(user: &User) -> Value {
+    Value::Struct(Row::new(user_cells(user, &all_groups())).into_struct(&user_columns()))
+}
+
+fn list(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let output = context.output.initialize(user_columns())?;
+    let groups = all_groups();
+    let mut users = create_user_map().into_iter().map(|(_, u)| u).collect::<Vec<User>>();
+    users.sort_by_key(|u| u.uid());
+    for user in users {
+        output.send(Row::new(user_cells(&user, &groups)))?;
+    }
+    Ok(())
+}
+
+fn current(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let user = mandate(
+        users::get_user_by_uid(users::get_current_uid()),
+        "Could not find the current user")?;
+    context.output.send(user_struct(&user))
+}
+
+fn info(mut context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(1)?;
+    let name = context.arguments.string(0)?;
+    match users::get_user_by_name(name.as_ref()) {
+        Some(user) => context.output.send(user_struct(&user)),
+        None => argument_error(format!("Unknown user {}", name).as_str()),
+    }
+}
+
+fn group_list(context: ExecutionContext) -> CrushResult<()> {
+    context.arguments.check_len(0)?;
+    let output = context.output.initialize(vec![
+        ColumnType::new("name", ValueType::String),
+        ColumnType::new("gid", ValueType::Integer),
+        ColumnType::new("members", ValueType::List(Box::from(ValueType::String))),
+    ])?;
+    for group in all_groups() {
+        output.send(Row::new(vec![
+            Value::String(group.name.into_boxed_str()),
+            Value::Integer(group.gid as i128),
+            Value::List(List::new(
+                ValueType::String,
+                group.members.iter().map(|m| Value::string(m.as_str())).collect())),
+        ]))?;
+    }
+    Ok(())
+}
+
+pub fn declare(root: &Scope) -> CrushResult<()> {
+    let user = root.create_namespace("user")?;
+    user.declare("list", Value::Command(CrushCommand::command(
+        list, true,
+        "user:list", "Return a table stream of all users on the system",
+        Some(r#"    Each row contains the following columns:
+
+    * name:string the name of the user
+    * uid:integer the user id
+    * gid:integer the id of the primary group of the user
+    * home:file the home directory of the user
+    * shell:file the login shell of the user
+    * groups:list the names of all groups the user is a member of
+
+    Example:
+
+    user:list | where {shell == /bin/bash}"#))))?;
+    user.declare("current", Value::Command(CrushCommand::command(
+        current, false,
+        "user:current", "Return a struct with information about the current user",
+        Some(r#"    The struct has the same fields as the rows returned by user:list.
+
+    Example:
+
+    ls | where {user == (user:current):name}"#))))?;
+    user.declare("info", Value::Command(CrushCommand::command(
+        info, false,
+        "user:info name:string", "Return a struct with information about the specified user",
+        Some(r#"    The struct has the same fields as the rows returned by user:list."#))))?;
+    user.readonly();
+
+    let group = root.create_namespace("group")?;
+    group.declare("list", Value::Command(CrushCommand::command(
+        group_list, true,
+        "group:list", "Return a table stream of all groups on the system",
+        Some(r#"    Groups are read from /etc/group. Each row contains the following columns:
+
+    * name:string the name of the group
+    * gid:integer the group id
+    * members:list the names of all users that have the group as a secondary group"#))))?;
+    group.readonly();
+    Ok(())
+}
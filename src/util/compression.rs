@@ -0,0 +1,179 @@
+use std::io::{Cursor, Read, Write};
+
+use crate::lang::errors::{argument_error, to_crush_error, CrushResult};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+/**
+    How files should be decompressed when read.
+*/
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Decompress {
+    None,
+    /** Decompress files that start with the magic bytes of a known format. */
+    Auto,
+    Format(Compression),
+}
+
+/* The number of bytes needed to recognize all supported formats. */
+const MAGIC_LEN: u64 = 6;
+
+impl Compression {
+    pub fn from_name(name: &str) -> Option<Compression> {
+        match name {
+            "gzip" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            "xz" => Some(Compression::Xz),
+            "bzip2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    pub fn detect(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if magic.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    /**
+        The valid range and the default of the compression level.
+    */
+    fn levels(&self) -> (i128, i128, i128) {
+        match self {
+            Compression::Gzip => (0, 9, 6),
+            Compression::Zstd => (1, 22, 3),
+            Compression::Xz => (0, 9, 6),
+            Compression::Bzip2 => (1, 9, 9),
+        }
+    }
+
+    pub fn level(&self, level: Option<i128>) -> CrushResult<u32> {
+        let (min, max, default) = self.levels();
+        match level.unwrap_or(default) {
+            l if l >= min && l <= max => Ok(l as u32),
+            _ => argument_error(format!("The compression level must be between {} and {}", min, max).as_str()),
+        }
+    }
+
+    pub fn decoder(&self, input: Box<dyn Read + Send>) -> CrushResult<Box<dyn Read + Send>> {
+        /* Use the multi member decoders, since concatenated compressed files are valid input to all tools. */
+        Ok(match self {
+            Compression::Gzip => Box::from(flate2::read::MultiGzDecoder::new(input)),
+            Compression::Zstd => Box::from(to_crush_error(zstd::stream::read::Decoder::new(input))?),
+            Compression::Xz => Box::from(xz2::read::XzDecoder::new_multi_decoder(input)),
+            Compression::Bzip2 => Box::from(bzip2::read::MultiBzDecoder::new(input)),
+        })
+    }
+
+    pub fn compress(&self, input: &mut dyn Read, output: &mut dyn Write, level: u32) -> CrushResult<()> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::new(level));
+                to_crush_error(std::io::copy(input, &mut encoder))?;
+                to_crush_error(encoder.finish())?;
+            }
+            Compression::Zstd => {
+                let mut encoder = to_crush_error(zstd::stream::write::Encoder::new(output, level as i32))?;
+                to_crush_error(std::io::copy(input, &mut encoder))?;
+                to_crush_error(encoder.finish())?;
+            }
+            Compression::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(output, level);
+                to_crush_error(std::io::copy(input, &mut encoder))?;
+                to_crush_error(encoder.finish())?;
+            }
+            Compression::Bzip2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(output, bzip2::Compression::new(level));
+                to_crush_error(std::io::copy(input, &mut encoder))?;
+                to_crush_error(encoder.finish())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Decompress {
+    pub fn parse(name: &str) -> CrushResult<Decompress> {
+        match name {
+            "none" => Ok(Decompress::None),
+            "auto" => Ok(Decompress::Auto),
+            name => match Compression::from_name(name) {
+                Some(c) => Ok(Decompress::Format(c)),
+                None => argument_error(format!("Unknown compression format {}", name).as_str()),
+            },
+        }
+    }
+
+    /**
+        Wrap the input in a decoder if needed. In auto mode, the first few bytes are
+        read to find the format and then put back in front of the rest of the input.
+    */
+    pub fn reader(&self, mut input: Box<dyn Read + Send>) -> CrushResult<Box<dyn Read + Send>> {
+        match self {
+            Decompress::None => Ok(input),
+            Decompress::Format(c) => c.decoder(input),
+            Decompress::Auto => {
+                let mut magic = Vec::new();
+                to_crush_error((&mut input).take(MAGIC_LEN).read_to_end(&mut magic))?;
+                let format = Compression::detect(&magic);
+                let input = Box::from(Cursor::new(magic).chain(input));
+                match format {
+                    Some(c) => c.decoder(input),
+                    None => Ok(input),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(format: Compression, decompress: Decompress) {
+        let data = b"hello hello hello hello\n".repeat(100);
+        let mut compressed = Vec::new();
+        format.compress(&mut data.as_slice(), &mut compressed, format.level(None).unwrap()).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(Compression::detect(&compressed), Some(format));
+        let mut res = Vec::new();
+        decompress.reader(Box::from(Cursor::new(compressed))).unwrap().read_to_end(&mut res).unwrap();
+        assert_eq!(res, data);
+    }
+
+    #[test]
+    fn all_formats_round_trip() {
+        for format in &[Compression::Gzip, Compression::Zstd, Compression::Xz, Compression::Bzip2] {
+            round_trip(*format, Decompress::Format(*format));
+            round_trip(*format, Decompress::Auto);
+        }
+    }
+
+    #[test]
+    fn auto_passes_through_uncompressed_input() {
+        let mut res = Vec::new();
+        Decompress::Auto.reader(Box::from(Cursor::new(b"BZ".to_vec()))).unwrap()
+            .read_to_end(&mut res).unwrap();
+        assert_eq!(res, b"BZ");
+    }
+
+    #[test]
+    fn levels_are_checked() {
+        assert!(Compression::Zstd.level(Some(0)).is_err());
+        assert_eq!(Compression::Gzip.level(Some(9)).unwrap(), 9);
+    }
+}
@@ -0,0 +1,428 @@
+use std::fs;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use std::sync::Arc;
+
+use crate::util::file::home;
+
+#[derive(Clone, Debug)]
+enum Token {
+    Char(char),
+    /** A ?, matching any single character. */
+    AnyChar,
+    /** A *, matching any number of characters. */
+    AnyChars,
+    /** A character class like [a-z] or [!0-9]. */
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(expected) => *expected == c,
+            Token::AnyChar => true,
+            Token::AnyChars => false,
+            Token::Class { negated, ranges } =>
+                ranges.iter().any(|(from, to)| *from <= c && c <= *to) != *negated,
+        }
+    }
+}
+
+/**
+    A single path component of a gitignore pattern, matched the way git does it, with
+    *, ?, character classes and backslash escapes.
+*/
+#[derive(Clone, Debug)]
+struct NamePattern {
+    tokens: Vec<Token>,
+}
+
+/**
+    Parse the character class starting after a [. Returns None if the class is never closed,
+    in which case git treats the [ as a literal character.
+*/
+fn parse_class(chars: &mut Peekable<Chars>) -> Option<Token> {
+    let mut class = chars.clone();
+    let negated = match class.peek() {
+        Some('!') | Some('^') => {
+            class.next();
+            true
+        }
+        _ => false,
+    };
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = match class.next()? {
+            ']' if !first => break,
+            '\\' => class.next()?,
+            c => c,
+        };
+        first = false;
+        let mut lookahead = class.clone();
+        match (lookahead.next(), lookahead.peek()) {
+            (Some('-'), Some(end)) if *end != ']' => {
+                let end = match lookahead.next()? {
+                    '\\' => lookahead.next()?,
+                    end => end,
+                };
+                ranges.push((c, end));
+                class = lookahead;
+            }
+            _ => ranges.push((c, c)),
+        }
+    }
+    *chars = class;
+    Some(Token::Class { negated, ranges })
+}
+
+impl NamePattern {
+    fn parse(component: &str) -> NamePattern {
+        let mut tokens = Vec::new();
+        let mut chars = component.chars().peekable();
+        while let Some(c) = chars.next() {
+            tokens.push(match c {
+                '*' => Token::AnyChars,
+                '?' => Token::AnyChar,
+                '[' => parse_class(&mut chars).unwrap_or(Token::Char('[')),
+                '\\' => match chars.next() {
+                    Some(escaped) => Token::Char(escaped),
+                    None => continue,
+                },
+                c => Token::Char(c),
+            });
+        }
+        NamePattern { tokens }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let name = name.chars().collect::<Vec<_>>();
+        let (mut token, mut idx) = (0, 0);
+        /* Where to resume if what follows the last * fails to match: after the *, and one
+        character further into the name than last time. */
+        let mut backtrack = None;
+        while idx < name.len() {
+            match self.tokens.get(token) {
+                Some(Token::AnyChars) => {
+                    token += 1;
+                    backtrack = Some((token, idx));
+                }
+                Some(t) if t.matches(name[idx]) => {
+                    token += 1;
+                    idx += 1;
+                }
+                _ => match backtrack {
+                    Some((star_token, star_idx)) => {
+                        token = star_token;
+                        idx = star_idx + 1;
+                        backtrack = Some((star_token, idx));
+                    }
+                    None => return false,
+                },
+            }
+        }
+        self.tokens[token..].iter().all(|t| matches!(t, Token::AnyChars))
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Component {
+    Name(NamePattern),
+    /** A ** component, matching any number of directories. */
+    AnyDepth,
+}
+
+#[derive(Clone, Debug)]
+struct Pattern {
+    components: Vec<Component>,
+    negated: bool,
+    directory_only: bool,
+    /** Patterns without a slash match a file name at any depth. */
+    anchored: bool,
+}
+
+fn match_components(pattern: &[Component], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (None, Some(_)) => false,
+        /* A trailing ** matches everything inside a directory, but not the directory itself. */
+        (Some(Component::AnyDepth), _) if pattern.len() == 1 => !path.is_empty(),
+        (Some(Component::AnyDepth), _) =>
+            (0..=path.len()).any(|skip| match_components(&pattern[1..], &path[skip..])),
+        (Some(Component::Name(_)), None) => false,
+        (Some(Component::Name(name_pattern)), Some(name)) =>
+            name_pattern.matches(name) && match_components(&pattern[1..], &path[1..]),
+    }
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Pattern> {
+        let mut line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let negated = line.starts_with('!');
+        if negated || line.starts_with("\\!") || line.starts_with("\\#") {
+            line = &line[1..];
+        }
+        let directory_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+        let anchored = line.contains('/');
+        let components = line.trim_start_matches('/')
+            .split('/')
+            .filter(|c| !c.is_empty())
+            .map(|c| if c == "**" { Component::AnyDepth } else { Component::Name(NamePattern::parse(c)) })
+            .collect();
+        Some(Pattern { components, negated, directory_only, anchored })
+    }
+
+    fn matches(&self, path: &[&str], is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            match_components(&self.components, path)
+        } else {
+            path.last()
+                .map(|name| match_components(&self.components, &[name]))
+                .unwrap_or(false)
+        }
+    }
+}
+
+/**
+    The patterns of a single ignore file. Patterns are matched against paths relative to
+    the directory containing the file.
+*/
+#[derive(Debug)]
+pub struct IgnoreFile {
+    base: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreFile {
+    pub fn parse(base: &Path, content: &str) -> IgnoreFile {
+        IgnoreFile {
+            base: base.to_path_buf(),
+            patterns: content.lines().filter_map(Pattern::parse).collect(),
+        }
+    }
+
+    /**
+        Read an ignore file. Missing or unreadable files are treated as empty.
+    */
+    fn read(base: &Path, file: &Path) -> Option<IgnoreFile> {
+        fs::read_to_string(file).ok()
+            .map(|content| IgnoreFile::parse(base, content.as_str()))
+            .filter(|f| !f.patterns.is_empty())
+    }
+}
+
+/**
+    Find the global git excludes file, either as configured with core.excludesFile in
+    ~/.gitconfig, or in its default location.
+*/
+fn global_excludes_file() -> Option<PathBuf> {
+    let home = home().ok()?;
+    if let Ok(config) = fs::read_to_string(home.join(".gitconfig")) {
+        let mut in_core = false;
+        for line in config.lines().map(|l| l.trim()) {
+            if line.starts_with('[') {
+                in_core = line.to_lowercase() == "[core]";
+            } else if in_core {
+                let mut parts = line.splitn(2, '=');
+                if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+                    if key.trim().to_lowercase() == "excludesfile" {
+                        let value = value.trim();
+                        return Some(if let Some(relative) = value.strip_prefix("~/") {
+                            home.join(relative)
+                        } else {
+                            PathBuf::from(value)
+                        });
+                    }
+                }
+            }
+        }
+    }
+    match std::env::var("XDG_CONFIG_HOME") {
+        Ok(config) if !config.is_empty() => Some(PathBuf::from(config).join("git/ignore")),
+        _ => Some(home.join(".config/git/ignore")),
+    }
+}
+
+/**
+    All ignore files that apply to the contents of a directory, from lowest to highest
+    precedence. When several patterns match a path, the last one wins. All paths are
+    absolute.
+*/
+#[derive(Clone, Debug)]
+pub struct IgnoreRules {
+    files: Vec<Arc<IgnoreFile>>,
+}
+
+impl IgnoreRules {
+    /**
+        The rules for the contents of a directory where a walk starts. This includes the
+        global excludes and the ignore files of all parent directories up to the root of
+        the repository the directory is in, if any.
+    */
+    pub fn for_root(root: &Path) -> IgnoreRules {
+        let repository = root.ancestors().find(|dir| dir.join(".git").exists());
+        let mut rules = IgnoreRules { files: Vec::new() };
+        if let Some(file) = global_excludes_file()
+            .and_then(|f| IgnoreFile::read(repository.unwrap_or(root), &f)) {
+            rules.files.push(Arc::new(file));
+        }
+        if let Some(repository) = repository {
+            let mut parents = root.ancestors()
+                .skip(1)
+                .take_while(|dir| dir.starts_with(repository))
+                .collect::<Vec<_>>();
+            parents.reverse();
+            for dir in parents {
+                rules = rules.for_directory(dir);
+            }
+        }
+        rules
+    }
+
+    /**
+        The rules for the contents of a subdirectory of the directory these rules apply to.
+    */
+    pub fn for_directory(&self, dir: &Path) -> IgnoreRules {
+        let mut res = self.clone();
+        let candidates = [
+            dir.join(".git/info/exclude"),
+            dir.join(".gitignore"),
+            dir.join(".ignore"),
+        ];
+        for candidate in candidates.iter() {
+            if let Some(file) = IgnoreFile::read(dir, candidate) {
+                res.files.push(Arc::new(file));
+            }
+        }
+        res
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for file in &self.files {
+            if let Ok(relative) = path.strip_prefix(&file.base) {
+                let components = relative.iter()
+                    .map(|c| c.to_str().unwrap_or(""))
+                    .collect::<Vec<_>>();
+                for pattern in &file.patterns {
+                    if pattern.matches(&components, is_dir) {
+                        ignored = !pattern.negated;
+                    }
+                }
+            }
+        }
+        ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(content: &str) -> IgnoreRules {
+        IgnoreRules { files: vec![Arc::new(IgnoreFile::parse(Path::new("/repo"), content))] }
+    }
+
+    #[test]
+    fn unanchored_patterns_match_at_any_depth() {
+        let r = rules("*.o\ntarget\n");
+        assert!(r.is_ignored(Path::new("/repo/a.o"), false));
+        assert!(r.is_ignored(Path::new("/repo/src/deep/a.o"), false));
+        assert!(r.is_ignored(Path::new("/repo/sub/target"), true));
+        assert!(!r.is_ignored(Path::new("/repo/a.c"), false));
+        assert!(!r.is_ignored(Path::new("/elsewhere/a.o"), false));
+    }
+
+    #[test]
+    fn anchored_patterns_match_relative_to_the_ignore_file() {
+        let r = rules("/build\ndoc/*.html\n");
+        assert!(r.is_ignored(Path::new("/repo/build"), true));
+        assert!(!r.is_ignored(Path::new("/repo/sub/build"), true));
+        assert!(r.is_ignored(Path::new("/repo/doc/index.html"), false));
+        assert!(!r.is_ignored(Path::new("/repo/doc/sub/index.html"), false));
+    }
+
+    #[test]
+    fn wildcards_do_not_cross_directories() {
+        let r = rules("a/*/c\n");
+        assert!(r.is_ignored(Path::new("/repo/a/b/c"), false));
+        assert!(!r.is_ignored(Path::new("/repo/a/b/b/c"), false));
+    }
+
+    #[test]
+    fn double_stars_match_any_number_of_directories() {
+        let r = rules("**/logs\na/**/z\nout/**\n");
+        assert!(r.is_ignored(Path::new("/repo/logs"), true));
+        assert!(r.is_ignored(Path::new("/repo/x/y/logs"), true));
+        assert!(r.is_ignored(Path::new("/repo/a/z"), false));
+        assert!(r.is_ignored(Path::new("/repo/a/b/c/z"), false));
+        assert!(r.is_ignored(Path::new("/repo/out/file"), false));
+        assert!(!r.is_ignored(Path::new("/repo/out"), true));
+    }
+
+    #[test]
+    fn directory_patterns_only_match_directories() {
+        let r = rules("cache/\n");
+        assert!(r.is_ignored(Path::new("/repo/cache"), true));
+        assert!(!r.is_ignored(Path::new("/repo/cache"), false));
+    }
+
+    #[test]
+    fn the_last_matching_pattern_wins() {
+        let r = rules("*.log\n!important.log\n");
+        assert!(r.is_ignored(Path::new("/repo/debug.log"), false));
+        assert!(!r.is_ignored(Path::new("/repo/important.log"), false));
+    }
+
+    #[test]
+    fn character_classes_are_supported() {
+        let r = rules("[ab].txt\nlog[!0-9]\n[]x]\n");
+        assert!(r.is_ignored(Path::new("/repo/a.txt"), false));
+        assert!(r.is_ignored(Path::new("/repo/b.txt"), false));
+        assert!(!r.is_ignored(Path::new("/repo/c.txt"), false));
+        assert!(r.is_ignored(Path::new("/repo/logx"), false));
+        assert!(!r.is_ignored(Path::new("/repo/log1"), false));
+        assert!(r.is_ignored(Path::new("/repo/]"), false));
+        assert!(r.is_ignored(Path::new("/repo/x"), false));
+        assert!(!rules("[unclosed\n").is_ignored(Path::new("/repo/u"), false));
+        assert!(rules("[unclosed\n").is_ignored(Path::new("/repo/[unclosed"), false));
+    }
+
+    #[test]
+    fn other_characters_are_literal() {
+        let r = rules("100%\na\\*b\n?.c\n");
+        assert!(r.is_ignored(Path::new("/repo/100%"), false));
+        assert!(!r.is_ignored(Path::new("/repo/100abc"), false));
+        assert!(r.is_ignored(Path::new("/repo/a*b"), false));
+        assert!(!r.is_ignored(Path::new("/repo/axb"), false));
+        assert!(r.is_ignored(Path::new("/repo/x.c"), false));
+        assert!(!r.is_ignored(Path::new("/repo/xy.c"), false));
+    }
+
+    #[test]
+    fn stars_backtrack() {
+        let r = rules("a*b*c\n");
+        assert!(r.is_ignored(Path::new("/repo/abbbc"), false));
+        assert!(r.is_ignored(Path::new("/repo/axbxbxc"), false));
+        assert!(!r.is_ignored(Path::new("/repo/axbxbx"), false));
+    }
+
+    #[test]
+    fn comments_and_escapes_are_handled() {
+        let r = rules("# a comment\n\n\\#hash\n\\!bang\n");
+        assert!(r.is_ignored(Path::new("/repo/#hash"), false));
+        assert!(r.is_ignored(Path::new("/repo/!bang"), false));
+        assert!(!r.is_ignored(Path::new("/repo/# a comment"), false));
+    }
+}
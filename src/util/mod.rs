@@ -5,3 +5,5 @@ pub mod time;
 pub mod glob;
 pub mod replace;
 pub mod regex;
+pub mod ignore;
+pub mod compression;
@@ -1,7 +1,8 @@
 use std::collections::HashMap;
+use std::fs;
 use std::sync::Mutex;
 
-use users::uid_t;
+use users::{gid_t, uid_t};
 use users::User;
 
 use lazy_static::lazy_static;
@@ -32,3 +33,72 @@ impl UserMap for HashMap<uid_t, User> {
         Value::string(self.get(&uid).map(|u| u.name().to_str().unwrap_or("<illegal username>")).unwrap_or("<unknown user>"))
     }
 }
+
+pub struct GroupEntry {
+    pub name: String,
+    pub gid: gid_t,
+    pub members: Vec<String>,
+}
+
+fn parse_group_line(line: &str) -> Option<GroupEntry> {
+    let parts = line.split(':').collect::<Vec<&str>>();
+    if parts.len() != 4 || line.starts_with('#') {
+        return None;
+    }
+    Some(GroupEntry {
+        name: parts[0].to_string(),
+        gid: parts[2].parse::<gid_t>().ok()?,
+        members: parts[3]
+            .split(',')
+            .filter(|m| !m.is_empty())
+            .map(|m| m.to_string())
+            .collect(),
+    })
+}
+
+/**
+    Read all groups from /etc/group. Malformed lines are skipped, and a missing file results in
+    an empty list.
+*/
+pub fn all_groups() -> Vec<GroupEntry> {
+    fs::read_to_string("/etc/group")
+        .map(|content| content.lines().filter_map(parse_group_line).collect())
+        .unwrap_or_else(|_| Vec::new())
+}
+
+pub fn create_group_map() -> HashMap<gid_t, GroupEntry> {
+    all_groups().into_iter().map(|g| (g.gid, g)).collect()
+}
+
+pub trait GroupMap {
+    fn get_name(&self, gid: gid_t) -> Value;
+}
+
+impl GroupMap for HashMap<gid_t, GroupEntry> {
+    fn get_name(&self, gid: gid_t) -> Value {
+        Value::string(self.get(&gid).map(|g| g.name.as_str()).unwrap_or("<unknown group>"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_lines_are_parsed() {
+        let g = parse_group_line("wheel:x:10:alice,bob").unwrap();
+        assert_eq!(g.name, "wheel");
+        assert_eq!(g.gid, 10);
+        assert_eq!(g.members, vec!["alice".to_string(), "bob".to_string()]);
+
+        let empty = parse_group_line("nogroup:x:65534:").unwrap();
+        assert!(empty.members.is_empty());
+    }
+
+    #[test]
+    fn malformed_group_lines_are_skipped() {
+        assert!(parse_group_line("").is_none());
+        assert!(parse_group_line("wheel:x:ten:").is_none());
+        assert!(parse_group_line("#wheel:x:10:").is_none());
+    }
+}